@@ -0,0 +1,188 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use glam::Vec3;
+use crate::scene::scene::Camera;
+
+pub const BOOKMARK_SLOTS: usize = 9;
+const TRANSITION_SECONDS: f32 = 0.75;
+
+//saved viewpoints, slot 0 is bound to key 1
+//persisted as plain text so the file can be shared alongside the scene
+pub struct Bookmarks {
+    path: PathBuf,
+    slots: [Option<Camera>; BOOKMARK_SLOTS],
+}
+
+impl Bookmarks {
+    //a missing file is not an error, it just means no bookmarks have been stored yet
+    pub fn load_or_default(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let slots = match File::open(&path) {
+            Ok(file) => parse_bookmarks(&path, BufReader::new(file))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => [None; BOOKMARK_SLOTS],
+            Err(e) => return Err(e),
+        };
+        Ok(Self { path, slots })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get(&self, slot: usize) -> Option<&Camera> {
+        self.slots.get(slot).and_then(Option::as_ref)
+    }
+
+    //stores the camera in `slot` and writes the sidecar file straight away
+    pub fn store(&mut self, slot: usize, camera: &Camera) -> io::Result<()> {
+        self.slots[slot] = Some(*camera);
+        self.save()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(&self.path)?);
+        writeln!(writer, "# slot x y z yaw pitch fov_y")?;
+        for (slot, camera) in self.slots.iter().enumerate() {
+            if let Some(c) = camera {
+                writeln!(writer, "{} {} {} {} {} {} {}", slot + 1, c.position.x, c.position.y, c.position.z, c.yaw, c.pitch, c.fov_y)?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+fn parse_bookmarks(path: &Path, reader: impl BufRead) -> io::Result<[Option<Camera>; BOOKMARK_SLOTS]> {
+    let mut slots = [None; BOOKMARK_SLOTS];
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |reason: &str| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}:{}: {}", path.display(), line_number + 1, reason),
+        );
+
+        let mut fields = line.split_whitespace();
+        let slot = fields
+            .next()
+            .and_then(|s| s.parse::<usize>().ok())
+            .filter(|s| (1..=BOOKMARK_SLOTS).contains(s))
+            .ok_or_else(|| invalid("expected a slot number from 1 to 9"))?;
+        let values = fields
+            .map(str::parse::<f32>)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| invalid(&e.to_string()))?;
+        if values.len() != 6 {
+            return Err(invalid("expected 7 values"));
+        }
+        slots[slot - 1] = Some(Camera {
+            position: Vec3::new(values[0], values[1], values[2]),
+            yaw: values[3],
+            pitch: values[4],
+            fov_y: values[5],
+        });
+    }
+    Ok(slots)
+}
+
+//eases the camera from its current pose to a recalled bookmark
+pub struct CameraTransition {
+    from: Camera,
+    to: Camera,
+    elapsed: f32,
+}
+
+impl CameraTransition {
+    pub fn new(from: Camera, to: Camera) -> Self {
+        Self { from, to, elapsed: 0.0 }
+    }
+
+    //returns false once the camera has arrived
+    pub fn update(&mut self, camera: &mut Camera, delta_time: f32) -> bool {
+        self.elapsed += delta_time;
+        let t = (self.elapsed / TRANSITION_SECONDS).min(1.0);
+        let eased = t * t * (3.0 - 2.0 * t);
+        //the lerp lands a rounding error or a full turn of yaw away, so the last step copies the bookmark as stored
+        *camera = if t < 1.0 { self.from.lerp(&self.to, eased) } else { self.to };
+        t < 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+
+    fn test_dir() -> PathBuf {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/bookmarks");
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn camera(x: f32, yaw: f32) -> Camera {
+        Camera { position: Vec3::new(x, -2.5, 1.0 / 3.0), yaw, pitch: -0.2, fov_y: 0.9 }
+    }
+
+    fn parse(text: &str) -> io::Result<[Option<Camera>; BOOKMARK_SLOTS]> {
+        parse_bookmarks(Path::new("test.bookmarks"), text.as_bytes())
+    }
+
+    #[test]
+    fn stored_bookmarks_load_back() {
+        let path = test_dir().join("round_trip.bookmarks");
+        let _ = std::fs::remove_file(&path);
+        let mut bookmarks = Bookmarks::load_or_default(&path).unwrap();
+        assert!((0..BOOKMARK_SLOTS).all(|slot| bookmarks.get(slot).is_none()));
+        bookmarks.store(0, &camera(0.1 + 0.2, 1.2345679)).unwrap();
+        bookmarks.store(8, &camera(-1e6, -3.0)).unwrap();
+
+        let loaded = Bookmarks::load_or_default(&path).unwrap();
+        assert_eq!(loaded.slots, bookmarks.slots);
+        assert_eq!(loaded.get(0), Some(&camera(0.1 + 0.2, 1.2345679)));
+        assert_eq!(loaded.get(8), Some(&camera(-1e6, -3.0)));
+        assert_eq!(loaded.get(4), None);
+        assert_eq!(loaded.get(BOOKMARK_SLOTS), None);
+    }
+
+    #[test]
+    fn parses_slots_skipping_comments_and_blank_lines() {
+        let slots = parse("# slot x y z yaw pitch fov_y\n\n  3 1 2 3 0.5 -0.25 1.05\n3 4 5 6 0 0 1\n").unwrap();
+        let expected = Camera { position: Vec3::new(4.0, 5.0, 6.0), yaw: 0.0, pitch: 0.0, fov_y: 1.0 };
+        assert_eq!(slots[2], Some(expected));
+        assert_eq!(slots.iter().flatten().count(), 1);
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        let error = |text: &str| parse(text).unwrap_err().to_string();
+        assert_eq!(error("0 1 2 3 0 0 1"), "test.bookmarks:1: expected a slot number from 1 to 9");
+        assert_eq!(error("\n10 1 2 3 0 0 1"), "test.bookmarks:2: expected a slot number from 1 to 9");
+        assert_eq!(error("one 1 2 3 0 0 1"), "test.bookmarks:1: expected a slot number from 1 to 9");
+        assert_eq!(error("1 1 2 3 0 0"), "test.bookmarks:1: expected 7 values");
+        assert_eq!(error("1 1 2 3 0 0 1 1"), "test.bookmarks:1: expected 7 values");
+        assert!(error("1 1 2 3 0 0 wide").starts_with("test.bookmarks:1: invalid float"));
+    }
+
+    #[test]
+    fn transition_eases_and_arrives_exactly() {
+        //across the yaw wrap, so the lerp turns the short way and ends a full turn from the stored yaw
+        let (from, to) = (camera(0.0, PI - 0.1), camera(10.0, -PI + 0.2));
+        let mut transition = CameraTransition::new(from, to);
+        let mut current = from;
+
+        assert!(transition.update(&mut current, TRANSITION_SECONDS / 2.0));
+        assert!((current.position.x - 5.0).abs() < 1e-4, "{}", current.position.x);
+        assert!((current.yaw - (PI + 0.05)).abs() < 1e-4, "{}", current.yaw);
+
+        assert!(transition.update(&mut current, TRANSITION_SECONDS / 4.0));
+        assert!(current.position.x > 7.5 && current.position.x < 10.0, "{}", current.position.x);
+
+        assert!(!transition.update(&mut current, TRANSITION_SECONDS));
+        assert_eq!(current, to);
+    }
+}
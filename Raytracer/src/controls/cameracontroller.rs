@@ -1,14 +1,61 @@
 
 use winit::dpi::PhysicalPosition;
-use winit::keyboard::{KeyCode, PhysicalKey};
-use winit::event::{ ElementState, MouseButton, MouseScrollDelta,};
-use glam::{ Vec3};
-use crate::gpu::raytracer::compute_pipeline::CameraUniform;
+use winit::event::MouseScrollDelta;
+use glam::Vec3;
+use serde::Deserialize;
+use crate::controls::inputmap::Action;
 use crate::scene::scene::Camera;
 
+const DEFAULT_ORBIT_DISTANCE: f32 = 100.0;
+const MIN_ORBIT_DISTANCE: f32 = 1.0;
+const PAN_SPEED: f32 = 0.002; //fraction of the orbit distance moved per pixel dragged
+const DOLLY_SPEED: f32 = 0.001; //fraction of the orbit distance moved per scroll unit
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CameraMode {
+    //WASD/QE to move, drag to look
+    Fly,
+    //drag to turn around `pivot`, middle drag to pan, scroll to dolly
+    Orbit,
+}
+
+//tuning read from the `[camera]` table of the input config
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ControllerSettings {
+    //top movement speed in world units per second
+    pub speed: f32,
+    //radians turned per pixel of mouse movement
+    pub sensitivity: f32,
+    //time constant in seconds for easing out mouse look, 0 turns smoothing off
+    pub look_smoothing: f32,
+    //how quickly movement reaches full speed and coasts to a stop, in 1/seconds, 0 changes speed instantly
+    pub acceleration: f32,
+    pub damping: f32,
+}
+
+impl Default for ControllerSettings {
+    fn default() -> Self {
+        Self {
+            speed: 100.0,
+            sensitivity: 0.004,
+            look_smoothing: 0.0,
+            acceleration: 10.0,
+            damping: 8.0,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct CameraController {
+    mode: CameraMode,
+    pivot: Vec3,
+    orbit_distance: f32,
     is_dragging: bool,
+    is_captured: bool,
+    is_panning: bool,
+    pan_horizontal: f32,
+    pan_vertical: f32,
     amount_left: f32,
     amount_right: f32,
     amount_forward: f32,
@@ -18,12 +65,19 @@ pub struct CameraController {
     rotate_horizontal: f32,
     rotate_vertical: f32,
     scroll: f32,
-    speed: f32,
-    sensitivity: f32,
+    velocity: Vec3,
+    settings: ControllerSettings,
 }impl CameraController{
-    pub fn new(speed: f32, sensitivity: f32) -> Self {
+    pub fn new(settings: ControllerSettings) -> Self {
         Self {
+            mode: CameraMode::Fly,
+            pivot: Vec3::ZERO,
+            orbit_distance: DEFAULT_ORBIT_DISTANCE,
             is_dragging: false,
+            is_captured: false,
+            is_panning: false,
+            pan_horizontal: 0.0,
+            pan_vertical: 0.0,
             amount_left: 0.0,
             amount_right: 0.0,
             amount_forward: 0.0,
@@ -33,56 +87,99 @@ pub struct CameraController {
             rotate_horizontal: 0.0,
             rotate_vertical: 0.0,
             scroll: 0.0,
-            speed,
-            sensitivity,
+            velocity: Vec3::ZERO,
+            settings,
         }
     }
 
-    pub fn process_keyboard(&mut self, key: PhysicalKey, state: ElementState) -> bool{
-        let amount = if state == ElementState::Pressed { 1.0 } else { 0.0 };
-        match key {
-            winit::keyboard::PhysicalKey::Code(KeyCode::KeyW)=> {
-                self.amount_forward = amount;
-                true
-            }
-            winit::keyboard::PhysicalKey::Code(KeyCode::KeyS) => {
-                self.amount_backward = amount;
-                true
-            }
-            winit::keyboard::PhysicalKey::Code(KeyCode::KeyA) => {
-                self.amount_left = amount;
-                true
-            }
-            winit::keyboard::PhysicalKey::Code(KeyCode::KeyD)=> {
-                self.amount_right = amount;
-                true
-            }
-            winit::keyboard::PhysicalKey::Code(KeyCode::KeyQ) => {
-                self.amount_up = amount;
-                true
-            }
-            winit::keyboard::PhysicalKey::Code(KeyCode::KeyE) => {
-                self.amount_down = amount;
-                true
+    pub fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    //switching into orbit keeps the view unchanged by placing the pivot straight ahead of the camera
+    pub fn toggle_mode(&mut self, camera: &Camera) {
+        self.mode = match self.mode {
+            CameraMode::Fly => {
+                self.pivot = camera.position + camera.forward() * self.orbit_distance;
+                CameraMode::Orbit
             }
-            _ => false,
+            CameraMode::Orbit => CameraMode::Fly,
+        };
+    }
+
+    //follows a camera that something other than the controller moved, such as a bookmark, playback or a reloaded scene
+    //the pivot goes back to straight ahead at the current orbit distance, and motion that built up meanwhile is dropped
+    pub fn sync(&mut self, camera: &Camera) {
+        self.pivot = camera.position + camera.forward() * self.orbit_distance;
+        self.rotate_horizontal = 0.0;
+        self.rotate_vertical = 0.0;
+        self.pan_horizontal = 0.0;
+        self.pan_vertical = 0.0;
+        self.scroll = 0.0;
+        self.velocity = Vec3::ZERO;
+    }
+
+    //orbits around `center`, backing off far enough to frame an object of the given radius
+    pub fn focus(&mut self, center: Vec3, radius: f32) {
+        self.mode = CameraMode::Orbit;
+        self.pivot = center;
+        self.orbit_distance = (radius * 3.0).max(MIN_ORBIT_DISTANCE);
+    }
+
+    //while captured the mouse looks around without a button held, like an fps game
+    pub fn set_captured(&mut self, captured: bool) {
+        self.is_captured = captured;
+    }
+
+    pub fn is_captured(&self) -> bool {
+        self.is_captured
+    }
+
+    //handles the movement and look actions, returns false for actions the controller doesn't own
+    pub fn process_action(&mut self, action: Action, pressed: bool) -> bool {
+        let amount = if pressed { 1.0 } else { 0.0 };
+        match action {
+            Action::MoveForward => self.amount_forward = amount,
+            Action::MoveBackward => self.amount_backward = amount,
+            Action::MoveLeft => self.amount_left = amount,
+            Action::MoveRight => self.amount_right = amount,
+            Action::MoveUp => self.amount_up = amount,
+            Action::MoveDown => self.amount_down = amount,
+            Action::Look => self.is_dragging = pressed,
+            Action::Pan => self.is_panning = pressed,
+            _ => return false,
         }
+        true
+    }
+
+    //stops all movement, used when the window loses focus and key releases can be missed
+    pub fn release_all(&mut self) {
+        self.amount_forward = 0.0;
+        self.amount_backward = 0.0;
+        self.amount_left = 0.0;
+        self.amount_right = 0.0;
+        self.amount_up = 0.0;
+        self.amount_down = 0.0;
+        self.is_dragging = false;
+        self.is_captured = false;
+        self.is_panning = false;
     }
 
     pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
-        if self.is_dragging {
-            self.rotate_horizontal = mouse_dx as f32;
-            self.rotate_vertical = mouse_dy as f32;
+        //accumulated until the next update so no motion is lost between frames
+        if self.is_dragging || self.is_captured {
+            self.rotate_horizontal += mouse_dx as f32;
+            self.rotate_vertical += mouse_dy as f32;
         }
-    }
-    pub fn process_mouse_button(&mut self, button: MouseButton, state: ElementState) {
-        if button == MouseButton::Left {
-            self.is_dragging = state == ElementState::Pressed;
+        if self.is_panning {
+            self.pan_horizontal += mouse_dx as f32;
+            self.pan_vertical += mouse_dy as f32;
         }
     }
 
+    //accumulated like mouse motion, a fast scroll sends several events per frame
     pub fn process_scroll(&mut self, delta: &MouseScrollDelta) {
-        self.scroll = -match delta {
+        self.scroll -= match delta {
             MouseScrollDelta::LineDelta(_, scroll) => scroll * 100.0,
             MouseScrollDelta::PixelDelta(PhysicalPosition {
                 y: scroll,
@@ -91,36 +188,61 @@ pub struct CameraController {
         };
     }
     
-    pub fn update_camera(&mut self, camera: &mut Camera, delta_time: f32, uniform: &mut CameraUniform) {
+    pub fn update_camera(&mut self, camera: &mut Camera, delta_time: f32) {
+        match self.mode {
+            CameraMode::Fly => self.update_fly(camera, delta_time),
+            CameraMode::Orbit => self.update_orbit(camera, delta_time),
+        }
+        self.scroll = 0.0;
+        self.pan_horizontal = 0.0;
+        self.pan_vertical = 0.0;
+    }
+
+    fn update_fly(&mut self, camera: &mut Camera, delta_time: f32) {
         self.update_camera_rotation(camera, delta_time);
-        let pitch = camera.pitch;
-        let yaw = camera.yaw;
-        let forward_direction = Vec3::new(
-            pitch.cos() * yaw.cos(),
-            pitch.sin(),
-            pitch.cos() * yaw.sin()
-        ).normalize();
-        
-        let right_direction = Vec3::new(
-            -yaw.sin(),
-            0.0,
-            yaw.cos()
-        ).normalize();
-
-        
-        camera.position += forward_direction * (self.amount_forward - self.amount_backward) * self.speed * delta_time;
-        camera.position += right_direction * (self.amount_right - self.amount_left) * self.speed * delta_time;
-
-        camera.position.y += (self.amount_up - self.amount_down) * self.speed * delta_time;
-
-        uniform.position = camera.position.into();
-        uniform.forward = forward_direction.into();
+        let forward_direction = camera.forward();
+        let right_direction = camera.right();
+
+        let input = forward_direction * (self.amount_forward - self.amount_backward)
+            + right_direction * (self.amount_right - self.amount_left)
+            + Vec3::Y * (self.amount_up - self.amount_down);
+        let target_velocity = input.normalize_or_zero() * self.settings.speed;
+
+        //ease towards the target velocity, speeding up with `acceleration` and coasting down with `damping`
+        let rate = if input == Vec3::ZERO { self.settings.damping } else { self.settings.acceleration };
+        self.velocity = self.velocity.lerp(target_velocity, ease_factor(rate, delta_time));
+
+        camera.position += self.velocity * delta_time;
     }
 
+    fn update_orbit(&mut self, camera: &mut Camera, delta_time: f32) {
+        self.update_camera_rotation(camera, delta_time);
+        let forward_direction = camera.forward();
+        let right_direction = camera.right();
+        let up_direction = right_direction.cross(forward_direction);
+
+        let pan_scale = self.orbit_distance * PAN_SPEED;
+        self.pivot -= right_direction * self.pan_horizontal * pan_scale;
+        self.pivot += up_direction * self.pan_vertical * pan_scale;
+
+        self.orbit_distance = (self.orbit_distance * (1.0 + self.scroll * DOLLY_SPEED)).max(MIN_ORBIT_DISTANCE);
+
+        camera.position = self.pivot - forward_direction * self.orbit_distance;
+    }
+
+    //mouse deltas are already a distance so they are applied directly, not scaled by the frame time
+    //smoothing spreads the pending motion over the next few frames at a rate that doesn't depend on fps
     pub fn update_camera_rotation(&mut self, camera: &mut Camera, delta_time: f32) {
+        let applied = if self.settings.look_smoothing > 0.0 {
+            1.0 - (-delta_time / self.settings.look_smoothing).exp()
+        } else {
+            1.0
+        };
+        let horizontal = self.rotate_horizontal * applied;
+        let vertical = self.rotate_vertical * applied;
 
-        camera.yaw += self.rotate_horizontal * self.sensitivity * delta_time;
-        camera.pitch += -self.rotate_vertical * self.sensitivity * delta_time;
+        camera.yaw += horizontal * self.settings.sensitivity;
+        camera.pitch += -vertical * self.settings.sensitivity;
         if camera.pitch > 1.50{
             camera.pitch = 1.49;
         }
@@ -128,8 +250,209 @@ pub struct CameraController {
             camera.pitch = -1.49;
         }
 
-        self.rotate_horizontal = 0.0;
-        self.rotate_vertical = 0.0;
+        self.rotate_horizontal -= horizontal;
+        self.rotate_vertical -= vertical;
     }
 
-}
\ No newline at end of file
+}
+
+//fraction of the remaining distance to cover this frame when closing a gap exponentially at `rate` per second
+fn ease_factor(rate: f32, delta_time: f32) -> f32 {
+    if rate <= 0.0 {
+        return 1.0;
+    }
+    1.0 - (-rate * delta_time).exp()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: f32 = 1.0 / 60.0;
+
+    fn camera() -> Camera {
+        Camera::new(Vec3::new(0.0, 0.0, -100.0), Vec3::Z)
+    }
+
+    fn orbiting(camera: &Camera) -> CameraController {
+        let mut controller = CameraController::new(ControllerSettings::default());
+        controller.toggle_mode(camera);
+        controller
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!(a.distance(b) < 1e-3, "{a} != {b}");
+    }
+
+    #[test]
+    fn orbiting_keeps_the_distance_to_the_pivot() {
+        let mut camera = camera();
+        let mut controller = orbiting(&camera);
+        assert_eq!(controller.mode(), CameraMode::Orbit);
+        controller.process_action(Action::Look, true);
+        for (dx, dy) in [(120.0, 0.0), (-40.0, 55.0), (300.0, -200.0)] {
+            controller.process_mouse(dx, dy);
+            controller.update_camera(&mut camera, FRAME);
+            assert!((camera.position.distance(controller.pivot) - controller.orbit_distance).abs() < 1e-3);
+            //still looking at the pivot
+            assert_close(camera.position + camera.forward() * controller.orbit_distance, controller.pivot);
+        }
+        assert_close(controller.pivot, Vec3::ZERO);
+    }
+
+    #[test]
+    fn panning_moves_the_pivot_with_the_camera() {
+        let mut camera = camera();
+        let mut controller = orbiting(&camera);
+        controller.process_action(Action::Pan, true);
+        controller.process_mouse(50.0, 0.0);
+        controller.update_camera(&mut camera, FRAME);
+        assert!(controller.pivot.x.abs() > 1.0 && controller.pivot.y.abs() < 1e-3, "{}", controller.pivot);
+        assert_close(camera.position, controller.pivot - Vec3::Z * 100.0);
+    }
+
+    #[test]
+    fn focus_frames_the_sphere_at_three_radii() {
+        let mut camera = camera();
+        let mut controller = CameraController::new(ControllerSettings::default());
+        let center = Vec3::new(40.0, 10.0, 20.0);
+        controller.focus(center, 12.0);
+        assert_eq!(controller.mode(), CameraMode::Orbit);
+        controller.update_camera(&mut camera, FRAME);
+        assert!((camera.position.distance(center) - 36.0).abs() < 1e-3, "{}", camera.position);
+        assert_close(camera.position + camera.forward() * 36.0, center);
+
+        //tiny objects still leave room for the near side
+        controller.focus(center, 0.01);
+        assert_eq!(controller.orbit_distance, MIN_ORBIT_DISTANCE);
+    }
+
+    #[test]
+    fn dolly_stops_at_the_minimum_distance() {
+        let mut camera = camera();
+        let mut controller = orbiting(&camera);
+        controller.process_scroll(&MouseScrollDelta::LineDelta(0.0, 1.0));
+        controller.update_camera(&mut camera, FRAME);
+        assert!((controller.orbit_distance - 90.0).abs() < 1e-3, "{}", controller.orbit_distance);
+
+        controller.process_scroll(&MouseScrollDelta::LineDelta(0.0, 50.0));
+        controller.update_camera(&mut camera, FRAME);
+        assert_eq!(controller.orbit_distance, MIN_ORBIT_DISTANCE);
+        assert!((camera.position.distance(Vec3::ZERO) - MIN_ORBIT_DISTANCE).abs() < 1e-3);
+    }
+
+    #[test]
+    fn wheel_events_in_one_frame_add_up() {
+        let distance_after = |deltas: &[MouseScrollDelta]| {
+            let mut camera = camera();
+            let mut controller = orbiting(&camera);
+            for delta in deltas {
+                controller.process_scroll(delta);
+            }
+            controller.update_camera(&mut camera, FRAME);
+            controller.orbit_distance
+        };
+        let two = distance_after(&[MouseScrollDelta::LineDelta(0.0, -1.0), MouseScrollDelta::LineDelta(0.0, -1.0)]);
+        assert_eq!(two, distance_after(&[MouseScrollDelta::LineDelta(0.0, -2.0)]));
+        assert_ne!(two, distance_after(&[MouseScrollDelta::LineDelta(0.0, -1.0)]));
+        let pixels = [PhysicalPosition::new(0.0, 30.0), PhysicalPosition::new(0.0, 70.0)].map(MouseScrollDelta::PixelDelta);
+        assert_eq!(distance_after(&pixels), distance_after(&[MouseScrollDelta::LineDelta(0.0, 1.0)]));
+    }
+
+    fn smoothed() -> CameraController {
+        let mut controller = CameraController::new(ControllerSettings { look_smoothing: 0.05, ..ControllerSettings::default() });
+        controller.process_action(Action::Look, true);
+        controller
+    }
+
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{a} != {b}");
+    }
+
+    #[test]
+    fn smoothed_look_does_not_depend_on_the_frame_rate() {
+        let (mut slow_camera, mut fast_camera) = (camera(), camera());
+        let (mut slow, mut fast) = (smoothed(), smoothed());
+        slow.process_mouse(200.0, -80.0);
+        fast.process_mouse(200.0, -80.0);
+
+        slow.update_camera(&mut slow_camera, 1.0 / 30.0);
+        fast.update_camera(&mut fast_camera, 1.0 / 60.0);
+        fast.update_camera(&mut fast_camera, 1.0 / 60.0);
+
+        assert!(slow.rotate_horizontal > 1.0, "smoothing should leave part of the delta for later");
+        assert_near(slow.rotate_horizontal, fast.rotate_horizontal);
+        assert_near(slow.rotate_vertical, fast.rotate_vertical);
+        assert_near(slow_camera.yaw, fast_camera.yaw);
+        assert_near(slow_camera.pitch, fast_camera.pitch);
+    }
+
+    #[test]
+    fn look_without_smoothing_applies_the_whole_delta() {
+        let mut camera = camera();
+        let start = camera.yaw;
+        let mut controller = CameraController::new(ControllerSettings::default());
+        controller.process_action(Action::Look, true);
+        controller.process_mouse(250.0, 0.0);
+        controller.update_camera(&mut camera, FRAME);
+        assert_eq!(controller.rotate_horizontal, 0.0);
+        assert_near(camera.yaw - start, 250.0 * controller.settings.sensitivity);
+    }
+
+    #[test]
+    fn velocity_does_not_depend_on_the_frame_rate() {
+        let (mut slow_camera, mut fast_camera) = (camera(), camera());
+        let mut slow = CameraController::new(ControllerSettings::default());
+        let mut fast = CameraController::new(ControllerSettings::default());
+        slow.process_action(Action::MoveForward, true);
+        fast.process_action(Action::MoveForward, true);
+
+        slow.update_camera(&mut slow_camera, 1.0 / 30.0);
+        fast.update_camera(&mut fast_camera, 1.0 / 60.0);
+        fast.update_camera(&mut fast_camera, 1.0 / 60.0);
+
+        assert!(slow.velocity.length() > 1.0 && slow.velocity.length() < slow.settings.speed);
+        assert!(slow.velocity.distance(fast.velocity) < 1e-3, "{} != {}", slow.velocity, fast.velocity);
+    }
+
+    #[test]
+    fn damping_stops_the_camera_without_input() {
+        let mut camera = camera();
+        let mut controller = CameraController::new(ControllerSettings::default());
+        controller.process_action(Action::MoveRight, true);
+        for _ in 0..60 {
+            controller.update_camera(&mut camera, FRAME);
+        }
+        assert!(controller.velocity.length() > 0.9 * controller.settings.speed);
+
+        controller.process_action(Action::MoveRight, false);
+        let mut speed = controller.velocity.length();
+        for _ in 0..300 {
+            controller.update_camera(&mut camera, FRAME);
+            assert!(controller.velocity.length() < speed);
+            speed = controller.velocity.length();
+        }
+        assert!(speed < 1e-3, "{speed}");
+        let stopped = camera.position;
+        controller.update_camera(&mut camera, FRAME);
+        assert!(camera.position.distance(stopped) < 1e-4);
+    }
+
+    #[test]
+    fn sync_follows_a_camera_moved_elsewhere() {
+        let mut camera = camera();
+        let mut controller = orbiting(&camera);
+        controller.process_action(Action::Look, true);
+        controller.process_mouse(500.0, 0.0);
+
+        //a bookmark or playback put the camera somewhere else
+        camera = Camera::new(Vec3::new(300.0, 20.0, 0.0), Vec3::X);
+        let moved = camera;
+        controller.sync(&camera);
+        controller.update_camera(&mut camera, FRAME);
+        assert_close(camera.position, moved.position);
+        assert_eq!((camera.yaw, camera.pitch), (moved.yaw, moved.pitch));
+        assert!((controller.orbit_distance - 100.0).abs() < 1e-6);
+    }
+}
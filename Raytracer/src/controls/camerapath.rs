@@ -0,0 +1,278 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use glam::Vec3;
+use crate::scene::scene::Camera;
+
+//one sampled camera pose, `time` is seconds since the recording started
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CameraKeyframe {
+    pub time: f32,
+    pub position: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl CameraKeyframe {
+    fn from_camera(camera: &Camera, time: f32) -> Self {
+        Self {
+            time,
+            position: camera.position,
+            yaw: camera.yaw,
+            pitch: camera.pitch,
+        }
+    }
+
+    fn lerp(&self, other: &CameraKeyframe, t: f32) -> Self {
+        Self {
+            time: self.time + (other.time - self.time) * t,
+            position: self.position.lerp(other.position, t),
+            yaw: self.yaw + (other.yaw - self.yaw) * t,
+            pitch: self.pitch + (other.pitch - self.pitch) * t,
+        }
+    }
+
+    fn apply(&self, camera: &mut Camera) {
+        camera.position = self.position;
+        camera.yaw = self.yaw;
+        camera.pitch = self.pitch;
+    }
+}
+
+//samples the camera once per frame and writes the path out when stopped
+pub struct CameraRecorder {
+    path: PathBuf,
+    elapsed: f32,
+    keyframes: Vec<CameraKeyframe>,
+}
+
+impl CameraRecorder {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            elapsed: 0.0,
+            keyframes: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, camera: &Camera, delta_time: f32) {
+        if !self.keyframes.is_empty() {
+            self.elapsed += delta_time;
+        }
+        self.keyframes.push(CameraKeyframe::from_camera(camera, self.elapsed));
+    }
+
+    pub fn keyframe_count(&self) -> usize {
+        self.keyframes.len()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        save_camera_path(&self.path, &self.keyframes)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PlaybackRate {
+    //follows the wall clock, frames are skipped or repeated to keep the recorded timing
+    RealTime,
+    //advances a fixed step per frame regardless of how long the frame took, for offline renders
+    FixedFrameRate(f32),
+}
+
+//drives the scene camera from a recorded path
+pub struct CameraPlayback {
+    keyframes: Vec<CameraKeyframe>,
+    rate: PlaybackRate,
+    elapsed: f32,
+    frame: u32,
+}
+
+impl CameraPlayback {
+    pub fn load(path: &Path, rate: PlaybackRate) -> io::Result<Self> {
+        let keyframes = load_camera_path(path)?;
+        if keyframes.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "camera path contains no keyframes"));
+        }
+        Ok(Self {
+            keyframes,
+            rate,
+            elapsed: 0.0,
+            frame: 0,
+        })
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |k| k.time)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed > self.duration()
+    }
+
+    //moves the camera to the pose at the current playback time, then advances the clock
+    //returns false once the end of the path has been passed
+    pub fn update(&mut self, camera: &mut Camera, delta_time: f32) -> bool {
+        if self.is_finished() {
+            return false;
+        }
+        self.sample(self.elapsed).apply(camera);
+
+        self.elapsed += match self.rate {
+            PlaybackRate::RealTime => delta_time,
+            PlaybackRate::FixedFrameRate(fps) => 1.0 / fps,
+        };
+        self.frame += 1;
+        true
+    }
+
+    fn sample(&self, time: f32) -> CameraKeyframe {
+        let next = self.keyframes.partition_point(|k| k.time <= time);
+        if next == 0 {
+            return self.keyframes[0];
+        }
+        if next == self.keyframes.len() {
+            return self.keyframes[next - 1];
+        }
+        let a = &self.keyframes[next - 1];
+        let b = &self.keyframes[next];
+        let span = b.time - a.time;
+        if span <= f32::EPSILON {
+            return *b;
+        }
+        a.lerp(b, (time - a.time) / span)
+    }
+}
+
+//plain text, one keyframe per line: time x y z yaw pitch
+pub fn save_camera_path(path: &Path, keyframes: &[CameraKeyframe]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "# time x y z yaw pitch")?;
+    for k in keyframes {
+        writeln!(writer, "{} {} {} {} {} {}", k.time, k.position.x, k.position.y, k.position.z, k.yaw, k.pitch)?;
+    }
+    writer.flush()
+}
+
+pub fn load_camera_path(path: &Path) -> io::Result<Vec<CameraKeyframe>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut keyframes = Vec::new();
+
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let values = line
+            .split_whitespace()
+            .map(str::parse::<f32>)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| invalid_line(path, line_number, &e.to_string()))?;
+        if values.len() != 6 {
+            return Err(invalid_line(path, line_number, "expected 6 values"));
+        }
+        keyframes.push(CameraKeyframe {
+            time: values[0],
+            position: Vec3::new(values[1], values[2], values[3]),
+            yaw: values[4],
+            pitch: values[5],
+        });
+    }
+    Ok(keyframes)
+}
+
+fn invalid_line(path: &Path, line_number: usize, reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}:{}: {}", path.display(), line_number + 1, reason),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: f32, x: f32, yaw: f32) -> CameraKeyframe {
+        CameraKeyframe { time, position: Vec3::new(x, 2.0 * x, -x), yaw, pitch: yaw * 0.5 }
+    }
+
+    fn test_dir() -> PathBuf {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/camerapath");
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn playback(keyframes: Vec<CameraKeyframe>, rate: PlaybackRate) -> CameraPlayback {
+        CameraPlayback { keyframes, rate, elapsed: 0.0, frame: 0 }
+    }
+
+    #[test]
+    fn keyframes_round_trip_through_a_file() {
+        let path = test_dir().join("round_trip.txt");
+        let keyframes = vec![
+            keyframe(0.0, 0.0, 0.0),
+            CameraKeyframe { time: 1.0 / 60.0, position: Vec3::new(0.1 + 0.2, -1.0 / 3.0, 1e-7), yaw: -3.1, pitch: 1.2345679 },
+            keyframe(2.5, 1e6, 6.2),
+        ];
+        save_camera_path(&path, &keyframes).unwrap();
+        assert_eq!(load_camera_path(&path).unwrap(), keyframes);
+    }
+
+    #[test]
+    fn malformed_lines_name_the_line() {
+        let path = test_dir().join("malformed.txt");
+        std::fs::write(&path, "# time x y z yaw pitch\n\n0 0 0 0 0 0\n1 2 3\n").unwrap();
+        let error = load_camera_path(&path).unwrap_err().to_string();
+        assert!(error.ends_with("malformed.txt:4: expected 6 values"), "{error}");
+    }
+
+    #[test]
+    fn samples_between_keyframes_are_interpolated() {
+        let playback = playback(vec![keyframe(0.0, 0.0, 0.0), keyframe(1.0, 10.0, 1.0), keyframe(3.0, 30.0, 0.0)], PlaybackRate::RealTime);
+        assert_eq!(playback.sample(0.0), keyframe(0.0, 0.0, 0.0));
+        assert_eq!(playback.sample(0.5), keyframe(0.5, 5.0, 0.5));
+        assert_eq!(playback.sample(1.0), keyframe(1.0, 10.0, 1.0));
+        assert_eq!(playback.sample(2.0), keyframe(2.0, 20.0, 0.5));
+        //held at either end
+        assert_eq!(playback.sample(-1.0), keyframe(0.0, 0.0, 0.0));
+        assert_eq!(playback.sample(5.0), keyframe(3.0, 30.0, 0.0));
+    }
+
+    #[test]
+    fn fixed_frame_rate_steps_until_past_the_end() {
+        let mut playback = playback(vec![keyframe(0.0, 0.0, 0.0), keyframe(1.0, 8.0, 0.0)], PlaybackRate::FixedFrameRate(4.0));
+        let mut camera = Camera::new(Vec3::ZERO, Vec3::Z);
+        let mut positions = Vec::new();
+        //the frame time passed in is ignored, a slow frame still advances a quarter second
+        while playback.update(&mut camera, 10.0) {
+            positions.push(camera.position.x);
+            assert_eq!(playback.frame() as usize, positions.len());
+        }
+        assert_eq!(positions, [0.0, 2.0, 4.0, 6.0, 8.0]);
+        assert!(playback.is_finished());
+        assert!(!playback.update(&mut camera, 0.0));
+        assert_eq!(playback.frame(), 5);
+    }
+
+    #[test]
+    fn recorder_times_keyframes_from_the_first() {
+        let mut recorder = CameraRecorder::new("unused.txt");
+        let mut camera = Camera::new(Vec3::ZERO, Vec3::Z);
+        recorder.record(&camera, 5.0);
+        camera.position.x = 1.0;
+        recorder.record(&camera, 0.25);
+        assert_eq!(recorder.keyframe_count(), 2);
+        assert_eq!(recorder.keyframes[0].time, 0.0);
+        assert_eq!(recorder.keyframes[1].time, 0.25);
+        assert_eq!(recorder.keyframes[1].position.x, 1.0);
+    }
+}
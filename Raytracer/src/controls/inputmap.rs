@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Deserialize;
+use winit::event::MouseButton;
+use winit::keyboard::{KeyCode, ModifiersState};
+
+use crate::controls::bookmarks::BOOKMARK_SLOTS;
+use crate::controls::cameracontroller::ControllerSettings;
+
+//everything the user can trigger from the keyboard or mouse
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    Look,
+    Pan,
+    Screenshot,
+    SaveExr,
+    CycleAovView,
+    ToggleCameraMode,
+    ToggleMouseCapture,
+    ToggleOverlay,
+    ToggleDenoiser,
+    ToggleProfiler,
+    Pick,
+    Focus,
+    ToggleRecording,
+    TogglePlayback,
+    Undo,
+    Redo,
+    SaveScene,
+    ReloadScene,
+    StoreBookmark(usize),
+    RecallBookmark(usize),
+}
+
+impl Action {
+    const SIMPLE: [Action; 24] = [
+        Action::MoveForward,
+        Action::MoveBackward,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::Look,
+        Action::Pan,
+        Action::Screenshot,
+        Action::SaveExr,
+        Action::CycleAovView,
+        Action::ToggleCameraMode,
+        Action::ToggleMouseCapture,
+        Action::ToggleOverlay,
+        Action::ToggleDenoiser,
+        Action::ToggleProfiler,
+        Action::Pick,
+        Action::Focus,
+        Action::ToggleRecording,
+        Action::TogglePlayback,
+        Action::Undo,
+        Action::Redo,
+        Action::SaveScene,
+        Action::ReloadScene,
+    ];
+
+    //the snake_case name used in the config file, bookmarks are `store_bookmark_1` to `recall_bookmark_9`
+    pub fn name(&self) -> String {
+        match self {
+            Action::MoveForward => "move_forward".into(),
+            Action::MoveBackward => "move_backward".into(),
+            Action::MoveLeft => "move_left".into(),
+            Action::MoveRight => "move_right".into(),
+            Action::MoveUp => "move_up".into(),
+            Action::MoveDown => "move_down".into(),
+            Action::Look => "look".into(),
+            Action::Pan => "pan".into(),
+            Action::Screenshot => "screenshot".into(),
+            Action::SaveExr => "save_exr".into(),
+            Action::CycleAovView => "cycle_aov_view".into(),
+            Action::ToggleCameraMode => "toggle_camera_mode".into(),
+            Action::ToggleMouseCapture => "toggle_mouse_capture".into(),
+            Action::ToggleOverlay => "toggle_overlay".into(),
+            Action::ToggleDenoiser => "toggle_denoiser".into(),
+            Action::ToggleProfiler => "toggle_profiler".into(),
+            Action::Pick => "pick".into(),
+            Action::Focus => "focus".into(),
+            Action::ToggleRecording => "toggle_recording".into(),
+            Action::TogglePlayback => "toggle_playback".into(),
+            Action::Undo => "undo".into(),
+            Action::Redo => "redo".into(),
+            Action::SaveScene => "save_scene".into(),
+            Action::ReloadScene => "reload_scene".into(),
+            Action::StoreBookmark(slot) => format!("store_bookmark_{}", slot + 1),
+            Action::RecallBookmark(slot) => format!("recall_bookmark_{}", slot + 1),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        if let Some(action) = Action::SIMPLE.iter().find(|a| a.name() == name) {
+            return Some(*action);
+        }
+        let bookmark_slot = |prefix: &str| {
+            name.strip_prefix(prefix)
+                .and_then(|n| n.parse::<usize>().ok())
+                .filter(|n| (1..=BOOKMARK_SLOTS).contains(n))
+                .map(|n| n - 1)
+        };
+        bookmark_slot("store_bookmark_")
+            .map(Action::StoreBookmark)
+            .or_else(|| bookmark_slot("recall_bookmark_").map(Action::RecallBookmark))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Trigger {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+//a key or mouse button plus the modifiers that must be held with it
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Binding {
+    pub trigger: Trigger,
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+}
+
+impl Binding {
+    pub fn key(code: KeyCode) -> Self {
+        Self { trigger: Trigger::Key(code), ctrl: false, shift: false, alt: false }
+    }
+
+    pub fn mouse(button: MouseButton) -> Self {
+        Self { trigger: Trigger::Mouse(button), ctrl: false, shift: false, alt: false }
+    }
+
+    pub fn with_ctrl(mut self) -> Self {
+        self.ctrl = true;
+        self
+    }
+
+    pub fn with_shift(mut self) -> Self {
+        self.shift = true;
+        self
+    }
+
+    //bindings without modifiers still work while some are held, e.g. moving with shift down
+    //ones with modifiers need exactly those, so ctrl+z doesn't also fire for ctrl+shift+z
+    fn matches_modifiers(&self, modifiers: ModifiersState) -> bool {
+        let exact = self.ctrl == modifiers.control_key() && self.shift == modifiers.shift_key() && self.alt == modifiers.alt_key();
+        exact || self.modifier_count() == 0
+    }
+
+    fn modifier_count(&self) -> u32 {
+        self.ctrl as u32 + self.shift as u32 + self.alt as u32
+    }
+
+    //parses `KeyW`, `Ctrl+Digit1`, `Mouse:Left` or `Shift+Mouse:4`
+    //key names are winit `KeyCode` variants, so they refer to physical positions on the keyboard
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parts: Vec<&str> = text.split('+').map(str::trim).collect();
+        let trigger_name = parts.pop().filter(|t| !t.is_empty()).ok_or_else(|| format!("empty binding `{text}`"))?;
+
+        let trigger = match trigger_name.strip_prefix("Mouse:") {
+            Some(button) => Trigger::Mouse(parse_mouse_button(button)?),
+            None => {
+                let deserializer = serde::de::value::StrDeserializer::<serde::de::value::Error>::new(trigger_name);
+                Trigger::Key(KeyCode::deserialize(deserializer).map_err(|_| format!("unknown key `{trigger_name}`"))?)
+            }
+        };
+
+        let mut binding = Self { trigger, ctrl: false, shift: false, alt: false };
+        for modifier in parts {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => binding.ctrl = true,
+                "shift" => binding.shift = true,
+                "alt" => binding.alt = true,
+                _ => return Err(format!("unknown modifier `{modifier}` in `{text}`")),
+            }
+        }
+        Ok(binding)
+    }
+}
+
+fn parse_mouse_button(name: &str) -> Result<MouseButton, String> {
+    match name {
+        "Left" => Ok(MouseButton::Left),
+        "Right" => Ok(MouseButton::Right),
+        "Middle" => Ok(MouseButton::Middle),
+        "Back" => Ok(MouseButton::Back),
+        "Forward" => Ok(MouseButton::Forward),
+        _ => name.parse::<u16>().map(MouseButton::Other).map_err(|_| format!("unknown mouse button `{name}`")),
+    }
+}
+
+#[derive(Deserialize)]
+struct InputConfig {
+    #[serde(default)]
+    bindings: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    camera: ControllerSettings,
+}
+
+//maps raw keys and mouse buttons to actions
+pub struct InputMap {
+    bindings: Vec<(Binding, Action)>,
+    pub controller_settings: ControllerSettings,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        let mut map = Self { bindings: Vec::new(), controller_settings: ControllerSettings::default() };
+        map.bind(Action::MoveForward, Binding::key(KeyCode::KeyW));
+        map.bind(Action::MoveBackward, Binding::key(KeyCode::KeyS));
+        map.bind(Action::MoveLeft, Binding::key(KeyCode::KeyA));
+        map.bind(Action::MoveRight, Binding::key(KeyCode::KeyD));
+        map.bind(Action::MoveUp, Binding::key(KeyCode::KeyQ));
+        map.bind(Action::MoveDown, Binding::key(KeyCode::KeyE));
+        map.bind(Action::Look, Binding::mouse(MouseButton::Left));
+        map.bind(Action::Pan, Binding::mouse(MouseButton::Middle));
+        map.bind(Action::Screenshot, Binding::key(KeyCode::F12));
+        map.bind(Action::SaveExr, Binding::key(KeyCode::F12).with_shift());
+        map.bind(Action::CycleAovView, Binding::key(KeyCode::KeyV));
+        map.bind(Action::ToggleCameraMode, Binding::key(KeyCode::KeyO));
+        map.bind(Action::ToggleMouseCapture, Binding::key(KeyCode::Tab));
+        map.bind(Action::ToggleOverlay, Binding::key(KeyCode::F1));
+        map.bind(Action::ToggleDenoiser, Binding::key(KeyCode::KeyN));
+        map.bind(Action::ToggleProfiler, Binding::key(KeyCode::F3));
+        map.bind(Action::Pick, Binding::mouse(MouseButton::Right));
+        map.bind(Action::Focus, Binding::key(KeyCode::KeyF));
+        map.bind(Action::ToggleRecording, Binding::key(KeyCode::F5));
+        map.bind(Action::TogglePlayback, Binding::key(KeyCode::F6));
+        map.bind(Action::Undo, Binding::key(KeyCode::KeyZ).with_ctrl());
+        map.bind(Action::Redo, Binding::key(KeyCode::KeyY).with_ctrl());
+        map.bind(Action::Redo, Binding::key(KeyCode::KeyZ).with_ctrl().with_shift());
+        map.bind(Action::SaveScene, Binding::key(KeyCode::KeyS).with_ctrl());
+        map.bind(Action::ReloadScene, Binding::key(KeyCode::KeyR).with_ctrl());
+
+        let digits = [
+            KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3,
+            KeyCode::Digit4, KeyCode::Digit5, KeyCode::Digit6,
+            KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9,
+        ];
+        for (slot, digit) in digits.into_iter().enumerate() {
+            map.bind(Action::StoreBookmark(slot), Binding::key(digit).with_ctrl());
+            map.bind(Action::RecallBookmark(slot), Binding::key(digit));
+        }
+        map
+    }
+}
+
+impl InputMap {
+    //starts from the defaults and replaces the bindings of every action listed in the file
+    //a missing file just means the defaults are used
+    pub fn load_or_default(path: &Path) -> Result<Self, String> {
+        let mut map = Self::default();
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(map),
+            Err(e) => return Err(format!("{}: {e}", path.display())),
+        };
+        let config: InputConfig = toml::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+        map.controller_settings = config.camera;
+
+        for (name, bindings) in config.bindings {
+            let action = Action::from_name(&name).ok_or_else(|| format!("{}: unknown action `{name}`", path.display()))?;
+            map.bindings.retain(|(_, a)| *a != action);
+            for text in bindings {
+                let binding = Binding::parse(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+                map.bind(action, binding);
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn bind(&mut self, action: Action, binding: Binding) {
+        self.bindings.push((binding, action));
+    }
+
+    //actions started by pressing `trigger` while `modifiers` are held
+    //only the most specific bindings fire, so ctrl+s saves the scene without also moving backward
+    pub fn pressed(&self, trigger: Trigger, modifiers: ModifiersState) -> impl Iterator<Item = Action> + '_ {
+        let matching = move || self.bindings.iter().filter(move |(b, _)| b.trigger == trigger && b.matches_modifiers(modifiers));
+        let most_specific = matching().map(|(b, _)| b.modifier_count()).max();
+        matching()
+            .filter(move |(b, _)| Some(b.modifier_count()) == most_specific)
+            .map(|(_, a)| *a)
+    }
+
+    //actions ended by releasing `trigger`, modifiers are ignored so letting go of ctrl first can't leave an action stuck on
+    pub fn released(&self, trigger: Trigger) -> impl Iterator<Item = Action> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |(b, _)| b.trigger == trigger)
+            .map(|(_, a)| *a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pressed(map: &InputMap, code: KeyCode, modifiers: ModifiersState) -> Vec<Action> {
+        map.pressed(Trigger::Key(code), modifiers).collect()
+    }
+
+    #[test]
+    fn parses_keys_and_mouse_buttons() {
+        assert_eq!(Binding::parse("KeyW"), Ok(Binding::key(KeyCode::KeyW)));
+        assert_eq!(Binding::parse("Mouse:Left"), Ok(Binding::mouse(MouseButton::Left)));
+        assert_eq!(Binding::parse("Mouse:4"), Ok(Binding::mouse(MouseButton::Other(4))));
+        assert_eq!(Binding::parse("Ctrl+Digit1"), Ok(Binding::key(KeyCode::Digit1).with_ctrl()));
+        assert_eq!(Binding::parse("Shift+Mouse:Middle"), Ok(Binding::mouse(MouseButton::Middle).with_shift()));
+        assert_eq!(
+            Binding::parse(" control + Alt + KeyA "),
+            Ok(Binding { trigger: Trigger::Key(KeyCode::KeyA), ctrl: true, shift: false, alt: true })
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_bindings() {
+        for text in ["", "Ctrl+", "KeyNope", "Hyper+KeyA", "Mouse:Wheel", "Mouse:"] {
+            assert!(Binding::parse(text).is_err(), "`{text}` parsed");
+        }
+    }
+
+    #[test]
+    fn action_names_round_trip() {
+        for action in Action::SIMPLE {
+            assert_eq!(Action::from_name(&action.name()), Some(action));
+        }
+        for slot in 0..BOOKMARK_SLOTS {
+            assert_eq!(Action::from_name(&Action::StoreBookmark(slot).name()), Some(Action::StoreBookmark(slot)));
+            assert_eq!(Action::from_name(&Action::RecallBookmark(slot).name()), Some(Action::RecallBookmark(slot)));
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_action_names() {
+        let out_of_range = format!("recall_bookmark_{}", BOOKMARK_SLOTS + 1);
+        for name in ["", "jump", "MoveForward", "store_bookmark_0", "store_bookmark_x", out_of_range.as_str()] {
+            assert_eq!(Action::from_name(name), None, "`{name}` parsed");
+        }
+    }
+
+    #[test]
+    fn extra_modifiers_keep_plain_bindings_working() {
+        let map = InputMap::default();
+        assert_eq!(pressed(&map, KeyCode::KeyW, ModifiersState::SHIFT), [Action::MoveForward]);
+        assert_eq!(pressed(&map, KeyCode::KeyW, ModifiersState::CONTROL | ModifiersState::ALT), [Action::MoveForward]);
+    }
+
+    #[test]
+    fn most_specific_binding_wins() {
+        let map = InputMap::default();
+        assert_eq!(pressed(&map, KeyCode::KeyS, ModifiersState::empty()), [Action::MoveBackward]);
+        assert_eq!(pressed(&map, KeyCode::KeyS, ModifiersState::CONTROL), [Action::SaveScene]);
+        assert_eq!(pressed(&map, KeyCode::Digit3, ModifiersState::CONTROL), [Action::StoreBookmark(2)]);
+        assert_eq!(pressed(&map, KeyCode::KeyZ, ModifiersState::CONTROL), [Action::Undo]);
+        assert_eq!(pressed(&map, KeyCode::KeyZ, ModifiersState::CONTROL | ModifiersState::SHIFT), [Action::Redo]);
+        //no binding for ctrl+shift+s, so it falls back to the plain one
+        assert_eq!(pressed(&map, KeyCode::KeyS, ModifiersState::CONTROL | ModifiersState::SHIFT), [Action::MoveBackward]);
+    }
+}
@@ -1 +1,4 @@
+pub mod bookmarks;
 pub mod cameracontroller;
+pub mod camerapath;
+pub mod inputmap;
@@ -0,0 +1,364 @@
+use std::f32::consts::PI;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use glam::{Mat3, Vec3, Vec4};
+
+use crate::gpu::raytracer::compute_pipeline::{CameraUniform, Sphere, Star, MAX_BOUNCES};
+use crate::gpu::raytracer::materials::Material;
+use crate::gpu::raytracer::sampling::SAMPLES_PER_FRAME;
+use crate::scene::scene::Scene;
+
+//same limits as compute_shader.wgsl
+const EPSILON: f32 = 0.005;
+const PIXEL_SPREAD: f32 = 0.001;
+//seeds of successive frames, as in the tiled renderer
+const SEED_STEP: u32 = 0x9E37_79B9;
+
+//the environment map as the shader sees it, rgba bytes with rows from the top
+pub struct Environment {
+    pixels: Vec<[u8; 4]>,
+    width: u32,
+    height: u32,
+}
+
+impl Environment {
+    pub fn load(path: &Path) -> image::ImageResult<Self> {
+        let image = image::open(path)?.to_rgba8();
+        let (width, height) = image.dimensions();
+        Ok(Self { pixels: image.pixels().map(|p| p.0).collect(), width, height })
+    }
+
+    //what the gpu binds when the environment can't be loaded
+    pub fn black() -> Self {
+        Self { pixels: vec![[0, 0, 0, 255]], width: 1, height: 1 }
+    }
+
+    fn texel(&self, x: u32, y: u32) -> Vec4 {
+        Vec4::from_array(self.pixels[(y * self.width + x) as usize].map(|c| c as f32 / 255.0))
+    }
+
+    //see sample_spherical_background
+    fn sample(&self, ray_dir: Vec3) -> Vec3 {
+        let relative_dir = ray_dir.normalize();
+        let theta = relative_dir.z.atan2(relative_dir.x);
+        let phi = relative_dir.y.acos();
+
+        let u = theta / (2.0 * PI) + 0.5;
+        let v = phi / PI;
+
+        let x = u * (self.width - 1) as f32;
+        let y = v * (self.height - 1) as f32;
+
+        let x0 = x.floor() as u32;
+        let x1 = (x0 + 1).min(self.width - 1);
+        let y0 = y.floor() as u32;
+        let y1 = (y0 + 1).min(self.height - 1);
+
+        let tx = x - x0 as f32;
+        let ty = y - y0 as f32;
+
+        let color0 = mix(self.texel(x0, y0), self.texel(x1, y0), tx);
+        let color1 = mix(self.texel(x0, y1), self.texel(x1, y1), tx);
+        mix(color0, color1, ty).truncate()
+    }
+}
+
+//pure rust copy of compute_shader.wgsl, renders the same scene to the same pixels give or take float precision
+//slow, but needs no gpu, so it can check shader changes on any machine and stand in when there is no adapter
+//anything changed in the shader's ray generation, intersection, shading or environment lookup must be changed here too
+pub struct CpuRaytracer {
+    pub camera: CameraUniform,
+    pub spheres: Vec<Sphere>,
+    pub lights: Vec<Star>,
+    pub environment: Environment,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CpuRaytracer {
+    //takes a copy of the scene, with the camera set up like the compute state's for an image of `width` by `height`
+    pub fn new(scene: &Scene, width: u32, height: u32) -> Self {
+        let environment = Environment::load(scene.environment_path()).unwrap_or_else(|e| {
+            eprintln!("Failed to load environment map {}: {e}", scene.environment_path().display());
+            Environment::black()
+        });
+        let camera = CameraUniform::new(
+            scene.camera.position,
+            scene.camera.forward(),
+            Vec3::Y,
+            scene.camera.fov_y,
+            width as f32 / height as f32,
+        );
+        Self {
+            camera,
+            spheres: scene.spheres().to_vec(),
+            lights: scene.lights().to_vec(),
+            environment,
+            width,
+            height,
+        }
+    }
+
+    //one frame of SAMPLES_PER_FRAME samples per pixel, what a single dispatch writes to the output buffer without adaptive sampling
+    //rows run bottom to top like the gpu buffers
+    pub fn frame(&self, seed: u32) -> Vec<[f32; 4]> {
+        let mut pixels = vec![[0.0; 4]; self.width as usize * self.height as usize];
+        //rows are handed out one at a time so threads that get the cheap rows of sky pick up more of them
+        let rows = Mutex::new(pixels.chunks_mut(self.width as usize).enumerate());
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        std::thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| loop {
+                    let Some((y, row)) = rows.lock().unwrap().next() else { break };
+                    for (x, pixel) in row.iter_mut().enumerate() {
+                        *pixel = self.pixel(x as u32, y as u32, seed).to_array();
+                    }
+                });
+            }
+        });
+        pixels
+    }
+
+    //averages as many frames as it takes to reach `samples` per pixel, rounded up to whole frames
+    pub fn render(&self, samples: u32, seed: u32) -> Vec<[f32; 4]> {
+        let frames = samples.div_ceil(SAMPLES_PER_FRAME).max(1);
+        let started = Instant::now();
+        let mut sum = vec![Vec4::ZERO; self.width as usize * self.height as usize];
+        for frame in 0..frames {
+            let pixels = self.frame(seed.wrapping_add(frame.wrapping_mul(SEED_STEP)));
+            for (total, pixel) in sum.iter_mut().zip(pixels) {
+                *total += Vec4::from_array(pixel);
+            }
+            println!(
+                "Frame {}/{frames} done on the cpu after {:.1}s",
+                frame + 1,
+                started.elapsed().as_secs_f32()
+            );
+        }
+        sum.into_iter().map(|total| (total / frames as f32).to_array()).collect()
+    }
+
+    //one pixel's samples for a frame, with the random number ids the shader gives a pixel's first 16 samples
+    fn pixel(&self, x: u32, y: u32, seed: u32) -> Vec4 {
+        let mut accumulated = Vec4::ZERO;
+        for local_y in 0..4 {
+            for local_x in 0..4 {
+                let global_id = [x * 4 + local_x, y * 4 + local_y];
+                accumulated += self.trace(x, y, global_id, seed).extend(1.0);
+            }
+        }
+        accumulated / SAMPLES_PER_FRAME as f32
+    }
+
+    //the shader's trace_path, minus the gbuffer and aovs
+    fn trace(&self, x: u32, y: u32, global_id: [u32; 2], seed: u32) -> Vec3 {
+        let rng = Rng { seed, global_id };
+        let camera = &self.camera;
+        let forward = Vec3::from_array(camera.forward);
+        let max_x = (self.width / 2) as f32;
+        let max_y = (self.height / 2) as f32;
+
+        let pixel_x = (x as f32 - max_x) / max_x;
+        let pixel_y = (y as f32 - max_y) / max_y;
+
+        let scale = (camera.fov_y * 0.5).tan();
+        let aspect_corrected_x = pixel_x * camera.aspect_ratio * scale;
+        let aspect_corrected_y = pixel_y * scale;
+
+        let right = forward.cross(Vec3::from_array(camera.up)).normalize();
+        let up = right.cross(forward).normalize();
+
+        let pix_ray_dir = (aspect_corrected_x * right + aspect_corrected_y * up + forward).normalize();
+
+        let mut ray_dir = conic_distribution(pix_ray_dir, PIXEL_SPREAD, &rng);
+        let mut ray_origin = Vec3::from_array(camera.position);
+        let mut is_inside = false;
+        let mut weight = 1.0;
+        let mut ray_accumulated_color = Vec3::ZERO;
+
+        for b in 0..MAX_BOUNCES {
+            let mut hit_point = Vec3::ZERO;
+            let mut normal = Vec3::ZERO;
+            let mut closest_t = 1000000.0;
+            let mut closest_material = None;
+            let mut inv_norm = false;
+
+            for sphere in &self.spheres {
+                //the shader clears this for every sphere, so only the last sphere tested can flip the normal
+                inv_norm = false;
+                let t = detect_hit(ray_origin, ray_dir, sphere);
+                if t > 0.0 && t < closest_t {
+                    let center = Vec3::from_array(sphere.center);
+                    closest_t = t;
+                    closest_material = Some(sphere.material);
+                    hit_point = ray_origin + t * ray_dir;
+                    normal = (hit_point - center).normalize();
+                    if (ray_origin - hit_point).length() > (ray_origin - center).length() {
+                        inv_norm = true;
+                    }
+                }
+            }
+
+            let Some(material) = closest_material else {
+                ray_accumulated_color += self.environment.sample(ray_dir) * weight;
+                break;
+            };
+
+            if inv_norm {
+                normal = -normal;
+            }
+            ray_origin = hit_point;
+
+            let refraction_dir = refract_dir(ray_dir, material.refractive_index, normal, is_inside);
+            //a zero direction is total internal reflection
+            let refracts = material.refractive_index > 0.01 && refraction_dir.length() >= 0.01;
+            if refracts {
+                is_inside = !is_inside;
+            }
+
+            let reflection_dir = ray_dir - 2.0 * ray_dir.dot(normal) * normal;
+
+            //surface roughness
+            ray_dir = if refracts {
+                conic_distribution(refraction_dir, material.mirror_matte, &rng)
+            } else {
+                conic_distribution(reflection_dir, material.mirror_matte, &rng)
+            };
+
+            for light in &self.lights {
+                ray_accumulated_color += shade(&material, light, hit_point, normal, refracts, b);
+            }
+            weight -= material.absorption;
+
+            if weight < 0.01 {
+                break;
+            }
+
+            ray_origin += EPSILON * ray_dir;
+        }
+        ray_accumulated_color
+    }
+}
+
+//diffuse and highlight from one light, scaled down with each bounce
+fn shade(material: &Material, light: &Star, hit_point: Vec3, normal: Vec3, refracts: bool, bounce: u32) -> Vec3 {
+    let light_color = Vec3::from_array(light.color) * light.intensity;
+    let light_dir = (Vec3::from_array(light.position) - hit_point).normalize();
+
+    let diffuse_intensity = if refracts {
+        normal.dot(light_dir).abs().max(0.5)
+    } else {
+        normal.dot(light_dir).max(0.5)
+    };
+    let highlight = normal.dot(light_dir).max(0.0).powf(material.specular * 32.0);
+
+    let color = Vec3::new(material.color[0], material.color[1], material.color[2]);
+    let highlight_contribution = (color * light_color * highlight).min(light_color);
+    let diffuse_contribution = (color * light_color * diffuse_intensity).min(light_color);
+
+    let falloff = 1.0 / (bounce + 1) as f32;
+    diffuse_contribution * material.absorption * falloff + highlight_contribution * material.specular * falloff
+}
+
+fn refract_dir(ray_dir: Vec3, refractive_index: f32, normal: Vec3, exit_cond: bool) -> Vec3 {
+    let eta_ratio = if exit_cond { refractive_index } else { 1.0 / refractive_index };
+    let cos_theta = (-ray_dir).dot(normal);
+    let sin_theta_sq = 1.0 - cos_theta * cos_theta;
+
+    if eta_ratio * eta_ratio * sin_theta_sq > 1.0 {
+        return Vec3::ZERO;
+    }
+
+    //snells law
+    let r_out_perpendicular = eta_ratio * (ray_dir + cos_theta * normal);
+    let r_out_parallel = -(1.0 - r_out_perpendicular.dot(r_out_perpendicular)).abs().sqrt() * normal;
+
+    r_out_perpendicular + r_out_parallel
+}
+
+//distance along the ray to the near side of the sphere, -1 for a miss
+fn detect_hit(origin: Vec3, ray_dir: Vec3, sphere: &Sphere) -> f32 {
+    let oc = origin - Vec3::from_array(sphere.center);
+    let a = ray_dir.dot(ray_dir);
+    let b = 2.0 * oc.dot(ray_dir);
+    let c = oc.dot(oc) - sphere.radius * sphere.radius;
+    let discriminant = b * b - 4.0 * a * c;
+
+    if discriminant > 0.0 {
+        return (-b - discriminant.sqrt()) / (2.0 * a);
+    }
+    -1.0
+}
+
+//a direction within a cone of half angle `stdev` around `a`
+fn conic_distribution(a: Vec3, stdev: f32, rng: &Rng) -> Vec3 {
+    let u = rng.float(0);
+    let o = rng.float(1);
+    let theta = (1.0 - u * (1.0 - stdev.cos())).acos();
+    let phi = o * 2.0 * PI;
+
+    let sin_theta = theta.sin();
+    let dir = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), theta.cos());
+
+    let up = Vec3::Z;
+    let axis = -up.cross(a).normalize();
+    let angle = up.dot(a.normalize()).acos();
+
+    (rotation_matrix_around_axis(axis, angle) * dir).normalize()
+}
+
+//the shader builds a mat4x4 column by column, only the upper 3x3 touches a direction
+fn rotation_matrix_around_axis(axis: Vec3, angle: f32) -> Mat3 {
+    let (sin_a, cos_a) = angle.sin_cos();
+    let one_minus_cos_a = 1.0 - cos_a;
+    let Vec3 { x, y, z } = axis;
+
+    Mat3::from_cols_array(&[
+        cos_a + x * x * one_minus_cos_a,
+        x * y * one_minus_cos_a - z * sin_a,
+        x * z * one_minus_cos_a + y * sin_a,
+
+        y * x * one_minus_cos_a + z * sin_a,
+        cos_a + y * y * one_minus_cos_a,
+        y * z * one_minus_cos_a - x * sin_a,
+
+        z * x * one_minus_cos_a - y * sin_a,
+        z * y * one_minus_cos_a + x * sin_a,
+        cos_a + z * z * one_minus_cos_a,
+    ])
+}
+
+//wgsl's mix
+fn mix(a: Vec4, b: Vec4, t: f32) -> Vec4 {
+    a * (1.0 - t) + b * t
+}
+
+//the shader's hash, every call with the same offset gives the same number for a thread
+struct Rng {
+    seed: u32,
+    global_id: [u32; 2],
+}
+
+impl Rng {
+    fn next(&self, offset: u32) -> u32 {
+        let state = self.seed
+            ^ self.global_id[0].wrapping_mul(374761393)
+            ^ self.global_id[1].wrapping_mul(668265263)
+            ^ offset;
+        state.wrapping_mul(1664525).wrapping_add(1013904223)
+    }
+
+    fn float(&self, offset: u32) -> f32 {
+        self.next(offset) as f32 / u32::MAX as f32
+    }
+}
+
+//flips the rows into image order and quantizes like the gpu screenshots
+pub fn to_image(pixels: &[[f32; 4]], width: u32, height: u32) -> image::RgbaImage {
+    image::RgbaImage::from_fn(width, height, |x, y| {
+        let pixel = pixels[((height - 1 - y) * width + x) as usize];
+        image::Rgba(pixel.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8))
+    })
+}
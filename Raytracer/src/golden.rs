@@ -0,0 +1,237 @@
+//golden image tests, a few canonical scenes rendered with a fixed seed and compared against tests/golden/<scene>.png
+//both the gpu, on whatever headless adapter is available, and the cpu raytracer are checked against the same references
+//after an intended change to the look of the renders, regenerate the references from the cpu path with
+//  UPDATE_GOLDEN=1 cargo test golden
+//failures write the render and a heat map of the difference to target/golden/
+
+use std::path::{Path, PathBuf};
+
+use glam::{vec3, Vec3};
+use winit::dpi::PhysicalSize;
+
+use crate::cpu::raytracer::{self as cpu_raytracer, CpuRaytracer};
+use crate::gpu::raytracer::compute_pipeline::{read_texels, ComputeState, Sphere, Star, TileInfo};
+use crate::gpu::raytracer::materials;
+use crate::gpu::raytracer::sampling::SAMPLES_PER_FRAME;
+use crate::gpu::wgpu_init::headless_device;
+use crate::scene::scene::{Camera, Scene};
+
+const WIDTH: u32 = 160;
+const HEIGHT: u32 = 120;
+const SAMPLES: u32 = 2 * SAMPLES_PER_FRAME;
+const SEED: u32 = 0;
+
+//CIE76 distance in Lab, about 2.3 is the smallest difference people notice side by side
+const PIXEL_TOLERANCE: f32 = 10.0;
+//share of pixels allowed past PIXEL_TOLERANCE, the gpu and cpu take different paths through a few edge and glass pixels
+const MAX_DIFFERENT_PIXELS: f32 = 0.01;
+const MAX_MEAN_DIFFERENCE: f32 = 1.0;
+
+fn reference_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn output_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("target/golden")
+}
+
+fn empty_scene(position: Vec3, forward: Vec3) -> Scene {
+    let mut scene = Scene::empty(PathBuf::from("golden.scene"));
+    scene.camera = Camera::new(position, forward);
+    scene
+}
+
+fn sun() -> Star {
+    Star { color: [1.0, 0.95, 0.85], intensity: 1.0, position: [800.0, 1500.0, -1200.0], radius: 0.0 }
+}
+
+fn single_sphere() -> Scene {
+    let mut scene = empty_scene(vec3(0.0, 0.0, -100.0), Vec3::Z);
+    scene.add_sphere(Sphere { center: [0.0, 0.0, 0.0], radius: 30.0, material: materials::pearlescent() });
+    scene.add_light(sun());
+    scene
+}
+
+//the built in scene, seen from in front rather than from its default camera
+fn showcase() -> Scene {
+    let mut scene = Scene::new();
+    scene.path = PathBuf::from("golden.scene");
+    scene.camera = Camera::new(vec3(40.0, 20.0, -160.0), vec3(0.0, -0.1, 1.0));
+    scene
+}
+
+//glass over a rough floor, lit from above
+fn glass_caustics() -> Scene {
+    let mut scene = empty_scene(vec3(0.0, 30.0, -120.0), vec3(0.0, -0.25, 1.0));
+    scene.add_sphere(Sphere { center: [0.0, -10030.0, 0.0], radius: 10000.0, material: materials::rusty_metal() });
+    scene.add_sphere(Sphere { center: [-25.0, 0.0, 0.0], radius: 30.0, material: materials::glass_material() });
+    scene.add_sphere(Sphere { center: [35.0, -10.0, -20.0], radius: 20.0, material: materials::emerald_crystal() });
+    scene.add_light(Star { position: [0.0, 2000.0, 0.0], ..sun() });
+    scene
+}
+
+//a warm and a cool light from either side, each should show up on the sphere
+fn two_lights() -> Scene {
+    let mut scene = empty_scene(vec3(0.0, 0.0, -100.0), Vec3::Z);
+    scene.add_sphere(Sphere { center: [0.0, 0.0, 0.0], radius: 30.0, material: materials::pearlescent() });
+    scene.add_light(Star { color: [1.0, 0.3, 0.1], position: [-1500.0, 300.0, -800.0], ..sun() });
+    scene.add_light(Star { color: [0.1, 0.4, 1.0], position: [1500.0, 300.0, -800.0], ..sun() });
+    scene
+}
+
+fn environment_only() -> Scene {
+    empty_scene(Vec3::ZERO, vec3(1.0, 0.1, 0.3))
+}
+
+//a reference image name and the scene it shows
+type GoldenScene = (&'static str, fn() -> Scene);
+
+const SCENES: [GoldenScene; 5] = [
+    ("single_sphere", single_sphere),
+    ("showcase", showcase),
+    ("glass_caustics", glass_caustics),
+    ("two_lights", two_lights),
+    ("environment_only", environment_only),
+];
+
+fn render_cpu(scene: &Scene) -> Vec<[f32; 4]> {
+    CpuRaytracer::new(scene, WIDTH, HEIGHT).render(SAMPLES, SEED)
+}
+
+//the frames the tiled renderer would take without adaptive sampling, averaged on the cpu like CpuRaytracer::render
+fn render_gpu(device: &wgpu::Device, queue: &wgpu::Queue, scene: &Scene) -> Vec<[f32; 4]> {
+    let mut compute_state = pollster::block_on(ComputeState::new(device, queue, &PhysicalSize::new(WIDTH, HEIGHT), scene));
+    let camera = CpuRaytracer::new(scene, WIDTH, HEIGHT).camera;
+    queue.write_buffer(&compute_state.camera_buffer, 0, bytemuck::cast_slice(&[camera]));
+    compute_state.set_tile(queue, TileInfo::new([0, 0], [WIDTH, HEIGHT], [WIDTH, HEIGHT]));
+
+    let frames = SAMPLES / SAMPLES_PER_FRAME;
+    let mut sum = vec![[0.0; 4]; (WIDTH * HEIGHT) as usize];
+    for frame in 0..frames {
+        let seed = SEED.wrapping_add(frame.wrapping_mul(0x9E37_79B9));
+        queue.write_buffer(&compute_state.rand_buffer, 0, bytemuck::cast_slice(&[seed]));
+        compute_state.sampler.begin_frame(queue, &camera);
+        compute_state.dispatch_pixels(device, queue, None);
+        let pixels = read_texels(device, queue, &compute_state.output_buffer, (WIDTH * HEIGHT) as u64);
+        for (total, pixel) in sum.iter_mut().zip(pixels) {
+            for c in 0..4 {
+                total[c] += pixel[c] / frames as f32;
+            }
+        }
+    }
+    sum
+}
+
+//8 bit srgb, as stored in the reference, to CIE Lab under D65
+fn lab(pixel: [u8; 4]) -> Vec3 {
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    };
+    let (r, g, b) = (linear(pixel[0]), linear(pixel[1]), linear(pixel[2]));
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f32| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    vec3(116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+struct Comparison {
+    different_pixels: f32,
+    mean_difference: f32,
+    heat_map: image::RgbImage,
+}
+
+impl Comparison {
+    fn new(actual: &image::RgbaImage, reference: &image::RgbaImage) -> Self {
+        let mut heat_map = image::RgbImage::new(actual.width(), actual.height());
+        let mut different = 0;
+        let mut total = 0.0;
+        for (x, y, pixel) in actual.enumerate_pixels() {
+            let difference = lab(pixel.0).distance(lab(reference.get_pixel(x, y).0));
+            total += difference;
+            if difference > PIXEL_TOLERANCE {
+                different += 1;
+            }
+            //black where they agree, through red to yellow at twice the tolerance
+            let heat = (difference / (2.0 * PIXEL_TOLERANCE)).min(1.0);
+            heat_map.put_pixel(x, y, image::Rgb([(heat * 2.0).min(1.0), (heat * 2.0 - 1.0).max(0.0), 0.0].map(|c| (c * 255.0) as u8)));
+        }
+        let count = (actual.width() * actual.height()) as f32;
+        Self { different_pixels: different as f32 / count, mean_difference: total / count, heat_map }
+    }
+
+    fn passed(&self) -> bool {
+        self.different_pixels <= MAX_DIFFERENT_PIXELS && self.mean_difference <= MAX_MEAN_DIFFERENCE
+    }
+}
+
+//checks every scene against its reference, returns a line per failure
+fn check(backend: &str, mut render: impl FnMut(&Scene) -> Vec<[f32; 4]>) -> Vec<String> {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let mut failures = Vec::new();
+    for (name, scene) in SCENES {
+        let actual = cpu_raytracer::to_image(&render(&scene()), WIDTH, HEIGHT);
+        let reference_path = reference_dir().join(format!("{name}.png"));
+        if update {
+            std::fs::create_dir_all(reference_dir()).unwrap();
+            actual.save(&reference_path).unwrap();
+            println!("Updated {}", reference_path.display());
+            continue;
+        }
+
+        let reference = match image::open(&reference_path) {
+            Ok(reference) => reference.to_rgba8(),
+            Err(e) => {
+                failures.push(format!("{name}: failed to load {}: {e}", reference_path.display()));
+                continue;
+            }
+        };
+        if reference.dimensions() != actual.dimensions() {
+            failures.push(format!("{name}: reference is {:?}, render is {:?}", reference.dimensions(), actual.dimensions()));
+            continue;
+        }
+
+        let comparison = Comparison::new(&actual, &reference);
+        println!(
+            "{name} on the {backend}: {:.2}% of pixels differ, mean difference {:.3}",
+            comparison.different_pixels * 100.0,
+            comparison.mean_difference
+        );
+        if !comparison.passed() {
+            std::fs::create_dir_all(output_dir()).unwrap();
+            let render_path = output_dir().join(format!("{name}_{backend}.png"));
+            let diff_path = output_dir().join(format!("{name}_{backend}_diff.png"));
+            actual.save(&render_path).unwrap();
+            comparison.heat_map.save(&diff_path).unwrap();
+            failures.push(format!(
+                "{name}: {:.2}% of pixels differ, mean difference {:.3}, see {}",
+                comparison.different_pixels * 100.0,
+                comparison.mean_difference,
+                diff_path.display()
+            ));
+        }
+    }
+    failures
+}
+
+#[test]
+fn cpu_matches_references() {
+    let failures = check("cpu", render_cpu);
+    assert!(failures.is_empty(), "golden images differ on the cpu:\n{}", failures.join("\n"));
+}
+
+//skipped rather than failed when the machine has no adapter at all, the cpu test still covers the scenes
+#[test]
+fn gpu_matches_references() {
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        return;
+    }
+    let Some((device, queue)) = headless_device() else {
+        println!("No adapter available, skipping the gpu golden images");
+        return;
+    };
+    let failures = check("gpu", |scene| render_gpu(&device, &queue, scene));
+    assert!(failures.is_empty(), "golden images differ on the gpu:\n{}", failures.join("\n"));
+}
@@ -0,0 +1,38 @@
+use std::fmt;
+
+//everything that can stop the gpu renderer, worded for the person running the app
+#[derive(Debug)]
+pub enum RendererError {
+    //the window can't hand out the native handle a surface is made from
+    WindowHandle(wgpu::rwh::HandleError),
+    CreateSurface(wgpu::CreateSurfaceError),
+    NoAdapter,
+    RequestDevice(wgpu::RequestDeviceError),
+    OutOfMemory,
+    //the device went away and no new one could be opened in its place
+    DeviceLost(String),
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RendererError::WindowHandle(e) => write!(f, "the window has no handle to draw to: {e}"),
+            RendererError::CreateSurface(e) => write!(f, "failed to create a surface for the window: {e}"),
+            RendererError::NoAdapter => write!(f, "no compatible graphics adapter found"),
+            RendererError::RequestDevice(e) => write!(f, "failed to open the graphics device: {e}"),
+            RendererError::OutOfMemory => write!(f, "the graphics device ran out of memory"),
+            RendererError::DeviceLost(reason) => write!(f, "the graphics device was lost: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for RendererError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RendererError::WindowHandle(e) => Some(e),
+            RendererError::CreateSurface(e) => Some(e),
+            RendererError::RequestDevice(e) => Some(e),
+            RendererError::NoAdapter | RendererError::OutOfMemory | RendererError::DeviceLost(_) => None,
+        }
+    }
+}
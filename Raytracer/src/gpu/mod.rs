@@ -1,2 +1,6 @@
+pub mod profiler;
 pub mod wgpu_init;
 pub mod raytracer;
+pub mod preprocessor;
+pub mod error;
+pub mod pacing;
@@ -0,0 +1,58 @@
+use std::time::{Duration, Instant};
+
+use winit::event_loop::{ActiveEventLoop, ControlFlow};
+use winit::window::Window;
+
+//how often an idle app still draws a frame to check on the shader files, when they are watched
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+//decides when the next frame starts: straight away, once the frame rate cap allows, or not until something happens
+pub struct FramePacer {
+    //shortest time between the starts of two frames, none to draw as fast as presentation allows
+    min_frame_time: Option<Duration>,
+    frame_start: Instant,
+    //no frame is scheduled, the next input wakes the app up again
+    idle: bool,
+}
+
+impl FramePacer {
+    pub fn new(max_fps: Option<f32>) -> Self {
+        Self {
+            min_frame_time: max_fps.map(|fps| Duration::from_secs_f32(1.0 / fps)),
+            frame_start: Instant::now(),
+            idle: false,
+        }
+    }
+
+    pub fn begin_frame(&mut self) {
+        self.frame_start = Instant::now();
+        self.idle = false;
+    }
+
+    //called at the end of a frame, waits are ended by winit with StartCause::ResumeTimeReached, which should request a redraw
+    //`idle` stops drawing until an event arrives, `poll` still draws now and then to check on watched files
+    pub fn schedule(&mut self, event_loop: &ActiveEventLoop, window: &Window, idle: bool, poll: bool) {
+        self.idle = idle;
+        if idle && poll {
+            event_loop.set_control_flow(ControlFlow::WaitUntil(Instant::now() + IDLE_POLL_INTERVAL));
+        } else if idle {
+            event_loop.set_control_flow(ControlFlow::Wait);
+        } else {
+            match self.min_frame_time.map(|time| self.frame_start + time).filter(|next| *next > Instant::now()) {
+                Some(next) => event_loop.set_control_flow(ControlFlow::WaitUntil(next)),
+                None => {
+                    event_loop.set_control_flow(ControlFlow::Wait);
+                    window.request_redraw();
+                }
+            }
+        }
+    }
+
+    //input arrived, an idle app draws again to respond to it
+    pub fn wake(&mut self, window: &Window) {
+        if self.idle {
+            self.idle = false;
+            window.request_redraw();
+        }
+    }
+}
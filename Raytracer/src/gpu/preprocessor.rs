@@ -0,0 +1,342 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+//a small c style preprocessor for wgsl, which has no way of sharing code between files by itself
+//  #include "file.wgsl"   pastes in a file, relative to the one including it, a file already included is skipped
+//  #define NAME [value]   afterwards NAME is replaced by value wherever it appears as a whole word
+//  #undef NAME
+//  #ifdef NAME, #ifndef NAME, #else, #endif
+//defines passed in from rust are set before the first line, so `#ifndef` can give them defaults
+
+//the expanded source, with the file and line every line of it came from
+pub struct Shader {
+    pub source: String,
+    origins: Vec<(String, usize)>,
+}
+
+impl Shader {
+    //file and 1 based line that produced line `line` of the expanded source
+    pub fn origin(&self, line: usize) -> Option<(&str, usize)> {
+        self.origins.get(line.checked_sub(1)?).map(|(file, line)| (file.as_str(), *line))
+    }
+
+    //parses and validates the expanded source with naga so errors can point at the original files
+    //wgpu would only report a line of the expanded source, which doesn't exist anywhere on disk
+    pub fn validate(&self) -> Result<(), String> {
+        let module = naga::front::wgsl::parse_str(&self.source)
+            .map_err(|e| self.describe(e.location(&self.source).map(|l| l.line_number), e.message()))?;
+        naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+            .validate(&module)
+            .map_err(|e| {
+                let mut message = e.to_string();
+                let mut source = e.as_inner().source();
+                while let Some(inner) = source {
+                    message += &format!(": {inner}");
+                    source = inner.source();
+                }
+                for (span, label) in e.spans().filter(|(_, label)| !label.is_empty()) {
+                    let line = span.location(&self.source).line_number as usize;
+                    match self.origin(line) {
+                        Some((file, line)) => message += &format!("\n  {file}:{line}: {label}"),
+                        None => message += &format!("\n  {label}"),
+                    }
+                }
+                self.describe(e.location(&self.source).map(|l| l.line_number), &message)
+            })?;
+        Ok(())
+    }
+
+    fn describe(&self, line: Option<u32>, message: &str) -> String {
+        match line.and_then(|line| self.origin(line as usize)) {
+            Some((file, line)) => format!("{file}:{line}: {message}"),
+            None => message.to_string(),
+        }
+    }
+
+    pub fn create_module(&self, device: &wgpu::Device, label: &str) -> wgpu::ShaderModule {
+        device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(self.source.as_str().into()),
+        })
+    }
+}
+
+//one #ifdef, #ifndef or #else block
+struct Condition {
+    //whether lines in the block are kept
+    active: bool,
+    //whether the block around this one is kept, an #else can't switch lines on inside a block that is off
+    enclosing_active: bool,
+    seen_else: bool,
+    line: usize,
+}
+
+struct Preprocessor<'a> {
+    load: &'a dyn Fn(&str) -> Result<String, String>,
+    defines: HashMap<String, String>,
+    included: HashSet<String>,
+    shader: Shader,
+}
+
+//expands `entry` and everything it includes, `load` reads a file by its path relative to the shader folder
+pub fn preprocess(entry: &str, defines: &[(&str, String)], load: &dyn Fn(&str) -> Result<String, String>) -> Result<Shader, String> {
+    let mut preprocessor = Preprocessor {
+        load,
+        defines: defines.iter().map(|(name, value)| (name.to_string(), value.clone())).collect(),
+        included: HashSet::new(),
+        shader: Shader { source: String::new(), origins: Vec::new() },
+    };
+    let text = load(entry)?;
+    preprocessor.included.insert(entry.to_string());
+    preprocessor.expand(entry, &text)?;
+    Ok(preprocessor.shader)
+}
+
+impl Preprocessor<'_> {
+    fn expand(&mut self, file: &str, text: &str) -> Result<(), String> {
+        let mut conditions: Vec<Condition> = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let number = index + 1;
+            let error = |message: String| format!("{file}:{number}: {message}");
+            let active = conditions.last().is_none_or(|c| c.active);
+
+            let Some(directive) = line.trim_start().strip_prefix('#') else {
+                if active {
+                    self.shader.source += &self.substitute(line);
+                    self.shader.source.push('\n');
+                    self.shader.origins.push((file.to_string(), number));
+                }
+                continue;
+            };
+            let (keyword, argument) = directive.split_once(char::is_whitespace).unwrap_or((directive, ""));
+            let argument = argument.trim();
+
+            match keyword {
+                "ifdef" | "ifndef" => {
+                    let name = identifier(argument).map_err(error)?;
+                    let defined = self.defines.contains_key(name);
+                    conditions.push(Condition {
+                        active: active && defined == (keyword == "ifdef"),
+                        enclosing_active: active,
+                        seen_else: false,
+                        line: number,
+                    });
+                }
+                "else" => {
+                    let condition = conditions.last_mut().ok_or_else(|| error("#else without #ifdef".to_string()))?;
+                    if condition.seen_else {
+                        return Err(error("second #else for the same #ifdef".to_string()));
+                    }
+                    condition.seen_else = true;
+                    condition.active = condition.enclosing_active && !condition.active;
+                }
+                "endif" => {
+                    conditions.pop().ok_or_else(|| error("#endif without #ifdef".to_string()))?;
+                }
+                _ if !active => {}
+                "define" => {
+                    let (name, value) = argument.split_once(char::is_whitespace).unwrap_or((argument, ""));
+                    let name = identifier(name).map_err(error)?;
+                    let value = self.substitute(value.trim());
+                    self.defines.insert(name.to_string(), value);
+                }
+                "undef" => {
+                    self.defines.remove(identifier(argument).map_err(error)?);
+                }
+                "include" => {
+                    let path = argument
+                        .strip_prefix('"')
+                        .and_then(|a| a.strip_suffix('"'))
+                        .ok_or_else(|| error(format!("expected #include \"file\", found `{argument}`")))?;
+                    let path = resolve(file, path);
+                    if self.included.insert(path.clone()) {
+                        let text = (self.load)(&path).map_err(error)?;
+                        self.expand(&path, &text)?;
+                    }
+                }
+                _ => return Err(error(format!("unknown directive #{keyword}"))),
+            }
+        }
+        match conditions.pop() {
+            Some(condition) => Err(format!("{file}:{}: #ifdef without #endif", condition.line)),
+            None => Ok(()),
+        }
+    }
+
+    //replaces whole words that are defined, everything else is copied as it is
+    fn substitute(&self, line: &str) -> String {
+        if self.defines.is_empty() {
+            return line.to_string();
+        }
+        let mut result = String::with_capacity(line.len());
+        let mut rest = line;
+        while let Some(start) = rest.find(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            //digits before a word belong to a number such as 1e5, so they go through untouched
+            let (before, word_start) = rest.split_at(start);
+            result += before;
+            let end = word_start.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(word_start.len());
+            let (word, after) = word_start.split_at(end);
+            let part_of_number = before.ends_with(|c: char| c.is_ascii_digit() || c == '.');
+            match self.defines.get(word) {
+                Some(value) if !part_of_number => result += value,
+                _ => result += word,
+            }
+            rest = after;
+        }
+        result + rest
+    }
+}
+
+fn identifier(name: &str) -> Result<&str, String> {
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(name)
+    } else {
+        Err(format!("expected a name, found `{name}`"))
+    }
+}
+
+//`path` relative to the folder of `from`, with any `..` folded away
+fn resolve(from: &str, path: &str) -> String {
+    let mut parts: Vec<&str> = from.split('/').collect();
+    parts.pop();
+    for part in path.split('/') {
+        match part {
+            "." | "" => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //runs the preprocessor over in memory files, starting from main.wgsl
+    fn run(files: &[(&str, &str)], defines: &[(&str, String)]) -> Result<Shader, String> {
+        let files: HashMap<&str, &str> = files.iter().copied().collect();
+        let load = |path: &str| files.get(path).map(|text| text.to_string()).ok_or_else(|| format!("no file {path}"));
+        preprocess("main.wgsl", defines, &load)
+    }
+
+    fn lines(shader: &Shader) -> Vec<&str> {
+        shader.source.lines().collect()
+    }
+
+    #[test]
+    fn includes_resolve_relative_to_the_including_file() {
+        let shader = run(
+            &[
+                ("main.wgsl", "#include \"shaders/a.wgsl\"\nmain"),
+                ("shaders/a.wgsl", "#include \"../common.wgsl\"\na"),
+                ("common.wgsl", "common"),
+            ],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(lines(&shader), ["common", "a", "main"]);
+    }
+
+    #[test]
+    fn a_file_is_only_included_once() {
+        let shader = run(
+            &[
+                ("main.wgsl", "#include \"a.wgsl\"\n#include \"./b.wgsl\"\n#include \"a.wgsl\"\nmain"),
+                ("a.wgsl", "#include \"b.wgsl\"\na"),
+                ("b.wgsl", "#include \"a.wgsl\"\nb"),
+            ],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(lines(&shader), ["b", "a", "main"]);
+    }
+
+    #[test]
+    fn nested_conditions() {
+        let text = "\
+#ifdef A
+a
+#ifndef B
+a_not_b
+#else
+a_and_b
+#endif
+#else
+not_a
+#ifdef B
+not_a_but_b
+#endif
+#endif
+always";
+        let expand = |defines: &[&str]| {
+            let defines: Vec<(&str, String)> = defines.iter().map(|name| (*name, String::new())).collect();
+            run(&[("main.wgsl", text)], &defines).unwrap().source
+        };
+        assert_eq!(expand(&[]), "not_a\nalways\n");
+        assert_eq!(expand(&["A"]), "a\na_not_b\nalways\n");
+        assert_eq!(expand(&["A", "B"]), "a\na_and_b\nalways\n");
+        assert_eq!(expand(&["B"]), "not_a\nnot_a_but_b\nalways\n");
+    }
+
+    #[test]
+    fn ifndef_gives_defines_from_rust_a_default() {
+        let text = "#ifndef MAX_BOUNCES\n#define MAX_BOUNCES 8u\n#endif\nlet b = MAX_BOUNCES;";
+        assert_eq!(run(&[("main.wgsl", text)], &[]).unwrap().source, "let b = 8u;\n");
+        assert_eq!(run(&[("main.wgsl", text)], &[("MAX_BOUNCES", "2u".to_string())]).unwrap().source, "let b = 2u;\n");
+    }
+
+    #[test]
+    fn defines_replace_whole_words_only() {
+        let text = "\
+#define N 4u
+#define e5 nope
+#define SCALE N * 2u
+let a = N + N_MAX + MIN_N + vecN;
+let b = 1e5 + 2.5e5 + e5;
+let c = SCALE;
+#undef N
+let d = N;";
+        let shader = run(&[("main.wgsl", text)], &[]).unwrap();
+        assert_eq!(
+            lines(&shader),
+            ["let a = 4u + N_MAX + MIN_N + vecN;", "let b = 1e5 + 2.5e5 + nope;", "let c = 4u * 2u;", "let d = N;"]
+        );
+    }
+
+    #[test]
+    fn origins_point_into_included_files() {
+        let shader = run(
+            &[
+                ("main.wgsl", "first\n#include \"shaders/lib.wgsl\"\n#ifdef X\nskipped\n#endif\nlast"),
+                ("shaders/lib.wgsl", "// lib\n\n#define X\nlib_line"),
+            ],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(lines(&shader), ["first", "// lib", "", "lib_line", "skipped", "last"]);
+        assert_eq!(shader.origin(1), Some(("main.wgsl", 1)));
+        assert_eq!(shader.origin(2), Some(("shaders/lib.wgsl", 1)));
+        assert_eq!(shader.origin(4), Some(("shaders/lib.wgsl", 4)));
+        assert_eq!(shader.origin(5), Some(("main.wgsl", 4)));
+        assert_eq!(shader.origin(6), Some(("main.wgsl", 6)));
+        assert_eq!(shader.origin(0), None);
+        assert_eq!(shader.origin(7), None);
+    }
+
+    #[test]
+    fn errors_name_the_file_and_line() {
+        let error = |text: &str| run(&[("main.wgsl", "#include \"lib.wgsl\""), ("lib.wgsl", text)], &[]).err().unwrap();
+        assert_eq!(error("a\n#ifdef A\nb"), "lib.wgsl:2: #ifdef without #endif");
+        assert_eq!(error("#ifdef A\n#ifdef B\n#endif"), "lib.wgsl:1: #ifdef without #endif");
+        assert_eq!(error("a\nb\n#endif"), "lib.wgsl:3: #endif without #ifdef");
+        assert_eq!(error("#pragma once"), "lib.wgsl:1: unknown directive #pragma");
+        assert_eq!(error("\n#else"), "lib.wgsl:2: #else without #ifdef");
+        assert_eq!(error("#include \"missing.wgsl\""), "lib.wgsl:1: no file missing.wgsl");
+        assert_eq!(run(&[("main.wgsl", "ok\n#elif A")], &[]).err().unwrap(), "main.wgsl:2: unknown directive #elif");
+    }
+}
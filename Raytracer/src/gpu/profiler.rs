@@ -0,0 +1,268 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+//frames averaged for the readout
+const WINDOW: usize = 60;
+//how often the readout is refreshed, in seconds
+const READOUT_INTERVAL: f32 = 0.5;
+
+//the parts of a frame that are timed, each gets a begin and end timestamp
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Scope {
+    Compute,
+    Denoise,
+    Display,
+}
+
+impl Scope {
+    const ALL: [Scope; 3] = [Scope::Compute, Scope::Denoise, Scope::Display];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+//what one frame cost, times in milliseconds
+#[derive(Copy, Clone, Debug, Default)]
+struct FrameTimes {
+    scopes: [f32; 3],
+    frame: f32,
+    samples: u64,
+    rays: u64,
+}
+
+//timestamp queries and their buffers, only created when the adapter supports them
+struct Timestamps {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    //nanoseconds per timestamp tick
+    period: f32,
+}
+
+//times the compute, denoise and display passes each frame
+//uses gpu timestamps when the device has TIMESTAMP_QUERY, otherwise waits for the gpu after each scope and times that on the cpu
+pub struct Profiler {
+    pub enabled: bool,
+    timestamps: Option<Timestamps>,
+    current: FrameTimes,
+    //which scopes ran this frame, a converged adaptive render skips compute and denoise
+    ran: [bool; 3],
+    cpu_start: Option<Instant>,
+    frame_start: Instant,
+    history: VecDeque<FrameTimes>,
+    last_readout: Instant,
+    frame_index: u64,
+    started: Instant,
+    csv: Option<BufWriter<File>>,
+}
+
+impl Profiler {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let timestamps = create_timestamps(device, queue);
+
+        let now = Instant::now();
+        Self {
+            enabled: false,
+            timestamps,
+            current: FrameTimes::default(),
+            ran: [false; 3],
+            cpu_start: None,
+            frame_start: now,
+            history: VecDeque::with_capacity(WINDOW),
+            last_readout: now,
+            frame_index: 0,
+            started: now,
+            csv: None,
+        }
+    }
+
+    //moves the timers to a new device after the old one was lost, the frame in flight is dropped
+    pub fn set_device(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.timestamps = create_timestamps(device, queue);
+        self.ran = [false; 3];
+        self.cpu_start = None;
+        self.frame_start = Instant::now();
+    }
+
+    pub fn uses_timestamps(&self) -> bool {
+        self.timestamps.is_some()
+    }
+
+    //starts logging one row per frame to `path`, profiling is switched on as well
+    pub fn log_to(&mut self, path: &Path) -> io::Result<()> {
+        let mut csv = BufWriter::new(File::create(path)?);
+        writeln!(csv, "frame,time_s,compute_ms,denoise_ms,display_ms,frame_ms,samples,rays,timer")?;
+        self.csv = Some(csv);
+        self.enabled = true;
+        Ok(())
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        self.history.clear();
+        self.frame_start = Instant::now();
+    }
+
+    pub fn compute_pass_writes(&self, scope: Scope) -> Option<wgpu::ComputePassTimestampWrites<'_>> {
+        let timestamps = self.timestamps.as_ref().filter(|_| self.enabled)?;
+        Some(wgpu::ComputePassTimestampWrites {
+            query_set: &timestamps.query_set,
+            beginning_of_pass_write_index: Some(2 * scope.index() as u32),
+            end_of_pass_write_index: Some(2 * scope.index() as u32 + 1),
+        })
+    }
+
+    pub fn render_pass_writes(&self, scope: Scope) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        let timestamps = self.timestamps.as_ref().filter(|_| self.enabled)?;
+        Some(wgpu::RenderPassTimestampWrites {
+            query_set: &timestamps.query_set,
+            beginning_of_pass_write_index: Some(2 * scope.index() as u32),
+            end_of_pass_write_index: Some(2 * scope.index() as u32 + 1),
+        })
+    }
+
+    //brackets the cpu side of a scope, the pass itself picks up its timestamp writes from the methods above
+    pub fn begin(&mut self, _scope: Scope) {
+        if self.enabled && self.timestamps.is_none() {
+            self.cpu_start = Some(Instant::now());
+        }
+    }
+
+    pub fn end(&mut self, scope: Scope, device: &wgpu::Device) {
+        if !self.enabled {
+            return;
+        }
+        self.ran[scope.index()] = true;
+        if let Some(start) = self.cpu_start.take() {
+            //without timestamps the only way to know when the gpu finished is to wait for it
+            device.poll(wgpu::Maintain::Wait);
+            self.current.scopes[scope.index()] = start.elapsed().as_secs_f32() * 1000.0;
+        }
+    }
+
+    //collects the timestamps, updates the rolling stats and csv, returns a new readout when one is due
+    pub fn end_frame(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, samples: u64, rays: u64) -> Option<String> {
+        if !self.enabled {
+            return None;
+        }
+        let ran = std::mem::take(&mut self.ran);
+        if let Some(timestamps) = self.timestamps.as_ref() {
+            let ticks = read_timestamps(device, queue, timestamps);
+            for scope in Scope::ALL {
+                let (begin, end) = (ticks[2 * scope.index()], ticks[2 * scope.index() + 1]);
+                self.current.scopes[scope.index()] = end.saturating_sub(begin) as f32 * timestamps.period / 1_000_000.0;
+            }
+        }
+        for scope in Scope::ALL {
+            if !ran[scope.index()] {
+                self.current.scopes[scope.index()] = 0.0;
+            }
+        }
+        let now = Instant::now();
+        self.current.frame = (now - self.frame_start).as_secs_f32() * 1000.0;
+        self.current.samples = samples;
+        self.current.rays = rays;
+        self.frame_start = now;
+
+        let frame = std::mem::take(&mut self.current);
+        if let Some(csv) = self.csv.as_mut() {
+            let timer = if self.timestamps.is_some() { "gpu" } else { "cpu" };
+            let row = writeln!(
+                csv,
+                "{},{:.4},{:.4},{:.4},{:.4},{:.4},{},{},{timer}",
+                self.frame_index,
+                self.started.elapsed().as_secs_f32(),
+                frame.scopes[0],
+                frame.scopes[1],
+                frame.scopes[2],
+                frame.frame,
+                frame.samples,
+                frame.rays
+            );
+            if let Err(e) = row {
+                eprintln!("Stopped writing the profiler log: {e}");
+                self.csv = None;
+            }
+        }
+        self.frame_index += 1;
+
+        if self.history.len() == WINDOW {
+            self.history.pop_front();
+        }
+        self.history.push_back(frame);
+
+        if self.last_readout.elapsed().as_secs_f32() < READOUT_INTERVAL {
+            return None;
+        }
+        self.last_readout = now;
+        Some(self.readout())
+    }
+
+    //averages over the last WINDOW frames
+    fn readout(&self) -> String {
+        let count = self.history.len().max(1) as f32;
+        let average = |f: &dyn Fn(&FrameTimes) -> f32| self.history.iter().map(f).sum::<f32>() / count;
+        let frame_ms = average(&|t| t.frame).max(1e-3);
+        let seconds = self.history.iter().map(|t| t.frame).sum::<f32>().max(1e-3) / 1000.0;
+        let samples = self.history.iter().map(|t| t.samples).sum::<u64>() as f32 / seconds;
+        let rays = self.history.iter().map(|t| t.rays).sum::<u64>() as f32 / seconds;
+        format!(
+            "compute {:.2} ms | denoise {:.2} ms | display {:.2} ms | {:.1} fps | {:.0} Msamples/s | {:.0} Mrays/s | {} timer",
+            average(&|t| t.scopes[0]),
+            average(&|t| t.scopes[1]),
+            average(&|t| t.scopes[2]),
+            1000.0 / frame_ms,
+            samples / 1e6,
+            rays / 1e6,
+            if self.timestamps.is_some() { "gpu" } else { "cpu" }
+        )
+    }
+}
+
+fn read_timestamps(device: &wgpu::Device, queue: &wgpu::Queue, timestamps: &Timestamps) -> Vec<u64> {
+    let count = 2 * Scope::ALL.len() as u32;
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Profiler Resolve Encoder"),
+    });
+    encoder.resolve_query_set(&timestamps.query_set, 0..count, &timestamps.resolve_buffer, 0);
+    encoder.copy_buffer_to_buffer(&timestamps.resolve_buffer, 0, &timestamps.readback_buffer, 0, timestamps.resolve_buffer.size());
+    queue.submit(Some(encoder.finish()));
+
+    let slice = timestamps.readback_buffer.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |_| {});
+    device.poll(wgpu::Maintain::Wait);
+    let ticks = bytemuck::pod_collect_to_vec(&slice.get_mapped_range()[..]);
+    timestamps.readback_buffer.unmap();
+    ticks
+}
+
+fn create_timestamps(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Timestamps> {
+    device.features().contains(wgpu::Features::TIMESTAMP_QUERY).then(|| {
+        let count = 2 * Scope::ALL.len() as u32;
+        let size = count as u64 * std::mem::size_of::<u64>() as u64;
+        Timestamps {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("Profiler Query Set"),
+                ty: wgpu::QueryType::Timestamp,
+                count,
+            }),
+            resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Profiler Resolve Buffer"),
+                size,
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Profiler Readback Buffer"),
+                size,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            period: queue.get_timestamp_period(),
+        }
+    })
+}
@@ -0,0 +1,129 @@
+use std::path::Path;
+
+use exr::prelude::*;
+
+use crate::gpu::raytracer::compute_pipeline::{read_buffer, ComputeState, RESOLUTION_X, RESOLUTION_Y};
+
+//what the display pass shows, the numbering must match the view switch in fragment_shader.wgsl
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum AovView {
+    #[default]
+    Beauty,
+    Depth,
+    Normal,
+    Albedo,
+    Position,
+    Direct,
+    Indirect,
+    ObjectId,
+    MaterialId,
+    Convergence,
+}
+
+impl AovView {
+    const ALL: [AovView; 10] = [
+        AovView::Beauty,
+        AovView::Depth,
+        AovView::Normal,
+        AovView::Albedo,
+        AovView::Position,
+        AovView::Direct,
+        AovView::Indirect,
+        AovView::ObjectId,
+        AovView::MaterialId,
+        AovView::Convergence,
+    ];
+
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|view| *view == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AovView::Beauty => "beauty",
+            AovView::Depth => "depth",
+            AovView::Normal => "normal",
+            AovView::Albedo => "albedo",
+            AovView::Position => "position",
+            AovView::Direct => "direct",
+            AovView::Indirect => "indirect",
+            AovView::ObjectId => "object id",
+            AovView::MaterialId => "material id",
+            AovView::Convergence => "convergence",
+        }
+    }
+
+    //whether the view reads one of the optional aov buffers rather than the output or gbuffer
+    pub fn needs_aovs(self) -> bool {
+        !matches!(self, AovView::Beauty | AovView::Normal | AovView::Albedo | AovView::Convergence)
+    }
+
+    //the buffer holding the view, and which vec4 of each texel to read when a texel spans several
+    pub fn source(self, compute_state: &ComputeState) -> (&wgpu::Buffer, u32, u32) {
+        match self {
+            AovView::Beauty => (&compute_state.output_buffer, 1, 0),
+            AovView::Normal => (&compute_state.gbuffer, 2, 0),
+            AovView::Albedo => (&compute_state.gbuffer, 2, 1),
+            AovView::Depth | AovView::Position => (&compute_state.aovs.position, 1, 0),
+            AovView::Direct | AovView::ObjectId => (&compute_state.aovs.direct, 1, 0),
+            AovView::Indirect | AovView::MaterialId => (&compute_state.aovs.indirect, 1, 0),
+            //one vec4 of header, then the error half of each texel
+            AovView::Convergence => (&compute_state.sampler.stats_buffer, 2, 2),
+        }
+    }
+}
+
+//writes the beauty pass plus every aov into one exr, using `layer.channel` names so compositors group them
+//the aov buffers have to be enabled and rendered into before this is called
+pub fn save_exr(device: &wgpu::Device, queue: &wgpu::Queue, compute_state: &ComputeState, path: &Path) -> exr::error::UnitResult {
+    let beauty = read_buffer(device, queue, &compute_state.output_buffer);
+    let gbuffer = read_buffer(device, queue, &compute_state.gbuffer);
+    let position = read_buffer(device, queue, &compute_state.aovs.position);
+    let direct = read_buffer(device, queue, &compute_state.aovs.direct);
+    let indirect = read_buffer(device, queue, &compute_state.aovs.indirect);
+
+    //the gpu buffers start at the bottom row, exr starts at the top
+    let channel = |name: &str, texels: &[[f32; 4]], stride: usize, offset: usize, component: usize| {
+        let mut samples = Vec::with_capacity((RESOLUTION_X * RESOLUTION_Y) as usize);
+        for y in (0..RESOLUTION_Y as usize).rev() {
+            for x in 0..RESOLUTION_X as usize {
+                samples.push(texels[(y * RESOLUTION_X as usize + x) * stride + offset][component]);
+            }
+        }
+        AnyChannel::new(name, FlatSamples::F32(samples))
+    };
+
+    let channels = vec![
+        channel("R", &beauty, 1, 0, 0),
+        channel("G", &beauty, 1, 0, 1),
+        channel("B", &beauty, 1, 0, 2),
+        channel("A", &beauty, 1, 0, 3),
+        channel("normal.X", &gbuffer, 2, 0, 0),
+        channel("normal.Y", &gbuffer, 2, 0, 1),
+        channel("normal.Z", &gbuffer, 2, 0, 2),
+        channel("albedo.R", &gbuffer, 2, 1, 0),
+        channel("albedo.G", &gbuffer, 2, 1, 1),
+        channel("albedo.B", &gbuffer, 2, 1, 2),
+        channel("position.X", &position, 1, 0, 0),
+        channel("position.Y", &position, 1, 0, 1),
+        channel("position.Z", &position, 1, 0, 2),
+        channel("depth.Z", &position, 1, 0, 3),
+        channel("direct.R", &direct, 1, 0, 0),
+        channel("direct.G", &direct, 1, 0, 1),
+        channel("direct.B", &direct, 1, 0, 2),
+        channel("object_id.ID", &direct, 1, 0, 3),
+        channel("indirect.R", &indirect, 1, 0, 0),
+        channel("indirect.G", &indirect, 1, 0, 1),
+        channel("indirect.B", &indirect, 1, 0, 2),
+        channel("material_id.ID", &indirect, 1, 0, 3),
+    ];
+
+    let layer = Layer::new(
+        (RESOLUTION_X as usize, RESOLUTION_Y as usize),
+        LayerAttributes::named("render"),
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(SmallVec::from_vec(channels)),
+    );
+    Image::from_layer(layer).write().to_file(path)
+}
@@ -0,0 +1,52 @@
+use std::time::{Duration, Instant};
+
+use winit::dpi::PhysicalSize;
+
+use crate::gpu::raytracer::compute_pipeline::{ComputeState, RESOLUTION_X, RESOLUTION_Y};
+use crate::scene::scene::Scene;
+
+//rendered and thrown away first, so pipeline compilation and the first buffer uploads don't count
+const WARMUP_FRAMES: u32 = 2;
+
+//times the path tracing pass on its own over the full output buffer, without a window, the display pass or the denoiser
+//every frame is waited for before the next is submitted, so the times are the gpu's rather than how fast commands queue up
+pub fn run(device: &wgpu::Device, queue: &wgpu::Queue, scene: &Scene, frames: u32, samples_per_frame: u32) {
+    let mut compute_state = pollster::block_on(ComputeState::new(device, queue, &PhysicalSize::new(RESOLUTION_X, RESOLUTION_Y), scene));
+    //the same rays every run, so two builds can be compared
+    compute_state.fixed_seed = Some(0);
+    compute_state.update(queue, scene);
+    compute_state.sampler.count_rays = true;
+    compute_state.sampler.settings.samples_per_frame = samples_per_frame;
+
+    println!("Benchmarking {frames} frames at {RESOLUTION_X}x{RESOLUTION_Y}, {samples_per_frame} samples per pixel");
+    let mut frame_times = Vec::with_capacity(frames as usize);
+    let mut rays = 0;
+    for frame in 0..WARMUP_FRAMES + frames {
+        compute_state.sampler.begin_frame(queue, &compute_state.camera_uniform);
+        let started = Instant::now();
+        compute_state.dispatch_pixels(device, queue, None);
+        device.poll(wgpu::Maintain::Wait);
+        let elapsed = started.elapsed();
+        let counters = compute_state.sampler.read_counters(device, queue);
+        if frame >= WARMUP_FRAMES {
+            frame_times.push(elapsed);
+            rays += counters.rays();
+        }
+    }
+
+    let total: Duration = frame_times.iter().sum();
+    let min = frame_times.iter().min().unwrap();
+    let max = frame_times.iter().max().unwrap();
+    let samples = RESOLUTION_X as f64 * RESOLUTION_Y as f64 * samples_per_frame as f64 * frames as f64;
+    println!(
+        "Frame time: mean {:.2} ms, min {:.2} ms, max {:.2} ms",
+        total.as_secs_f64() * 1000.0 / frames as f64,
+        min.as_secs_f64() * 1000.0,
+        max.as_secs_f64() * 1000.0
+    );
+    println!(
+        "{:.1} M samples/s, {:.1} M rays/s",
+        samples / total.as_secs_f64() / 1e6,
+        rays as f64 / total.as_secs_f64() / 1e6
+    );
+}
@@ -1,27 +1,99 @@
+use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
 use wgpu::util::DeviceExt;
 use winit::dpi::PhysicalSize;
 use image::GenericImageView;
 
 
-const RESOLUTION_X: u32 = 2400;//MUST BE DIVISIBLE BY 8
-const RESOLUTION_Y: u32 = 1600;
+pub const RESOLUTION_X: u32 = 2400;
+pub const RESOLUTION_Y: u32 = 1600;
+//bounces a path may take before it is cut off, handed to the shaders as a define
+pub const MAX_BOUNCES: u32 = 8;
+//edge length of the compute shader's workgroups in pixels, must match @workgroup_size in compute_shader.wgsl
+const WORKGROUP_SIZE: u32 = 8;
 const PIXEL_SIZE: u64 = 16; // 16 bytes per pixel for vec3 format
+pub const GBUFFER_TEXEL_SIZE: u64 = 32; // normal + depth, albedo
 #[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct Sphere {
-    center: [f32; 3],  
-    radius: f32,      
-    material: materials::Material,
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable, serde::Serialize, serde::Deserialize)]
+pub struct Sphere {
+    pub center: [f32; 3],  
+    pub radius: f32,      
+    pub material: materials::Material,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable, serde::Serialize, serde::Deserialize)]
+pub struct Star { //light struct
+    pub color: [f32; 3],
+    pub intensity: f32,  
+    pub position: [f32; 3],
+    pub radius: f32,
 }
 
+//how many entries of the sphere and light buffers are in use, the buffers themselves may be larger
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct Star { //light struct
-    color: [f32; 3],
-    intensity: f32,  
-    position: [f32; 3],
-    radius: f32,
+struct SceneInfo {
+    sphere_count: u32,
+    light_count: u32,
+    aovs_enabled: u32,
+    _padding: u32,
+}
+
+impl SceneInfo {
+    fn new(scene: &Scene, aovs_enabled: bool) -> Self {
+        Self {
+            sphere_count: scene.spheres().len() as u32,
+            light_count: scene.lights().len() as u32,
+            aovs_enabled: aovs_enabled as u32,
+            _padding: 0,
+        }
+    }
+}
+
+//extra passes written on the primary hit for compositing, see the bindings in compute_shader.wgsl
+//allocated at full size only once something asks for them
+pub struct AovBuffers {
+    pub position: wgpu::Buffer,
+    pub direct: wgpu::Buffer,
+    pub indirect: wgpu::Buffer,
+}
+
+impl AovBuffers {
+    fn new(device: &wgpu::Device, enabled: bool) -> Self {
+        let size = if enabled { RESOLUTION_X as u64 * RESOLUTION_Y as u64 * PIXEL_SIZE } else { PIXEL_SIZE };
+        let create = |label: &str| device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        Self {
+            position: create("AOV Position Buffer"),
+            direct: create("AOV Direct Buffer"),
+            indirect: create("AOV Indirect Buffer"),
+        }
+    }
+}
+
+//the part of the image a dispatch renders, in pixels, see TileInfo in compute_shader.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TileInfo {
+    pub origin: [u32; 2],
+    pub image_size: [u32; 2],
+    //pixels in the tile, and so in the output buffer's rows
+    pub size: [u32; 2],
+    _padding: [u32; 2],
+}
+
+impl TileInfo {
+    //the interactive view, one dispatch over the whole output buffer
+    pub const FULL: TileInfo = TileInfo::new([0, 0], [RESOLUTION_X, RESOLUTION_Y], [RESOLUTION_X, RESOLUTION_Y]);
+
+    pub const fn new(origin: [u32; 2], size: [u32; 2], image_size: [u32; 2]) -> Self {
+        Self { origin, image_size, size, _padding: [0; 2] }
+    }
 }
 
 #[repr(C)]
@@ -32,9 +104,9 @@ struct EnvDimensions {
 }
 
 use bytemuck::{Pod, Zeroable};
-use glam::{vec3, Vec3};
+use glam::Vec3;
 
-use crate::{controls::cameracontroller::{CameraController}, gpu::{raytracer::materials, wgpu_init::Init}, scene::{self, scene::Scene}};
+use crate::{gpu::{preprocessor::Shader, raytracer::{materials, sampling::AdaptiveSampler, shaders}, wgpu_init::Init}, scene::scene::Scene};
 
 #[repr(C)]
 #[derive(Copy, Clone, Pod, Zeroable)]
@@ -48,7 +120,7 @@ pub struct CameraUniform {
 }
 
 impl CameraUniform {
-    fn new(position: Vec3, forward: Vec3, up: Vec3, fov_y: f32, aspect_ratio: f32) -> Self {
+    pub fn new(position: Vec3, forward: Vec3, up: Vec3, fov_y: f32, aspect_ratio: f32) -> Self {
         Self {
             position: position.to_array(),
             up: up.normalize().to_array(),
@@ -57,9 +129,6 @@ impl CameraUniform {
             fov_y,
             _padding2:  0.0,
         }
-    }
-    fn update(){
-
     }
     fn resize(&mut self, height: f32, width: f32){
         self.aspect_ratio = height/width;
@@ -69,24 +138,41 @@ impl CameraUniform {
 pub struct ComputeState {
     pub camera_uniform: CameraUniform,
     pub compute_pipeline: wgpu::ComputePipeline,
+    pipeline_layout: wgpu::PipelineLayout,
     pub object_bind_group: wgpu::BindGroup,
     pub output_buffer_bind_group: wgpu::BindGroup,
     pub output_buffer_bind_group_layout: wgpu::BindGroupLayout,
     pub camera_bind_group: wgpu::BindGroup,
     pub output_buffer: wgpu::Buffer,            
+    //normal, depth and albedo of each pixel's primary hit, read by the denoiser
+    pub gbuffer: wgpu::Buffer,
+    pub aovs: AovBuffers,
+    aovs_enabled: bool,
+    //per pixel noise estimates, decides which pixels still need samples
+    pub sampler: AdaptiveSampler,
     pub camera_buffer: wgpu::Buffer,
+    pub tile_buffer: wgpu::Buffer,
     pub env_bind_group: wgpu::BindGroup,
+    pub env_bind_group_layout: wgpu::BindGroupLayout,
     pub rand_buffer: wgpu::Buffer,
+    pub object_bind_group_layout: wgpu::BindGroupLayout,
+    pub sphere_buffer: wgpu::Buffer,
+    pub light_buffer: wgpu::Buffer,
+    pub scene_info_buffer: wgpu::Buffer,
+    //number of elements the sphere and light buffers can hold before they have to be reallocated
+    sphere_capacity: usize,
+    light_capacity: usize,
+    //when set, used as the rng seed instead of the clock so frames can be reproduced
+    pub fixed_seed: Option<u32>,
+    //what the tile buffer holds, the dispatch is sized from it
+    tile: TileInfo,
 }
 
 impl ComputeState {
-    pub async fn new(device: &wgpu::Device, queue: &wgpu::Queue, size: &PhysicalSize<u32>) -> Self {
-        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Compute Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("compute_shader.wgsl").into()), 
-        });
+    pub async fn new(device: &wgpu::Device, _queue: &wgpu::Queue, size: &PhysicalSize<u32>, scene: &Scene) -> Self {
+        let shader_module = shaders::load("compute_shader.wgsl").create_module(device, "Compute Shader");
 
-        let buffer_size = (RESOLUTION_X as u64 * RESOLUTION_Y as u64 * PIXEL_SIZE) as u64;
+        let buffer_size = RESOLUTION_X as u64 * RESOLUTION_Y as u64 * PIXEL_SIZE;
 
         let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Output Buffer"),
@@ -95,6 +181,15 @@ impl ComputeState {
             mapped_at_creation: false,
         });
 
+        let gbuffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GBuffer"),
+            size: RESOLUTION_X as u64 * RESOLUTION_Y as u64 * GBUFFER_TEXEL_SIZE,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let aovs = AovBuffers::new(device, false);
+        let sampler = AdaptiveSampler::new(device);
+
         let output_buffer_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Buffer Bind Group Layout"),
             entries: &[
@@ -108,42 +203,15 @@ impl ComputeState {
                     },
                     count: None,
                 },
+                storage_layout_entry(1),
+                storage_layout_entry(2),
+                storage_layout_entry(3),
+                storage_layout_entry(4),
+                storage_layout_entry(5),
             ],
         });
 
-        let output_buffer_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Buffer Bind Group"),
-            layout: &output_buffer_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &output_buffer,
-                        offset: 0,
-                        size: None,
-                    }),
-                },
-            ],
-        });
-
-        let (env_pixels, env_width, env_height) = load_image_as_rgba(r"src\assets\dock_texture.jpg");
-
-        let env_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Environment Pixel Buffer"),
-            contents: &env_pixels, 
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-        });
-
-        let env_dimensions_data = EnvDimensions {
-            width: env_width,
-            height: env_height,
-        };
-
-        let env_dimensions = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Environment Dimensions Buffer"),
-            contents: bytemuck::cast_slice(&[env_dimensions_data]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+        let output_buffer_bind_group = create_output_bind_group(device, &output_buffer_bind_group_layout, &output_buffer, &gbuffer, &aovs, &sampler.stats_buffer);
 
         let env_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             entries: &[
@@ -171,19 +239,9 @@ impl ComputeState {
             label: Some("Environment Bind Group Layout"),
         });
 
-        let env_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &env_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: env_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: env_dimensions.as_entire_binding(),
-                },
-            ],
-            label: Some("Environment Bind Group"),
+        let env_bind_group = create_env_bind_group(device, &env_bind_group_layout, scene.environment_path()).unwrap_or_else(|e| {
+            eprintln!("Failed to load environment map {}: {e}", scene.environment_path().display());
+            create_env_bind_group_from_pixels(device, &env_bind_group_layout, &[0, 0, 0, 255], 1, 1)
         });
 
         let camera_position = Vec3::from_array([0.0,0.0,-80.0]);
@@ -191,7 +249,7 @@ impl ComputeState {
         let camera_forward = (target-camera_position).normalize();
         let up = Vec3::Y;
         let fov_y = 1.05;
-        let aspect_ratio = (size.height as f32/size.width as f32) as f32;
+        let aspect_ratio = size.height as f32/size.width as f32;
 
 
         let camera_uniform = CameraUniform::new(camera_position, camera_forward,up,fov_y, aspect_ratio);
@@ -203,26 +261,50 @@ impl ComputeState {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
+        let tile_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tile Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[TileInfo::FULL]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
         let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                count: None,
-            }],
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
             label: Some("Camera Bind Group Layout"),
         });
 
         let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: tile_buffer.as_entire_binding(),
+                },
+            ],
             label: Some("Camera Bind Group"),
         });
 
@@ -237,64 +319,15 @@ impl ComputeState {
 
 
         // Sphere buffer
-        let sphere_1 = Sphere {
-            center: [0.0,0.0,0.0],  
-            radius: 30.0,              
-            material: materials::glass_material(),
-        };
-        let sphere_2 = Sphere {
-            center: [100.0,-0.0,0.0],  
-            radius: 30.0,            
-            material: materials::metal_material(),
-        };
-        let sphere_3 = Sphere {
-            center: [50.0,-0.0,-60.0],  
-            radius: 10.0,            
-            material: materials::colored_glass(),
-        };
-        let sphere_4 = Sphere {
-            center: [50.0,-0.0,-30.0],  
-            radius: 10.0,            
-            material: materials::dark_mirror(),
-        };
-        let sphere_5 = Sphere {
-            center: [50.0,-0.0,0.0],  
-            radius: 10.0,           
-            material: materials::emerald_crystal(),
-        };
-        let sphere_7 = Sphere {
-            center: [50.0, -0.0, 30.0],
-            radius: 10.0,
-            material: materials::polished_gold(),
-        };
-        
-        let sphere_8 = Sphere {
-            center: [50.0, -0.0, 60.0],
-            radius: 10.0,
-            material: materials::pearlescent(),
-        };
-        
-
-
-        let sphere_data = vec![sphere_1,sphere_2,sphere_3,sphere_4,sphere_5,sphere_7,sphere_8];
-        let sphere_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Sphere Storage Buffer"),
-            contents: bytemuck::cast_slice(&sphere_data),
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST 
-        });
-        let light_position = vec3(0.676,0.041,-0.735);
-
-        let light_data = Star {
-            color: [1.0,0.55,0.0],
-            intensity: 1.0,
-            position: (light_position * 2000.0).into(),
-            radius: 0.0,
-        };
-
-        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Light Buffer"),
-            contents: bytemuck::cast_slice(&[light_data]),
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        let sphere_capacity = scene.spheres().len().max(1);
+        let sphere_buffer = create_object_buffer(device, "Sphere Storage Buffer", scene.spheres(), sphere_capacity);
+        let light_capacity = scene.lights().len().max(1);
+        let light_buffer = create_object_buffer(device, "Light Buffer", scene.lights(), light_capacity);
+
+        let scene_info_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Scene Info Buffer"),
+            contents: bytemuck::cast_slice(&[SceneInfo::new(scene, false)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
 
@@ -331,28 +364,31 @@ impl ComputeState {
                     },
                     count: None,
                 },
-            ],
-        });
-
-        let object_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &object_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::Buffer(sphere_buffer.as_entire_buffer_binding()),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Buffer(light_buffer.as_entire_buffer_binding()),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::Buffer(rand_buffer.as_entire_buffer_binding()),
-                }
             ],
-            label: Some("Object Bind Group"),
         });
 
+        let object_bind_group = create_object_bind_group(device, &object_bind_group_layout, &sphere_buffer, &light_buffer, &rand_buffer, &scene_info_buffer, &sampler.params_buffer);
+
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Compute Pipeline Layout"),
@@ -366,39 +402,67 @@ impl ComputeState {
         });
 
 
-        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("Compute Pipeline"),
-            layout: Some(&pipeline_layout),
-            module: &shader_module,
-            entry_point: "main",
-            compilation_options: wgpu::PipelineCompilationOptions::default(),
-            cache: None,
-        });
+        let compute_pipeline = create_compute_pipeline(device, &pipeline_layout, &shader_module);
 
         Self {
             compute_pipeline,
+            pipeline_layout,
             object_bind_group,
             camera_bind_group,
             camera_uniform,
             camera_buffer,
+            tile_buffer,
             env_bind_group,
+            env_bind_group_layout,
             output_buffer_bind_group,
             output_buffer,
+            gbuffer,
+            aovs,
+            aovs_enabled: false,
+            sampler,
             output_buffer_bind_group_layout,
             rand_buffer,
+            object_bind_group_layout,
+            sphere_buffer,
+            light_buffer,
+            scene_info_buffer,
+            sphere_capacity,
+            light_capacity,
+            fixed_seed: None,
+            tile: TileInfo::FULL,
         }
     }
 
 
-    pub fn dispatch(&self, init: &Init) {
-        let mut encoder = init.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+    //swaps in a pipeline built from `shader`, if it fails to build the current one keeps running
+    pub fn set_shader(&mut self, device: &wgpu::Device, shader: &Shader) -> Result<(), String> {
+        self.compute_pipeline = shaders::capture_errors(device, || {
+            create_compute_pipeline(device, &self.pipeline_layout, &shader.create_module(device, "Compute Shader"))
+        })?;
+        self.sampler.reset();
+        Ok(())
+    }
+
+    //returns false when adaptive sampling has converged and the frame was skipped
+    pub fn dispatch(&mut self, init: &Init, timestamp_writes: Option<wgpu::ComputePassTimestampWrites>) -> bool {
+        if !self.sampler.begin_frame(&init.queue, &self.camera_uniform) {
+            return false;
+        }
+        self.dispatch_pixels(&init.device, &init.queue, timestamp_writes);
+        self.sampler.end_frame(&init.device, &init.queue);
+        true
+    }
+
+    //one thread per pixel of the current tile, written to the start of the output buffer
+    pub fn dispatch_pixels(&self, device: &wgpu::Device, queue: &wgpu::Queue, timestamp_writes: Option<wgpu::ComputePassTimestampWrites>) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Compute Encoder"),
         });
 
         {
             let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
                 label: Some("Compute Pass"),
-                timestamp_writes: None,
+                timestamp_writes,
             });
 
             compute_pass.set_pipeline(&self.compute_pipeline);
@@ -407,23 +471,260 @@ impl ComputeState {
             compute_pass.set_bind_group(2, &self.camera_bind_group, &[]);
             compute_pass.set_bind_group(3, &self.env_bind_group, &[]);
 
-
-            compute_pass.dispatch_workgroups(RESOLUTION_X, RESOLUTION_Y, 1);
+            //partial workgroups at the right and top edges skip the threads that fall outside the tile
+            let workgroups_x = self.tile.size[0].div_ceil(WORKGROUP_SIZE);
+            let workgroups_y = self.tile.size[1].div_ceil(WORKGROUP_SIZE);
+            compute_pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
         }
 
-        init.queue.submit(Some(encoder.finish()));
+        queue.submit(Some(encoder.finish()));
+    }
+
+    //points the next dispatches at part of a larger image, `TileInfo::FULL` goes back to the interactive view
+    pub fn set_tile(&mut self, queue: &wgpu::Queue, tile: TileInfo) {
+        self.tile = tile;
+        queue.write_buffer(&self.tile_buffer, 0, bytemuck::cast_slice(&[tile]));
     }
     pub fn resize(&mut self, size: PhysicalSize<u32>, queue: &wgpu::Queue){
         self.camera_uniform.resize(size.width as f32, size.height as f32);
         queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
     }
-    pub fn update(&mut self, queue: &wgpu::Queue, cameracontroller: &mut CameraController, scene: &mut Scene, delta_time: f32){
-        scene.compile_camera(cameracontroller, delta_time, &mut self.camera_uniform );
+    pub fn update(&mut self, queue: &wgpu::Queue, scene: &Scene){
+        scene.compile_camera(&mut self.camera_uniform);
 
         queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
-        queue.write_buffer(&self.rand_buffer, 0, bytemuck::cast_slice(&[random_seed()]));
+        queue.write_buffer(&self.rand_buffer, 0, bytemuck::cast_slice(&[self.fixed_seed.unwrap_or_else(random_seed)]));
+    }
+
+    //swaps in a different environment map, the old one stays if the image can't be loaded
+    pub fn set_environment(&mut self, device: &wgpu::Device, path: &Path) -> image::ImageResult<()> {
+        self.env_bind_group = create_env_bind_group(device, &self.env_bind_group_layout, path)?;
+        self.sampler.reset();
+        Ok(())
+    }
+
+    //uploads whatever the scene changed since the last call
+    //edits within capacity only rewrite the changed range, outgrowing a buffer reallocates it at the next power of two
+    //returns whether anything was uploaded
+    pub fn sync_scene(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, scene: &mut Scene) -> bool {
+        let changes = scene.compile_objects();
+        if changes.is_empty() {
+            return false;
+        }
+
+        let mut rebuild_bind_group = false;
+        if let Some(capacity) = grown_capacity(scene.spheres().len(), self.sphere_capacity) {
+            self.sphere_capacity = capacity;
+            self.sphere_buffer = create_object_buffer(device, "Sphere Storage Buffer", scene.spheres(), self.sphere_capacity);
+            rebuild_bind_group = true;
+        } else if let Some(range) = changes.spheres {
+            write_object_range(queue, &self.sphere_buffer, scene.spheres(), range);
+        }
+
+        if let Some(capacity) = grown_capacity(scene.lights().len(), self.light_capacity) {
+            self.light_capacity = capacity;
+            self.light_buffer = create_object_buffer(device, "Light Buffer", scene.lights(), self.light_capacity);
+            rebuild_bind_group = true;
+        } else if let Some(range) = changes.lights {
+            write_object_range(queue, &self.light_buffer, scene.lights(), range);
+        }
+
+        if rebuild_bind_group {
+            self.object_bind_group = create_object_bind_group(device, &self.object_bind_group_layout, &self.sphere_buffer, &self.light_buffer, &self.rand_buffer, &self.scene_info_buffer, &self.sampler.params_buffer);
+        }
+        if changes.counts_changed {
+            queue.write_buffer(&self.scene_info_buffer, 0, bytemuck::cast_slice(&[SceneInfo::new(scene, self.aovs_enabled)]));
+        }
+        self.sampler.reset();
+        true
+    }
+
+    pub fn aovs_enabled(&self) -> bool {
+        self.aovs_enabled
+    }
+
+    //allocates or frees the aov buffers, they cost another 180MB at full resolution
+    pub fn set_aovs_enabled(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, scene: &Scene, enabled: bool) {
+        if enabled == self.aovs_enabled {
+            return;
+        }
+        self.aovs_enabled = enabled;
+        self.aovs = AovBuffers::new(device, enabled);
+        self.output_buffer_bind_group = create_output_bind_group(device, &self.output_buffer_bind_group_layout, &self.output_buffer, &self.gbuffer, &self.aovs, &self.sampler.stats_buffer);
+        queue.write_buffer(&self.scene_info_buffer, 0, bytemuck::cast_slice(&[SceneInfo::new(scene, enabled)]));
+        //converged pixels would otherwise never write into the new buffers
+        self.sampler.refresh();
+    }
+
+    //copies the output buffer back to the cpu, rows run bottom to top as the shader writes them
+    pub fn read_output(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Vec<[f32; 4]> {
+        read_buffer(device, queue, &self.output_buffer)
+    }
+
+    pub fn save_screenshot(&self, device: &wgpu::Device, queue: &wgpu::Queue, path: &Path) -> image::ImageResult<()> {
+        let pixels = self.read_output(device, queue);
+        let image = image::RgbaImage::from_fn(RESOLUTION_X, RESOLUTION_Y, |x, y| {
+            let pixel = pixels[((RESOLUTION_Y - 1 - y) * RESOLUTION_X + x) as usize];
+            image::Rgba(pixel.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8))
+        });
+        image.save(path)
+    }
+
+}
+
+//copies any storage buffer made of vec4<f32> texels back to the cpu, blocking until the gpu is done
+pub fn read_buffer(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &wgpu::Buffer) -> Vec<[f32; 4]> {
+    read_texels(device, queue, buffer, buffer.size() / PIXEL_SIZE)
+}
+
+//like `read_buffer` but only the first `count` texels, so a small tile doesn't copy the whole buffer
+pub fn read_texels(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &wgpu::Buffer, count: u64) -> Vec<[f32; 4]> {
+    let size = count * PIXEL_SIZE;
+    let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Staging Buffer"),
+        size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    encoder.copy_buffer_to_buffer(buffer, 0, &staging_buffer, 0, size);
+    queue.submit(Some(encoder.finish()));
+
+    let slice = staging_buffer.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |_| {});
+    device.poll(wgpu::Maintain::Wait);
+
+    let pixels = bytemuck::pod_collect_to_vec(&slice.get_mapped_range()[..]);
+    staging_buffer.unmap();
+    pixels
+}
+
+fn create_compute_pipeline(device: &wgpu::Device, layout: &wgpu::PipelineLayout, module: &wgpu::ShaderModule) -> wgpu::ComputePipeline {
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Compute Pipeline"),
+        layout: Some(layout),
+        module,
+        entry_point: "main",
+        compilation_options: wgpu::PipelineCompilationOptions::default(),
+        cache: None,
+    })
+}
+
+fn storage_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: false },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
     }
+}
+
+fn create_output_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    output_buffer: &wgpu::Buffer,
+    gbuffer: &wgpu::Buffer,
+    aovs: &AovBuffers,
+    sample_stats: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Buffer Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: output_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: gbuffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: aovs.position.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: aovs.direct.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: aovs.indirect.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: sample_stats.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+//the capacity to reallocate an object buffer at once `len` elements no longer fit, None while they still do
+fn grown_capacity(len: usize, capacity: usize) -> Option<usize> {
+    (len > capacity).then(|| len.next_power_of_two())
+}
+
+//storage buffer with room for `capacity` elements, the first `data.len()` filled in
+fn create_object_buffer<T: Pod>(device: &wgpu::Device, label: &str, data: &[T], capacity: usize) -> wgpu::Buffer {
+    let element_size = std::mem::size_of::<T>();
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: (capacity.max(1) * element_size) as u64,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: true,
+    });
+    buffer.slice(..).get_mapped_range_mut()[..std::mem::size_of_val(data)].copy_from_slice(bytemuck::cast_slice(data));
+    buffer.unmap();
+    buffer
+}
+
+fn write_object_range<T: Pod>(queue: &wgpu::Queue, buffer: &wgpu::Buffer, data: &[T], range: std::ops::Range<usize>) {
+    let offset = (range.start * std::mem::size_of::<T>()) as u64;
+    queue.write_buffer(buffer, offset, bytemuck::cast_slice(&data[range]));
+}
 
+fn create_object_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    sphere_buffer: &wgpu::Buffer,
+    light_buffer: &wgpu::Buffer,
+    rand_buffer: &wgpu::Buffer,
+    scene_info_buffer: &wgpu::Buffer,
+    sampling_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(sphere_buffer.as_entire_buffer_binding()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Buffer(light_buffer.as_entire_buffer_binding()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Buffer(rand_buffer.as_entire_buffer_binding()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Buffer(scene_info_buffer.as_entire_buffer_binding()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::Buffer(sampling_buffer.as_entire_buffer_binding()),
+            },
+        ],
+        label: Some("Object Bind Group"),
+    })
 }
 
 fn random_seed() -> u32 {
@@ -434,12 +735,61 @@ fn random_seed() -> u32 {
     (since_epoch.as_millis() % u32::MAX as u128) as u32
 }
 
-fn load_image_as_rgba(path: &str) -> (Vec<u8>, u32, u32) {
+fn create_env_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, path: &Path) -> image::ImageResult<wgpu::BindGroup> {
+    let (env_pixels, env_width, env_height) = load_image_as_rgba(path)?;
+    Ok(create_env_bind_group_from_pixels(device, layout, &env_pixels, env_width, env_height))
+}
+
+fn create_env_bind_group_from_pixels(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, pixels: &[u8], width: u32, height: u32) -> wgpu::BindGroup {
+    let env_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Environment Pixel Buffer"),
+        contents: pixels,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    });
+
+    let env_dimensions = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Environment Dimensions Buffer"),
+        contents: bytemuck::cast_slice(&[EnvDimensions { width, height }]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: env_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: env_dimensions.as_entire_binding(),
+            },
+        ],
+        label: Some("Environment Bind Group"),
+    })
+}
 
-    let img = image::open(path).expect("Failed to load image");
+fn load_image_as_rgba(path: &Path) -> image::ImageResult<(Vec<u8>, u32, u32)> {
+
+    let img = image::open(path)?;
 
     let rgba = img.to_rgba8();
     let (width, height) = img.dimensions();
 
-    (rgba.to_vec(), width, height)
-}
\ No newline at end of file
+    Ok((rgba.to_vec(), width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn object_buffers_grow_to_the_next_power_of_two() {
+        assert_eq!(grown_capacity(0, 1), None);
+        assert_eq!(grown_capacity(7, 7), None);
+        assert_eq!(grown_capacity(3, 8), None);
+        assert_eq!(grown_capacity(8, 7), Some(8));
+        assert_eq!(grown_capacity(9, 8), Some(16));
+        assert_eq!(grown_capacity(2, 1), Some(2));
+    }
+}
@@ -1,10 +1,15 @@
-// Define the Sphere struct
-const MAX_RAY_DISTANCE: f32 = 1000.0; // Maximum ray travel distance
-struct Sphere {
-    center: vec3<f32>,
-    radius: f32,
-    material: Material,
-}
+// the path tracer, shared structs and functions live in shaders/
+#include "shaders/common.wgsl"
+#include "shaders/camera.wgsl"
+#include "shaders/intersection.wgsl"
+#include "shaders/sampling.wgsl"
+#include "shaders/environment.wgsl"
+
+// set from compute_pipeline.rs, the default keeps the shader valid on its own
+#ifndef MAX_BOUNCES
+#define MAX_BOUNCES 8u
+#endif
+
 // Define the Light struct
 struct Star {
     color: vec3<f32>,
@@ -12,71 +17,107 @@ struct Star {
     position: vec3<f32>,
     radius: f32,
 };
-struct Camera{
-    position: vec3<f32>,
-    aspect_ratio: f32,
-    up: vec3<f32>,
-    fov_y: f32, 
-    forward: vec3<f32>, 
-    _padding2: f32, 
-};
-struct EnvDimensions{
-    width: u32,
-    height: u32,
+// which part of the image this dispatch covers, the whole image unless it is rendered in tiles
+struct TileInfo{
+    origin: vec2<u32>,
+    image_size: vec2<u32>,
+    size: vec2<u32>, // pixels in this dispatch, rounded up to whole workgroups by the dispatch
+}
+struct SceneInfo{
+    sphere_count: u32,
+    light_count: u32,
+    aovs_enabled: u32,
 }
-struct Material{
-    refractive_index: f32,
-    mirror_matte: f32,
-    absorption: f32,
-    specular: f32,
-    color: vec4<f32>
+// running statistics of every sample a pixel has taken since the last reset, updated with Welford's algorithm
+struct SampleStats{
+    mean: vec4<f32>, // rgb mean, samples taken
+    error: vec4<f32>, // luminance mean, luminance M2, noise relative to the threshold, 1 once converged
+}
+struct SampleBuffer{
+    active_pixels: atomic<u32>, // pixels still sampling after this frame, read back to stop once everything converged
+    rays_low: atomic<u32>, // rays cast this frame, only counted while profiling
+    traced_pixels: atomic<u32>, // pixels that took samples this frame, only counted while profiling
+    rays_high: atomic<u32>, // carries out of rays_low, thousands of samples per pixel at a high resolution pass 2^32 rays
+    texels: array<SampleStats>,
+}
+struct SamplingParams{
+    flags: u32,
+    noise_threshold: f32,
+    min_samples: u32,
+    max_samples: u32,
+    samples_per_pixel: u32, // taken by every pixel that is still sampling, each dispatch
+}
+// what one path brings back, the primary hit fields describe its first bounce
+struct PathSample{
+    color: vec3<f32>,
+    direct: vec3<f32>,
+    rays: u32,
+    primary: GBufferTexel,
+    primary_position: vec4<f32>,
+    object_id: u32, // ids are offset by one so 0 is the background
+    material_id: u32,
 }
 
 // Binding the resources
 @group(0) @binding(0) var<storage, read_write> output_buffer: array<vec4<f32>>;
+@group(0) @binding(1) var<storage, read_write> gbuffer: array<GBufferTexel>;
+// optional passes for compositing, only written while scene_info.aovs_enabled is set
+@group(0) @binding(2) var<storage, read_write> aov_position: array<vec4<f32>>; // world position, linear depth
+@group(0) @binding(3) var<storage, read_write> aov_direct: array<vec4<f32>>; // direct light, object id
+@group(0) @binding(4) var<storage, read_write> aov_indirect: array<vec4<f32>>; // indirect light, material id
+@group(0) @binding(5) var<storage, read_write> sample_stats: SampleBuffer;
 @group(1) @binding(0) var<storage, read> sphere_data: array<Sphere>;
 @group(1) @binding(1) var<storage, read> stars: array<Star>;
 @group(2) @binding(0) var<uniform> camera: Camera;
+@group(2) @binding(1) var<uniform> tile: TileInfo;
 @group(1) @binding(2) var<uniform> rand_seed: u32;
-@group(3) @binding(0) var<storage, read> env_buffer: array<u32>;
-@group(3) @binding(1) var<uniform> env_dimensions: EnvDimensions;
-var<workgroup> shared_accum: array<vec4<f32>, 16>;
- 
-
-
-
-@compute @workgroup_size(4, 4)
-fn main(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(num_workgroups) dispatch_size: vec3<u32>, @builtin(workgroup_id) group_id: vec3<u32>, @builtin(local_invocation_id) local_id: vec3<u32>, @builtin(local_invocation_index) local_index: u32) {
-    let camera_pos: vec3<f32> = camera.position;
-    let max_x= f32(dispatch_size.x/2); 
-    let max_y= f32(dispatch_size.y/2);
-
-    
-    let pixel_x = (f32(group_id.x) - max_x) / max_x; 
-    let pixel_y = (f32(group_id.y) - max_y) / max_y;
-
-
-    let scale = tan(camera.fov_y * 0.5 ); 
-    let aspect_corrected_x = pixel_x * camera.aspect_ratio * scale;
-    let aspect_corrected_y = pixel_y * scale;
+@group(1) @binding(3) var<uniform> scene_info: SceneInfo;
+@group(1) @binding(4) var<uniform> sampling: SamplingParams;
+
+const SAMPLING_ADAPTIVE: u32 = 1u;
+const SAMPLING_RESET: u32 = 2u;
+const SAMPLING_REFRESH: u32 = 4u;
+const SAMPLING_COUNT_RAYS: u32 = 8u;
+const SAMPLING_ACCUMULATE: u32 = 16u;
+// keeps the relative noise of near black pixels from blowing up
+const NOISE_FLOOR: f32 = 0.05;
+
+// diffuse and highlight from one light, before the falloff with each bounce
+fn shade(material: Material, light: Star, hit_point: vec3<f32>, normal: vec3<f32>, refracts: bool) -> vec3<f32> {
+    let light_color = light.color * light.intensity;
+    let light_dir = normalize(light.position - hit_point);
+
+    var diffuse_intensity = 1.0;
+    if !refracts {
+        diffuse_intensity = max(dot(normal, light_dir), 0.5);
+    } else {
+        //ensure some 
+        diffuse_intensity = max(abs(dot(normal, light_dir)), 0.5);
+    }
 
+    let highlight = pow(max(dot(normal, light_dir), 0.0), material.specular * 32.0);
 
-    let right = normalize(cross(camera.forward, camera.up));
-    let up = normalize(cross(right, camera.forward)); 
+    let highlight_contribution = min(material.color.rgb * light_color * highlight, light_color);
+    let diffuse_contribution = min(material.color.rgb * light_color * diffuse_intensity, light_color);
 
-    let pix_ray_dir = normalize((aspect_corrected_x) * right + (aspect_corrected_y) * up + camera.forward);
+    return diffuse_contribution * material.absorption + highlight_contribution * material.specular;
+}
 
-    
+// follows one path from the camera through `pixel`, `global_id` picks its random numbers
+fn trace_path(pixel: vec2<u32>, global_id: vec3<u32>) -> PathSample {
+    let half_size = vec2<f32>(tile.image_size / 2u);
+    let pix_ray_dir = camera_ray(camera, (vec2<f32>(pixel) - half_size) / half_size);
     var ray_dir = conic_distribution(pix_ray_dir,0.001,global_id);
     var ray_color = vec3<f32>(0.0,0.5,0.0);
     var ray_origin = camera.position;
-    let max_bounces = u32(8);
     var is_inside = false;
     var weight = 1.0;
     var ray_accumulated_color = vec3<f32>(0.0,0.0,0.0);
     let epsilon = 0.005;
+    var path = PathSample();
 
-    for(var b = u32(0); b < max_bounces;b++){
+    for(var b = u32(0); b < MAX_BOUNCES;b++){
+        path.rays += 1u;
 
         var absorbed = false;
         var hit_point = vec3<f32>(0.0, 0.0, 0.0);
@@ -84,9 +125,10 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(num_workgr
         var closest_t = 1000000.0;
         var closest_hit = false;
         var closest_sphere = Sphere();
+        var closest_index = 0u;
         var inv_norm = false;
 
-        for (var s = u32(0); s < arrayLength(&sphere_data); s++) {
+        for (var s = u32(0); s < scene_info.sphere_count; s++) {
             let sphere = sphere_data[s];
             inv_norm = false;
             let t = detect_hit(ray_origin, ray_dir, sphere);
@@ -94,6 +136,7 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(num_workgr
                 closest_t = t;
                 closest_hit = true;
                 closest_sphere = sphere;
+                closest_index = s;
                 hit_point = ray_origin + t * ray_dir;
                 normal = normalize(hit_point - sphere.center);
                 if length(ray_origin-hit_point) > length(ray_origin-sphere.center){ 
@@ -108,6 +151,12 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(num_workgr
             if inv_norm{
                 normal = -normal;
             }
+            if b == 0u {
+                path.primary = GBufferTexel(normal, closest_t, closest_material.color);
+                path.primary_position = vec4<f32>(hit_point, dot(hit_point - camera.position, normalize(camera.forward)));
+                path.object_id = closest_index + 1u;
+                path.material_id = material_hash(closest_material);
+            }
             ray_origin = hit_point;
 
             var refracts = false;
@@ -137,42 +186,23 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(num_workgr
                 ray_dir = conic_distribution(reflection_dir,closest_material.mirror_matte,global_id);
             }
 
-            var diffuse_intensity = 1.0;
-            let light = stars[0];
-            let light_dir = normalize(light.position - hit_point);
-
-            if !refracts {
-                diffuse_intensity = max(dot(normal, light_dir), 0.5);
-            } else {
-                //ensure some 
-                diffuse_intensity = max(abs(dot(normal, light_dir)), 0.5);
+            // every light adds its own diffuse and highlight
+            for (var l = u32(0); l < scene_info.light_count; l++) {
+                ray_accumulated_color += shade(closest_material, stars[l], hit_point, normal, refracts) * (1.0 / f32(b + 1));
             }
-
-            let highlight = pow(max(dot(normal, light_dir), 0.0), closest_material.specular * 32.0);
-
-            let r_highlight = min(closest_material.color.r * light.color.r * highlight, light.color.r);
-            let g_highlight = min(closest_material.color.g * light.color.g * highlight, light.color.g);
-            let b_highlight = min(closest_material.color.b * light.color.b * highlight, light.color.b);
-            
-            let r_contribution = min(closest_material.color.r * light.color.r * diffuse_intensity, light.color.r);
-            let g_contribution = min(closest_material.color.g * light.color.g * diffuse_intensity, light.color.g);
-            let b_contribution = min(closest_material.color.b * light.color.b * diffuse_intensity, light.color.b);
-
             weight -= closest_material.absorption;
-            ray_accumulated_color += vec3<f32>(
-                r_contribution,
-                g_contribution,
-                b_contribution
-            ) * closest_material.absorption * (1.0 / f32(b + 1));
-
-            ray_accumulated_color += vec3<f32>(
-                r_highlight,
-                g_highlight,
-                b_highlight,
-            ) * closest_material.specular * (1.0 / f32(b + 1));
+
+            if b == 0u {
+                path.direct = ray_accumulated_color;
+            }
 
         } else{
-            ray_accumulated_color += sample_spherical_background(ray_dir) * weight;
+            let background = sample_spherical_background(ray_dir);
+            if b == 0u {
+                path.primary.albedo = vec4<f32>(background, 1.0);
+                path.direct = background;
+            }
+            ray_accumulated_color += background * weight;
             break;
         }
 
@@ -182,194 +212,98 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(num_workgr
 
         ray_origin += epsilon * ray_dir;
     }
-
-
-    shared_accum[local_index] = vec4<f32>(ray_accumulated_color,1.0);
-    workgroupBarrier();
-
-    let index = group_id.y * dispatch_size.x + group_id.x;
-    var accumulated_color = vec4<f32>(0.0,0.0,0.0,0.0);
-
-    for(var i: u32 = 0; i< u32(16);i++){
-        accumulated_color += shared_accum[i];
-    }
-    output_buffer[index] = accumulated_color/16.0;
-
+    path.color = ray_accumulated_color;
+    return path;
 }
 
-fn refract_dir(ray_dir: vec3<f32>, refractive_index: f32, normal: vec3<f32>, exit_cond: bool) -> vec3<f32> {
-
-    var eta_ratio = 1.0 / refractive_index;
-    if exit_cond{
-        eta_ratio = refractive_index / 1.0;
+// one thread per pixel, in 8x8 tiles of pixels so neighbouring threads follow similar paths
+// each thread takes all of its pixel's samples for the frame
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    // threads past the edge of the dispatch's last row or column of tiles have no pixel
+    if invocation_id.x >= tile.size.x || invocation_id.y >= tile.size.y {
+        return;
     }
-    let cos_theta = dot(-ray_dir, normal);
-    let sin_theta_sq = 1.0 - cos_theta * cos_theta;
-
-    if eta_ratio * eta_ratio * sin_theta_sq > 1.0 {
-        return vec3<f32>(0.0, 0.0, 0.0);
+    // buffers are indexed within the tile, the ray and random numbers follow the pixel's place in the whole image
+    let index = invocation_id.y * tile.size.x + invocation_id.x;
+    let pixel = tile.origin + invocation_id.xy;
+    let adaptive = (sampling.flags & SAMPLING_ADAPTIVE) != 0u;
+    let reset = (sampling.flags & SAMPLING_RESET) != 0u;
+    var stats = sample_stats.texels[index];
+    if reset {
+        stats = SampleStats();
     }
-
-    //snells law
-    let r_out_perpendicular = eta_ratio * (ray_dir + cos_theta * normal);
-    let r_out_parallel = -sqrt(abs(1.0 - dot(r_out_perpendicular, r_out_perpendicular))) * normal;
-
-    return r_out_perpendicular + r_out_parallel;
-}
-
-
-fn detect_hit(origin: vec3<f32>, ray_dir: vec3<f32>, sphere: Sphere) -> f32 {
-    let oc = origin - sphere.center;
-    let a = dot(ray_dir, ray_dir);
-    let b = 2.0 * dot(oc, ray_dir);
-    let c = dot(oc, oc) - sphere.radius * sphere.radius;
-    let discriminant = b * b - 4.0 * a * c;
-
-    if discriminant > 0.0 {
-        let t = (-b - sqrt(discriminant)) / (2.0 * a);
-        return t;
+    // converged pixels skip their samples, the rest of their workgroup keeps going
+    let converged = adaptive && stats.error.w > 0.0;
+    let traced = !converged || (sampling.flags & SAMPLING_REFRESH) != 0u;
+    var samples = sampling.samples_per_pixel;
+    if !traced {
+        samples = 0u;
     }
-    //no valid hit - flag value of -1 (does not consider hits behind camera)
-    return -1.0;
-}
 
-fn init_color(global_id: vec3<u32>) -> vec3<f32> {
-    let random_r = rand_float(global_id, u32(0));
-    let random_g = rand_float(global_id, u32(1)); 
-    let random_b = rand_float(global_id, u32(2));
-
-    let total_random = random_r + random_g + random_b;
-
-    let lightcolor = vec3<f32>(1.0,1.0,1.0);
-
-    let color = vec3<f32>(
-        (random_r / total_random) * lightcolor.x,
-        (random_g / total_random) * lightcolor.y,
-        (random_b / total_random) * lightcolor.z
-    );
-    
-    return color;
-}
-
-
-fn conic_distribution(a: vec3<f32>, stdev: f32, global_id: vec3<u32>) -> vec3<f32> {
-
-    //given a vector, a, returns a vector that is *close* to a but has a random angle difference according to a distribution
-    //random angles in spherical coordinates
-    let u = rand_float(global_id,u32(0)); 
-    let o = rand_float(global_id,u32(1));
-    let theta = acos(1.0 - u * (1.0 - cos(stdev)));
-    let phi = o * 2.0 * 3.141592653589793;
-
-    let sin_theta = sin(theta);
-    let x = sin_theta * cos(phi);
-    let y = sin_theta * sin(phi);
-    let z = cos(theta);
-
-    var dir = vec3<f32>(x, y, z);
-
-    let up = vec3<f32>(0.0, 0.0, 1.0); 
-    let axis = -normalize(cross(up, a));
-    let angle = acos(dot(up, normalize(a)));
-    let rotation_matrix = rotation_matrix_around_axis(axis, angle);
-
-    // apply rotation to the direction vector
-    dir = (rotation_matrix * vec4<f32>(dir, 0.0)).xyz;
-    return normalize(dir); 
-}
-
-fn rotation_matrix_around_axis(axis: vec3<f32>, angle: f32) -> mat4x4<f32> {
-    let cos_a = cos(angle);
-    let sin_a = sin(angle);
-    let one_minus_cos_a = 1.0 - cos_a;
-
-    let x = axis.x;
-    let y = axis.y;
-    let z = axis.z;
-
-    return mat4x4<f32>(
-        cos_a + x * x * one_minus_cos_a,
-        x * y * one_minus_cos_a - z * sin_a,
-        x * z * one_minus_cos_a + y * sin_a,
-        0.0,
-
-        y * x * one_minus_cos_a + z * sin_a,
-        cos_a + y * y * one_minus_cos_a,
-        y * z * one_minus_cos_a - x * sin_a,
-        0.0,
-
-        z * x * one_minus_cos_a - y * sin_a,
-        z * y * one_minus_cos_a + x * sin_a,
-        cos_a + z * z * one_minus_cos_a,
-        0.0,
-
-        0.0, 0.0, 0.0, 1.0
-    );
-}
-//////////////////
-//RNG
-fn rand(global_id: vec3<u32>, offset: u32) -> u32 {
-    var state = rand_seed ^ (global_id.x * 374761393u) ^ (global_id.y * 668265263u) ^ offset;
-    state = state * 1664525u + 1013904223u;  // LCG parameters for 32-bit values
-    return state;
-}
-fn rand_float(global_id: vec3<u32>, offset: u32) -> f32 {
-    let random_int = rand(global_id, offset);
-    return f32(random_int) / f32(0xFFFFFFFFu);  // normalize to 0-1
-}
-fn rand_normal(global_id: vec3<u32>, offset: u32, stdev: f32, average: f32) -> f32 {
-    let u1 = f32(rand(global_id, offset)) / f32(0xFFFFFFFFu);
-    let u2 = f32(rand(global_id, offset + 1u)) / f32(0xFFFFFFFFu);
-
-    // Box-Muller transform
-    let z0 = sqrt(-2.0 * log(u1)) * cos(2.0 * 3.14159265359 * u2);
-
-    let random_value = z0 * stdev + average;
-
-    return random_value;
-}
-
-fn sample_spherical_background(ray_dir: vec3<f32>) -> vec3<f32> {
-
-    let relative_dir = normalize(ray_dir);
-    let theta = atan2(relative_dir.z, relative_dir.x);  
-    let phi = acos(relative_dir.y);                    
-
-
-    let u = (theta / (2.0 * 3.141592653589793)) + 0.5;
-    let v = phi / 3.141592653589793;
-
-    let x = u * f32(env_dimensions.width - 1);
-    let y = v * f32(env_dimensions.height - 1);
-
-    let x0 = u32(floor(x));
-    let x1 = min(x0 + 1, env_dimensions.width - 1);
-    let y0 = u32(floor(y));
-    let y1 = min(y0 + 1, env_dimensions.height - 1);
+    var accumulated_color = vec3<f32>(0.0);
+    var accumulated_direct = vec3<f32>(0.0);
+    var rays = 0u;
+    var first = PathSample();
+    for (var s = 0u; s < samples; s++) {
+        // every sample of every pixel gets its own random numbers, 16 to a 4x4 block of ids and further blocks along z
+        let global_id = vec3<u32>(pixel * 4u + vec2<u32>(s % 4u, (s / 4u) % 4u), s / 16u);
+        let path = trace_path(pixel, global_id);
+        if s == 0u {
+            first = path;
+        }
+        accumulated_color += path.color;
+        accumulated_direct += path.direct;
+        rays += path.rays;
+
+        if !converged {
+            let lum = luminance(path.color);
+            stats.mean.w += 1.0;
+            stats.mean = vec4<f32>(stats.mean.rgb + (path.color - stats.mean.rgb) / stats.mean.w, stats.mean.w);
+            let delta = lum - stats.error.x;
+            stats.error.x += delta / stats.mean.w;
+            stats.error.y += delta * (lum - stats.error.x);
+        }
+    }
 
-    let tx = x - f32(x0);
-    let ty = y - f32(y0);
+    // one atomic per pixel rather than per sample keeps the counters cheap
+    if traced && (sampling.flags & SAMPLING_COUNT_RAYS) != 0u {
+        let before = atomicAdd(&sample_stats.rays_low, rays);
+        if before + rays < before {
+            atomicAdd(&sample_stats.rays_high, 1u);
+        }
+        atomicAdd(&sample_stats.traced_pixels, 1u);
+    }
 
-    let color00 = unpack_color(env_buffer[y0 * env_dimensions.width + x0]);
-    let color10 = unpack_color(env_buffer[y0 * env_dimensions.width + x1]);
-    let color01 = unpack_color(env_buffer[y1 * env_dimensions.width + x0]);
-    let color11 = unpack_color(env_buffer[y1 * env_dimensions.width + x1]);
+    if !converged {
+        let taken = stats.mean.w;
+        // a single sample says nothing about the variance yet
+        let standard_error = sqrt(stats.error.y / (taken * max(taken - 1.0, 1.0)));
+        stats.error.z = standard_error / max(stats.error.x, NOISE_FLOOR) / sampling.noise_threshold;
+        let done = (u32(taken) >= sampling.min_samples && stats.error.z <= 1.0) || u32(taken) >= sampling.max_samples;
+        stats.error.w = f32(done);
+        if !done {
+            atomicAdd(&sample_stats.active_pixels, 1u);
+        }
+        sample_stats.texels[index] = stats;
+    }
 
-    let color0 = mix(color00, color10, tx);
-    let color1 = mix(color01, color11, tx);
-    let final_color = mix(color0, color1, ty);
+    let sample_count = f32(sampling.samples_per_pixel);
+    // offline tiles show the running mean too, whether or not their pixels may stop early
+    if adaptive || (sampling.flags & SAMPLING_ACCUMULATE) != 0u {
+        output_buffer[index] = vec4<f32>(stats.mean.rgb, 1.0);
+    } else {
+        output_buffer[index] = vec4<f32>(accumulated_color / sample_count, 1.0);
+    }
 
-    return final_color.rgb;
-}
+    if traced {
+        gbuffer[index] = first.primary;
 
-fn unpack_color(packed_color: u32) -> vec4<f32> {
-    let r = f32((packed_color >> 0) & 0xFF) / 255.0;
-    let g = f32((packed_color >> 8) & 0xFF) / 255.0;
-    let b = f32((packed_color >> 16) & 0xFF) / 255.0;
-    let a = f32((packed_color >> 24) & 0xFF) / 255.0;
-    return vec4<f32>(r, g, b, a);
+        if scene_info.aovs_enabled != 0u {
+            let direct = accumulated_direct / sample_count;
+            aov_position[index] = first.primary_position;
+            aov_direct[index] = vec4<f32>(direct, f32(first.object_id));
+            aov_indirect[index] = vec4<f32>(accumulated_color / sample_count - direct, f32(first.material_id));
+        }
+    }
 }
-
-
-
-
@@ -0,0 +1,267 @@
+// Edge-avoiding a-trous wavelet filter, with the temporal accumulation and variance estimate from SVGF
+#include "shaders/common.wgsl"
+#include "shaders/camera.wgsl"
+
+const FLAG_TEMPORAL: u32 = 1u;
+const FLAG_RESET_HISTORY: u32 = 2u;
+const FLAG_FINAL: u32 = 4u;
+const FLAG_CAMERA_MOVED: u32 = 8u;
+const MAX_HISTORY: f32 = 32.0;
+// while moving the history is kept short so lighting catches up quickly with what the camera now sees
+const MAX_HISTORY_MOVING: f32 = 8.0;
+// how many standard deviations of the current neighbourhood reprojected colour may stray before it is clamped
+const CLAMP_GAMMA: f32 = 1.25;
+// relative difference in hit distance beyond which a history sample is treated as a different surface
+const DISOCCLUSION_TOLERANCE: f32 = 0.05;
+
+struct DenoiseParams {
+    width: u32,
+    height: u32,
+    step: u32,
+    flags: u32,
+    sigma_normal: f32,
+    sigma_depth: f32,
+    sigma_luminance: f32,
+    sigma_albedo: f32,
+}
+struct History {
+    color: vec4<f32>, // accumulated colour, distance from the camera to the surface it belongs to
+    moments: vec4<f32>, // mean luminance, mean squared luminance, frames accumulated
+}
+struct CameraPair {
+    current: Camera,
+    previous: Camera,
+}
+
+@group(0) @binding(0) var<uniform> params: DenoiseParams;
+@group(0) @binding(1) var<storage, read> gbuffer: array<GBufferTexel>;
+@group(0) @binding(2) var<storage, read> source: array<vec4<f32>>;
+@group(0) @binding(3) var<storage, read_write> filtered: array<vec4<f32>>;
+@group(0) @binding(4) var<storage, read> history_in: array<History>;
+@group(0) @binding(5) var<storage, read_write> history_out: array<History>;
+@group(0) @binding(6) var<uniform> cameras: CameraPair;
+
+// luminance variance of the 3x3 neighbourhood, used until enough frames have been accumulated
+fn spatial_variance(x: i32, y: i32) -> f32 {
+    var sum = 0.0;
+    var sum_sq = 0.0;
+    var count = 0.0;
+    for (var dy = -1; dy <= 1; dy++) {
+        for (var dx = -1; dx <= 1; dx++) {
+            let qx = x + dx;
+            let qy = y + dy;
+            if qx < 0 || qy < 0 || qx >= i32(params.width) || qy >= i32(params.height) {
+                continue;
+            }
+            let l = luminance(source[u32(qy) * params.width + u32(qx)].rgb);
+            sum += l;
+            sum_sq += l * l;
+            count += 1.0;
+        }
+    }
+    let mean = sum / count;
+    return max(sum_sq / count - mean * mean, 0.0);
+}
+
+fn half_resolution() -> vec2<f32> {
+    return vec2<f32>(f32(params.width / 2u), f32(params.height / 2u));
+}
+
+// the primary ray direction the compute shader uses for a pixel, without the jitter
+fn primary_direction(camera: Camera, pixel: vec2<f32>) -> vec3<f32> {
+    return camera_ray(camera, (pixel - half_resolution()) / half_resolution());
+}
+
+// inverse of primary_direction: where `direction` from the camera lands on screen, in pixels
+fn project_direction(camera: Camera, direction: vec3<f32>) -> vec2<f32> {
+    return camera_ndc(camera, direction) * half_resolution() + half_resolution();
+}
+
+// colour mean and standard deviation of the 3x3 neighbourhood in the new frame
+fn neighbourhood(x: i32, y: i32) -> array<vec3<f32>, 2> {
+    var sum = vec3<f32>(0.0);
+    var sum_sq = vec3<f32>(0.0);
+    var count = 0.0;
+    for (var dy = -1; dy <= 1; dy++) {
+        for (var dx = -1; dx <= 1; dx++) {
+            let qx = x + dx;
+            let qy = y + dy;
+            if qx < 0 || qy < 0 || qx >= i32(params.width) || qy >= i32(params.height) {
+                continue;
+            }
+            let c = source[u32(qy) * params.width + u32(qx)].rgb;
+            sum += c;
+            sum_sq += c * c;
+            count += 1.0;
+        }
+    }
+    let mean = sum / count;
+    return array<vec3<f32>, 2>(mean, sqrt(max(sum_sq / count - mean * mean, vec3<f32>(0.0))));
+}
+
+// bilinear fetch of last frame's history around `pixel`, skipping texels that belonged to a different surface
+// returns a history with zero length when nothing usable was found
+fn fetch_history(pixel: vec2<f32>, expected_distance: f32) -> History {
+    let base = floor(pixel);
+    let f = pixel - base;
+    var result = History(vec4<f32>(0.0), vec4<f32>(0.0));
+    var weight_sum = 0.0;
+
+    for (var tap = 0u; tap < 4u; tap++) {
+        let offset = vec2<f32>(f32(tap & 1u), f32(tap >> 1u));
+        let q = base + offset;
+        if q.x < 0.0 || q.y < 0.0 || q.x >= f32(params.width) || q.y >= f32(params.height) {
+            continue;
+        }
+        let h = history_in[u32(q.y) * params.width + u32(q.x)];
+        if h.moments.z <= 0.0 {
+            continue;
+        }
+        // the background is stored with distance 0 and only matches other background
+        if (expected_distance > 0.0) != (h.color.a > 0.0) {
+            continue;
+        }
+        if expected_distance > 0.0 && abs(h.color.a - expected_distance) > DISOCCLUSION_TOLERANCE * expected_distance {
+            continue;
+        }
+        let bilinear = mix(1.0 - f.x, f.x, offset.x) * mix(1.0 - f.y, f.y, offset.y);
+        result.color += h.color * bilinear;
+        result.moments += h.moments * bilinear;
+        weight_sum += bilinear;
+    }
+
+    if weight_sum < 0.01 {
+        return History(vec4<f32>(0.0), vec4<f32>(0.0));
+    }
+    result.color /= weight_sum;
+    result.moments /= weight_sum;
+    return result;
+}
+
+// reprojects last frame's history onto the new one, blends in the new frame and writes colour plus variance for the wavelet passes
+@compute @workgroup_size(8, 8)
+fn temporal(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if global_id.x >= params.width || global_id.y >= params.height {
+        return;
+    }
+    let index = global_id.y * params.width + global_id.x;
+    let color = source[index].rgb;
+    let lum = luminance(color);
+
+    var accumulated = color;
+    var moments = vec2<f32>(lum, lum * lum);
+    var history_length = 1.0;
+
+    if (params.flags & FLAG_TEMPORAL) != 0u {
+        let depth = gbuffer[index].depth;
+        let direction = primary_direction(cameras.current, vec2<f32>(global_id.xy));
+
+        // the background is infinitely far away so only the camera rotation moves it
+        var previous_pixel = project_direction(cameras.previous, direction);
+        var distance = 0.0;
+        if depth > 0.0 {
+            let world = cameras.current.position + direction * depth;
+            previous_pixel = project_direction(cameras.previous, world - cameras.previous.position);
+            distance = length(world - cameras.previous.position);
+            if dot(world - cameras.previous.position, cameras.previous.forward) <= 0.0 {
+                previous_pixel = vec2<f32>(-1.0);
+            }
+        }
+
+        var previous = History(vec4<f32>(0.0), vec4<f32>(0.0));
+        if (params.flags & FLAG_RESET_HISTORY) == 0u {
+            previous = fetch_history(previous_pixel, distance);
+        }
+
+        let moved = (params.flags & FLAG_CAMERA_MOVED) != 0u;
+        if moved && previous.moments.z > 0.0 {
+            // neighbourhood clamping keeps stale colours from smearing behind moving edges
+            let stats = neighbourhood(i32(global_id.x), i32(global_id.y));
+            let low = stats[0] - CLAMP_GAMMA * stats[1];
+            let high = stats[0] + CLAMP_GAMMA * stats[1];
+            previous.color = vec4<f32>(clamp(previous.color.rgb, low, high), previous.color.a);
+        }
+
+        var max_history = MAX_HISTORY;
+        if moved {
+            max_history = MAX_HISTORY_MOVING;
+        }
+        history_length = min(previous.moments.z + 1.0, max_history);
+        let alpha = 1.0 / history_length;
+        accumulated = mix(previous.color.rgb, color, alpha);
+        moments = mix(previous.moments.xy, moments, alpha);
+
+        // stored relative to the current camera, which is next frame's previous one
+        history_out[index] = History(vec4<f32>(accumulated, depth), vec4<f32>(moments, history_length, 0.0));
+    }
+
+    var variance = max(moments.y - moments.x * moments.x, 0.0);
+    if history_length < 4.0 {
+        variance = spatial_variance(i32(global_id.x), i32(global_id.y));
+    }
+    filtered[index] = vec4<f32>(accumulated, variance);
+}
+
+// one level of the wavelet: a 5x5 B3 spline kernel with holes of `step` pixels, weighted by how similar the features are
+@compute @workgroup_size(8, 8)
+fn atrous(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if global_id.x >= params.width || global_id.y >= params.height {
+        return;
+    }
+    let index = global_id.y * params.width + global_id.x;
+    let center = source[index];
+    let center_features = gbuffer[index];
+
+    var output_alpha = center.a;
+    if (params.flags & FLAG_FINAL) != 0u {
+        output_alpha = 1.0;
+    }
+
+    // the background has no geometry to guide the filter and is already noise free
+    if center_features.depth <= 0.0 {
+        filtered[index] = vec4<f32>(center.rgb, output_alpha);
+        return;
+    }
+
+    var kernel = array<f32, 3>(3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0);
+    let center_lum = luminance(center.rgb);
+    let lum_scale = params.sigma_luminance * sqrt(center.a) + 1e-4;
+    let depth_scale = params.sigma_depth * center_features.depth * f32(params.step) + 1e-4;
+
+    var color_sum = vec3<f32>(0.0);
+    var variance_sum = 0.0;
+    var weight_sum = 0.0;
+
+    for (var dy = -2; dy <= 2; dy++) {
+        for (var dx = -2; dx <= 2; dx++) {
+            let qx = i32(global_id.x) + dx * i32(params.step);
+            let qy = i32(global_id.y) + dy * i32(params.step);
+            if qx < 0 || qy < 0 || qx >= i32(params.width) || qy >= i32(params.height) {
+                continue;
+            }
+            let q = u32(qy) * params.width + u32(qx);
+            let features = gbuffer[q];
+            if features.depth <= 0.0 {
+                continue;
+            }
+            let sample = source[q];
+
+            let w_normal = pow(max(dot(center_features.normal, features.normal), 0.0), params.sigma_normal);
+            let w_depth = exp(-abs(center_features.depth - features.depth) / depth_scale);
+            let w_lum = exp(-abs(center_lum - luminance(sample.rgb)) / lum_scale);
+            let albedo_difference = center_features.albedo.rgb - features.albedo.rgb;
+            let w_albedo = exp(-dot(albedo_difference, albedo_difference) / params.sigma_albedo);
+
+            let w = kernel[abs(dx)] * kernel[abs(dy)] * w_normal * w_depth * w_lum * w_albedo;
+            color_sum += sample.rgb * w;
+            variance_sum += sample.a * w * w;
+            weight_sum += w;
+        }
+    }
+
+    // the centre always matches itself, so weight_sum can't be zero
+    if (params.flags & FLAG_FINAL) == 0u {
+        output_alpha = variance_sum / (weight_sum * weight_sum);
+    }
+    filtered[index] = vec4<f32>(color_sum / weight_sum, output_alpha);
+}
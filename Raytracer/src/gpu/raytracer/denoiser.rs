@@ -0,0 +1,298 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use crate::gpu::preprocessor::Shader;
+use crate::gpu::raytracer::compute_pipeline::{CameraUniform, ComputeState, RESOLUTION_X, RESOLUTION_Y};
+use crate::gpu::raytracer::shaders;
+use crate::gpu::wgpu_init::Init;
+
+pub const MAX_ITERATIONS: u32 = 5;
+const WORKGROUP_SIZE: u32 = 8;
+const HISTORY_TEXEL_SIZE: u64 = 32; // colour + hit distance, luminance moments + frame count
+
+//must match the flags in denoise_shader.wgsl
+const FLAG_TEMPORAL: u32 = 1;
+const FLAG_RESET_HISTORY: u32 = 2;
+const FLAG_FINAL: u32 = 4;
+const FLAG_CAMERA_MOVED: u32 = 8;
+
+//edge stopping strengths, larger sigma_normal is stricter, larger sigma_depth/luminance/albedo are looser
+const SIGMA_NORMAL: f32 = 128.0;
+const SIGMA_DEPTH: f32 = 0.02;
+const SIGMA_LUMINANCE: f32 = 4.0;
+const SIGMA_ALBEDO: f32 = 0.1;
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct DenoiseParams {
+    width: u32,
+    height: u32,
+    step: u32,
+    flags: u32,
+    sigma_normal: f32,
+    sigma_depth: f32,
+    sigma_luminance: f32,
+    sigma_albedo: f32,
+}
+
+impl DenoiseParams {
+    fn new(step: u32, flags: u32) -> Self {
+        Self {
+            width: RESOLUTION_X,
+            height: RESOLUTION_Y,
+            step,
+            flags,
+            sigma_normal: SIGMA_NORMAL,
+            sigma_depth: SIGMA_DEPTH,
+            sigma_luminance: SIGMA_LUMINANCE,
+            sigma_albedo: SIGMA_ALBEDO,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DenoiserSettings {
+    pub enabled: bool,
+    //accumulate frames over time, reprojected while the camera moves, this also gives the filter a better variance estimate
+    pub temporal: bool,
+    //number of wavelet levels, each doubles the filter footprint
+    pub iterations: u32,
+}
+
+impl Default for DenoiserSettings {
+    fn default() -> Self {
+        Self { enabled: true, temporal: true, iterations: 4 }
+    }
+}
+
+//bind groups for one wavelet level, one writing to scratch memory and one writing straight to the output for the last level
+struct AtrousLevel {
+    to_scratch: wgpu::BindGroup,
+    to_output: wgpu::BindGroup,
+}
+
+//edge-avoiding a-trous filter that runs between the path tracing dispatch and the display pass
+//filters the output buffer in place, guided by the gbuffer the compute shader writes on the primary hit
+pub struct Denoiser {
+    pub settings: DenoiserSettings,
+    //kept so edited shaders can be built against the same bind groups
+    temporal_layout: wgpu::PipelineLayout,
+    atrous_layout: wgpu::PipelineLayout,
+    temporal_pipeline: wgpu::ComputePipeline,
+    atrous_pipeline: wgpu::ComputePipeline,
+    temporal_params: wgpu::Buffer,
+    //the current and previous camera, used to find where each pixel was last frame
+    cameras: wgpu::Buffer,
+    //history is ping-ponged, bind group `i` reads history[i] and writes the other one
+    temporal_bind_groups: [wgpu::BindGroup; 2],
+    history_parity: usize,
+    levels: Vec<AtrousLevel>,
+    last_camera: Option<CameraUniform>,
+    reset_history: bool,
+}
+
+impl Denoiser {
+    pub fn new(device: &wgpu::Device, compute_state: &ComputeState) -> Self {
+        use wgpu::BufferBindingType::{Storage, Uniform};
+        let read = Storage { read_only: true };
+        let read_write = Storage { read_only: false };
+        //params, gbuffer, source, filtered, then for the temporal pass history in, history out and cameras
+        let temporal_bind_group_layout = create_bind_group_layout(device, "Denoise Temporal Bind Group Layout", &[Uniform, read, read, read_write, read, read_write, Uniform]);
+        let atrous_bind_group_layout = create_bind_group_layout(device, "Denoise A-trous Bind Group Layout", &[Uniform, read, read, read_write]);
+        let temporal_layout = create_pipeline_layout(device, &temporal_bind_group_layout);
+        let atrous_layout = create_pipeline_layout(device, &atrous_bind_group_layout);
+
+        let shader_module = shaders::load("denoise_shader.wgsl").create_module(device, "Denoise Shader");
+        let temporal_pipeline = create_pipeline(device, &temporal_layout, &shader_module, "temporal");
+        let atrous_pipeline = create_pipeline(device, &atrous_layout, &shader_module, "atrous");
+
+        let pixels = RESOLUTION_X as u64 * RESOLUTION_Y as u64;
+        let create_storage = |label: &str, texel_size: u64| device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: pixels * texel_size,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let scratch = [create_storage("Denoise Scratch A", 16), create_storage("Denoise Scratch B", 16)];
+        let history = [create_storage("Denoise History A", HISTORY_TEXEL_SIZE), create_storage("Denoise History B", HISTORY_TEXEL_SIZE)];
+
+        let temporal_params = create_params(device, DenoiseParams::new(1, 0));
+        let cameras = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Denoise Camera Buffer"),
+            size: 2 * std::mem::size_of::<CameraUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let create_temporal = |read: usize| device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Denoise Temporal Bind Group"),
+            layout: &temporal_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: temporal_params.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: compute_state.gbuffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: compute_state.output_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 3, resource: scratch[0].as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 4, resource: history[read].as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 5, resource: history[1 - read].as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 6, resource: cameras.as_entire_binding() },
+            ],
+        });
+        let temporal_bind_groups = [create_temporal(0), create_temporal(1)];
+
+        let create_level = |params: DenoiseParams, source: &wgpu::Buffer, destination: &wgpu::Buffer| {
+            let params = create_params(device, params);
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Denoise A-trous Bind Group"),
+                layout: &atrous_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry { binding: 0, resource: params.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 1, resource: compute_state.gbuffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 2, resource: source.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 3, resource: destination.as_entire_binding() },
+                ],
+            })
+        };
+        //level k reads the buffer level k-1 wrote and doubles the step
+        let levels = (0..MAX_ITERATIONS as usize)
+            .map(|level| {
+                let step = 1 << level;
+                let source = &scratch[level % 2];
+                AtrousLevel {
+                    to_scratch: create_level(DenoiseParams::new(step, 0), source, &scratch[(level + 1) % 2]),
+                    to_output: create_level(DenoiseParams::new(step, FLAG_FINAL), source, &compute_state.output_buffer),
+                }
+            })
+            .collect();
+
+        Self {
+            settings: DenoiserSettings::default(),
+            temporal_layout,
+            atrous_layout,
+            temporal_pipeline,
+            atrous_pipeline,
+            temporal_params,
+            cameras,
+            temporal_bind_groups,
+            history_parity: 0,
+            levels,
+            last_camera: None,
+            reset_history: true,
+        }
+    }
+
+    //swaps in a rebuilt denoise shader, on an error the old pipelines are kept
+    pub fn set_shader(&mut self, device: &wgpu::Device, shader: &Shader) -> Result<(), String> {
+        let (temporal_pipeline, atrous_pipeline) = shaders::capture_errors(device, || {
+            let shader_module = shader.create_module(device, "Denoise Shader");
+            (
+                create_pipeline(device, &self.temporal_layout, &shader_module, "temporal"),
+                create_pipeline(device, &self.atrous_layout, &shader_module, "atrous"),
+            )
+        })?;
+        self.temporal_pipeline = temporal_pipeline;
+        self.atrous_pipeline = atrous_pipeline;
+        self.reset_history = true;
+        Ok(())
+    }
+
+    //throws away the accumulated frames, called when the scene changes under the camera
+    pub fn reset_history(&mut self) {
+        self.reset_history = true;
+    }
+
+    pub fn dispatch(&mut self, init: &Init, camera: &CameraUniform, timestamp_writes: Option<wgpu::ComputePassTimestampWrites>) {
+        if !self.settings.enabled {
+            self.reset_history = true;
+            return;
+        }
+        //with nothing to reproject from the previous camera is the current one
+        let previous = self.last_camera.unwrap_or(*camera);
+        let camera_moved = bytemuck::bytes_of(&previous) != bytemuck::bytes_of(camera);
+        self.last_camera = Some(*camera);
+
+        let mut flags = 0;
+        if self.settings.temporal {
+            flags |= FLAG_TEMPORAL;
+        } else {
+            //the history isn't written while temporal accumulation is off, so it is stale once turned back on
+            self.reset_history = true;
+        }
+        if self.reset_history {
+            flags |= FLAG_RESET_HISTORY;
+        }
+        if camera_moved {
+            flags |= FLAG_CAMERA_MOVED;
+        }
+        self.reset_history = false;
+        init.queue.write_buffer(&self.temporal_params, 0, bytemuck::bytes_of(&DenoiseParams::new(1, flags)));
+        init.queue.write_buffer(&self.cameras, 0, bytemuck::cast_slice(&[*camera, previous]));
+        let temporal_bind_group = &self.temporal_bind_groups[self.history_parity];
+        self.history_parity = 1 - self.history_parity;
+
+        let mut encoder = init.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Denoise Encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Denoise Pass"),
+                timestamp_writes,
+            });
+            let workgroups_x = RESOLUTION_X.div_ceil(WORKGROUP_SIZE);
+            let workgroups_y = RESOLUTION_Y.div_ceil(WORKGROUP_SIZE);
+
+            compute_pass.set_pipeline(&self.temporal_pipeline);
+            compute_pass.set_bind_group(0, temporal_bind_group, &[]);
+            compute_pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
+
+            compute_pass.set_pipeline(&self.atrous_pipeline);
+            let iterations = self.settings.iterations.clamp(1, MAX_ITERATIONS) as usize;
+            for (index, level) in self.levels.iter().take(iterations).enumerate() {
+                let bind_group = if index + 1 == iterations { &level.to_output } else { &level.to_scratch };
+                compute_pass.set_bind_group(0, bind_group, &[]);
+                compute_pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
+            }
+        }
+        init.queue.submit(Some(encoder.finish()));
+    }
+}
+
+//every binding is a buffer only the compute stage sees, numbered in order
+fn create_bind_group_layout(device: &wgpu::Device, label: &str, bindings: &[wgpu::BufferBindingType]) -> wgpu::BindGroupLayout {
+    let entries: Vec<wgpu::BindGroupLayoutEntry> = bindings
+        .iter()
+        .enumerate()
+        .map(|(binding, ty)| wgpu::BindGroupLayoutEntry {
+            binding: binding as u32,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer { ty: *ty, has_dynamic_offset: false, min_binding_size: None },
+            count: None,
+        })
+        .collect();
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor { label: Some(label), entries: &entries })
+}
+
+fn create_pipeline_layout(device: &wgpu::Device, bind_group_layout: &wgpu::BindGroupLayout) -> wgpu::PipelineLayout {
+    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Denoise Pipeline Layout"),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    })
+}
+
+fn create_pipeline(device: &wgpu::Device, layout: &wgpu::PipelineLayout, module: &wgpu::ShaderModule, entry_point: &str) -> wgpu::ComputePipeline {
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some(entry_point),
+        layout: Some(layout),
+        module,
+        entry_point,
+        compilation_options: wgpu::PipelineCompilationOptions::default(),
+        cache: None,
+    })
+}
+
+fn create_params(device: &wgpu::Device, params: DenoiseParams) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Denoise Params Buffer"),
+        contents: bytemuck::bytes_of(&params),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    })
+}
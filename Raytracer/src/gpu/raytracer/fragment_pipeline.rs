@@ -1,7 +1,19 @@
 use wgpu::{util::DeviceExt};
+use crate::gpu::preprocessor::Shader;
+use crate::gpu::raytracer::aov::AovView;
+use crate::gpu::raytracer::shaders;
 use crate::gpu::wgpu_init::Init;
-const RESOLUTION_X: u32 = 2400;//MUST BE DIVISIBLE BY 8
-const RESOLUTION_Y: u32 = 1600;
+use crate::ui::overlay::Overlay;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct DisplayUniform {
+    exposure: f32,
+    //an `AovView` and where to find it in the bound buffer
+    view: u32,
+    stride: u32,
+    offset: u32,
+}
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 struct Vertex {
@@ -32,10 +44,14 @@ impl Vertex {
 
 pub struct RenderState {
     pub pipeline: wgpu::RenderPipeline,
+    pipeline_layout: wgpu::PipelineLayout,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_indices: u32,
     pub fragment_bind_group: wgpu::BindGroup,
+    pub fragment_bind_group_layout: wgpu::BindGroupLayout,
+    pub display_buffer: wgpu::Buffer,
+    display: DisplayUniform,
 }
 
 impl RenderState {
@@ -43,10 +59,7 @@ impl RenderState {
         device: &wgpu::Device,
         output_buffer: &wgpu::Buffer
     ) -> Self {
-        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Shader Module"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("fragment_shader.wgsl").into()),
-        });
+        let shader_module = shaders::load("fragment_shader.wgsl").create_module(device, "Shader Module");
 
         //vertices and indices for the two triangles (forming a quad)
         let vertices = [
@@ -74,34 +87,40 @@ impl RenderState {
         });
 
 
+        let display = DisplayUniform { exposure: 1.0, view: AovView::Beauty as u32, stride: 1, offset: 0 };
+        let display_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Display Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[display]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
         let fragment_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Fragment Bind Group Layout"),
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                count: None,
-            }],
-        });
-
-        let fragment_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Fragment Bind Group"),
-            layout: &fragment_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                    buffer: &output_buffer,
-                    offset: 0,
-                    size: None,
-                }),
-            }],
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
         });
 
-
+        let fragment_bind_group = create_fragment_bind_group(device, &fragment_bind_group_layout, output_buffer, &display_buffer);
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
@@ -109,43 +128,46 @@ impl RenderState {
             push_constant_ranges: &[],
         });
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader_module,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
-                compilation_options: wgpu::PipelineCompilationOptions::default(), 
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader_module,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: wgpu::TextureFormat::Rgba8Unorm,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState::default(),
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
-            cache: None,
-        });
+        let pipeline = create_render_pipeline(device, &pipeline_layout, &shader_module);
 
         Self {
             pipeline,
+            pipeline_layout,
             vertex_buffer,
             index_buffer,
             fragment_bind_group,
+            fragment_bind_group_layout,
+            display_buffer,
+            display,
             num_indices: indices.len() as u32,
         }
     }
 
-    pub fn render(&self, state: &Init) -> Result<(), wgpu::SurfaceError> {
+    //swaps in a pipeline built from `shader`, if it fails to build the current one keeps running
+    pub fn set_shader(&mut self, device: &wgpu::Device, shader: &Shader) -> Result<(), String> {
+        self.pipeline = shaders::capture_errors(device, || {
+            create_render_pipeline(device, &self.pipeline_layout, &shader.create_module(device, "Shader Module"))
+        })?;
+        Ok(())
+    }
+
+    pub fn set_exposure(&mut self, queue: &wgpu::Queue, exposure: f32) {
+        self.display.exposure = exposure;
+        queue.write_buffer(&self.display_buffer, 0, bytemuck::cast_slice(&[self.display]));
+    }
+
+    //points the display pass at another buffer, `stride` and `offset` count vec4s within each pixel's texel
+    pub fn set_view(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, view: AovView, buffer: &wgpu::Buffer, stride: u32, offset: u32) {
+        self.display.view = view as u32;
+        self.display.stride = stride;
+        self.display.offset = offset;
+        queue.write_buffer(&self.display_buffer, 0, bytemuck::cast_slice(&[self.display]));
+        self.fragment_bind_group = create_fragment_bind_group(device, &self.fragment_bind_group_layout, buffer, &self.display_buffer);
+    }
+
+    pub fn render(&self, state: &Init, overlay: Option<&mut Overlay>, timestamp_writes: Option<wgpu::RenderPassTimestampWrites>) -> Result<(), wgpu::SurfaceError> {
         let frame = state.surface.get_current_texture()?;
+        let view = frame.texture.create_view(&wgpu::TextureViewDescriptor::default());
         
         let mut encoder = state.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder"),
@@ -156,7 +178,7 @@ impl RenderState {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &frame.texture.create_view(&wgpu::TextureViewDescriptor::default()), 
+                    view: &view,
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
@@ -164,7 +186,7 @@ impl RenderState {
                     },
                 })],
                 depth_stencil_attachment: None,
-                timestamp_writes: None,
+                timestamp_writes,
                 occlusion_query_set: None,
             });
 
@@ -174,10 +196,59 @@ impl RenderState {
             render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
             render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
         }
+
+        if let Some(overlay) = overlay {
+            overlay.paint(&state.device, &state.queue, &mut encoder, &view, state.size);
+        }
     
         state.queue.submit(Some(encoder.finish()));
         frame.present();
     
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+fn create_render_pipeline(device: &wgpu::Device, layout: &wgpu::PipelineLayout, module: &wgpu::ShaderModule) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: "vs_main",
+            buffers: &[Vertex::desc()],
+            compilation_options: wgpu::PipelineCompilationOptions::default(), 
+        },
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+fn create_fragment_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, color_buffer: &wgpu::Buffer, display_buffer: &wgpu::Buffer) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Fragment Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: color_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: display_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
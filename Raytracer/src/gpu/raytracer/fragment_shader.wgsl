@@ -1,3 +1,4 @@
+#include "shaders/common.wgsl"
 
 struct VertexInput {
     @location(0) position: vec2<f32>, 
@@ -17,18 +18,70 @@ fn vs_main(vertex: VertexInput) -> VertexOutput {
     return out;
 }
 
+struct DisplaySettings {
+    exposure: f32,
+    view: u32, // matches AovView
+    stride: u32,
+    offset: u32,
+}
+
 @group(0) @binding(0) var<storage, read> color_buffer: array<vec4<f32>>;
+@group(0) @binding(1) var<uniform> display: DisplaySettings;
 
 @fragment
 fn fs_main(@location(0) in_uv: vec2<f32>) -> @location(0) vec4<f32> {
 
-    let width = u32(2400);
-    let height = u32(1600);
+    let width = OUTPUT_WIDTH;
+    let height = OUTPUT_HEIGHT;
     
     let x = u32(in_uv.x * f32(width));
     let y = u32(in_uv.y * f32(height));
     
     let index = y * width + x;
 
-    return color_buffer[index];
+    let value = color_buffer[index * display.stride + display.offset];
+    switch display.view {
+        case 1u: { // depth, nearer is brighter and the background is black
+            if value.w <= 0.0 {
+                return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+            }
+            return vec4<f32>(vec3<f32>(exp(-value.w / 300.0)), 1.0);
+        }
+        case 2u: { // normal
+            return vec4<f32>(value.xyz * 0.5 + 0.5, 1.0);
+        }
+        case 3u: { // albedo
+            return vec4<f32>(value.rgb, 1.0);
+        }
+        case 4u: { // position, banded every 100 units
+            return vec4<f32>(fract(value.xyz / 100.0), 1.0);
+        }
+        case 5u, 6u: { // direct and indirect light
+            return vec4<f32>(value.rgb * display.exposure, 1.0);
+        }
+        case 7u, 8u: { // object and material id
+            return vec4<f32>(id_color(u32(value.w)), 1.0);
+        }
+        case 9u: { // convergence, converged pixels in green, the rest from yellow to red as their noise grows past the threshold
+            if value.w > 0.0 {
+                return vec4<f32>(0.0, 0.25 + 0.5 * clamp(value.z, 0.0, 1.0), 0.0, 1.0);
+            }
+            let excess = clamp((value.z - 1.0) / 3.0, 0.0, 1.0);
+            return vec4<f32>(1.0, 1.0 - excess, 0.0, 1.0);
+        }
+        default: {
+            return vec4<f32>(value.rgb * display.exposure, value.a);
+        }
+    }
+}
+
+// a stable, well spread colour for each id, black for 0 (the background)
+fn id_color(id: u32) -> vec3<f32> {
+    if id == 0u {
+        return vec3<f32>(0.0);
+    }
+    var hash = id * 747796405u + 2891336453u;
+    hash = ((hash >> ((hash >> 28u) + 4u)) ^ hash) * 277803737u;
+    hash = (hash >> 22u) ^ hash;
+    return vec3<f32>(f32(hash & 0xFFu), f32((hash >> 8u) & 0xFFu), f32((hash >> 16u) & 0xFFu)) / 255.0;
 }
@@ -1,11 +1,11 @@
 #[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable, serde::Serialize, serde::Deserialize)]
 pub struct Material{
-    refractive_index: f32,
-    mirror_matte: f32,
-    absorption: f32,
-    specular: f32,
-    color: [f32;4],
+    pub refractive_index: f32,
+    pub mirror_matte: f32,
+    pub absorption: f32,
+    pub specular: f32,
+    pub color: [f32;4],
 }
 
 
@@ -1,3 +1,11 @@
+pub mod aov;
+pub mod benchmark;
 pub mod compute_pipeline;
+pub mod denoiser;
 pub mod materials;
-pub mod fragment_pipeline;
\ No newline at end of file
+pub mod sampling;
+pub mod shaders;
+pub mod tiled;
+pub mod fragment_pipeline;
+#[cfg(test)]
+mod shader_bench;
\ No newline at end of file
@@ -0,0 +1,287 @@
+use std::time::Instant;
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use crate::gpu::raytracer::compute_pipeline::{CameraUniform, RESOLUTION_X, RESOLUTION_Y};
+
+//samples each pixel takes per dispatch unless SamplingSettings::samples_per_frame says otherwise
+pub const SAMPLES_PER_FRAME: u32 = 16;
+const STATS_TEXEL_SIZE: u64 = 32; // rgb mean + sample count, luminance mean, M2, error ratio, converged
+const STATS_HEADER_SIZE: u64 = 16; // the FrameCounters, padded to the texel alignment
+
+//must match the flags in compute_shader.wgsl
+const FLAG_ADAPTIVE: u32 = 1;
+const FLAG_RESET: u32 = 2;
+const FLAG_REFRESH: u32 = 4;
+const FLAG_COUNT_RAYS: u32 = 8;
+const FLAG_ACCUMULATE: u32 = 16;
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct SamplingParams {
+    flags: u32,
+    noise_threshold: f32,
+    min_samples: u32,
+    max_samples: u32,
+    samples_per_pixel: u32,
+    _padding: [u32; 3],
+}
+
+//counters the compute shader keeps in the header of the stats buffer, cleared before every dispatch
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+pub struct FrameCounters {
+    pub active_pixels: u32,
+    //only counted while `AdaptiveSampler::count_rays` is set, split in two since a large frame overflows 32 bits
+    rays_low: u32,
+    pub traced_pixels: u32,
+    rays_high: u32,
+}
+
+impl FrameCounters {
+    pub fn rays(&self) -> u64 {
+        (self.rays_high as u64) << 32 | self.rays_low as u64
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SamplingSettings {
+    //keep sampling pixels only until their noise drops below the threshold, and show the running mean
+    pub adaptive: bool,
+    //standard error of a pixel's mean luminance relative to its brightness, 0.01 is 1% noise
+    pub noise_threshold: f32,
+    //pixels stop here even if they never reach the threshold, so fireflies can't keep a render going forever
+    pub max_samples: u32,
+    //samples every pixel that is still sampling takes per dispatch, more makes each frame slower but cuts the dispatch overhead
+    pub samples_per_frame: u32,
+}
+
+impl Default for SamplingSettings {
+    fn default() -> Self {
+        Self { adaptive: false, noise_threshold: 0.01, max_samples: 4096, samples_per_frame: SAMPLES_PER_FRAME }
+    }
+}
+
+//variance estimates need a few frames before they can be trusted
+const MIN_SAMPLES: u32 = 4 * SAMPLES_PER_FRAME;
+
+//per pixel running mean and M2 (Welford) of the samples taken since the camera or scene last changed
+//the compute shader updates the statistics and, when adaptive sampling is on, skips pixels that have converged
+pub struct AdaptiveSampler {
+    pub settings: SamplingSettings,
+    //header with the active pixel counter followed by one texel per pixel, see SampleBuffer in compute_shader.wgsl
+    pub stats_buffer: wgpu::Buffer,
+    pub params_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    //has the shader count rays and traced pixels for the profiler
+    pub count_rays: bool,
+    counters: FrameCounters,
+    applied: SamplingSettings,
+    last_camera: Option<CameraUniform>,
+    reset: bool,
+    refresh: bool,
+    samples: u32,
+    active_pixels: u32,
+    converged: bool,
+    started: Instant,
+}
+
+impl AdaptiveSampler {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let stats_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sample Stats Buffer"),
+            size: STATS_HEADER_SIZE + RESOLUTION_X as u64 * RESOLUTION_Y as u64 * STATS_TEXEL_SIZE,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sampling Params Buffer"),
+            contents: bytemuck::bytes_of(&SamplingParams::zeroed()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Active Pixels Readback Buffer"),
+            size: STATS_HEADER_SIZE,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let settings = SamplingSettings::default();
+        Self {
+            settings,
+            stats_buffer,
+            params_buffer,
+            readback_buffer,
+            count_rays: false,
+            counters: FrameCounters::default(),
+            applied: settings,
+            last_camera: None,
+            reset: true,
+            refresh: false,
+            samples: 0,
+            active_pixels: 0,
+            converged: false,
+            started: Instant::now(),
+        }
+    }
+
+    //throws away the statistics, called when the scene changes under the camera
+    pub fn reset(&mut self) {
+        self.reset = true;
+        self.converged = false;
+    }
+
+    //converged pixels are traced again on the next frame without adding to their statistics
+    //used when something other than the colour, such as the aovs, needs a fresh primary hit
+    pub fn refresh(&mut self) {
+        self.refresh = true;
+    }
+
+    pub fn samples_per_pixel(&self) -> u32 {
+        self.samples
+    }
+
+    //what each dispatch takes, never zero
+    pub fn samples_per_frame(&self) -> u32 {
+        self.settings.samples_per_frame.max(1)
+    }
+
+    pub fn active_pixels(&self) -> u32 {
+        self.active_pixels
+    }
+
+    //what the last interactive frame counted, zero when it wasn't read back
+    pub fn counters(&self) -> FrameCounters {
+        self.counters
+    }
+
+    //adaptive sampling is on and every pixel has stopped, there is nothing left to render until something changes
+    pub fn is_converged(&self) -> bool {
+        self.settings.adaptive && self.converged
+    }
+
+    //called before each dispatch, returns false when the frame can be skipped
+    pub fn begin_frame(&mut self, queue: &wgpu::Queue, camera: &CameraUniform) -> bool {
+        let camera_moved = self.last_camera.is_none_or(|last| bytemuck::bytes_of(&last) != bytemuck::bytes_of(camera));
+        self.last_camera = Some(*camera);
+        self.counters = FrameCounters::zeroed();
+        if camera_moved || self.settings != self.applied {
+            self.applied = self.settings;
+            self.reset();
+        }
+        if self.is_converged() && !self.refresh {
+            return false;
+        }
+
+        let mut flags = 0;
+        if self.settings.adaptive {
+            flags |= FLAG_ADAPTIVE;
+        }
+        if self.reset {
+            flags |= FLAG_RESET;
+            self.samples = 0;
+            self.started = Instant::now();
+        }
+        if self.refresh {
+            flags |= FLAG_REFRESH;
+        }
+        if self.count_rays {
+            flags |= FLAG_COUNT_RAYS;
+        }
+        let params = SamplingParams {
+            flags,
+            noise_threshold: self.settings.noise_threshold,
+            min_samples: MIN_SAMPLES,
+            max_samples: self.settings.max_samples.max(MIN_SAMPLES),
+            samples_per_pixel: self.samples_per_frame(),
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+        queue.write_buffer(&self.stats_buffer, 0, bytemuck::bytes_of(&FrameCounters::zeroed()));
+        if !self.is_converged() {
+            self.samples += self.samples_per_frame();
+        }
+        self.reset = false;
+        self.refresh = false;
+        true
+    }
+
+    //sets up one pass of `samples` over an offline tile instead of an interactive frame, the statistics start over on each tile's first pass
+    //the running mean is always what gets written out, with adaptive sampling on pixels below the noise threshold stop early
+    pub fn begin_tile_pass(&mut self, queue: &wgpu::Queue, first_pass: bool, samples: u32, max_samples: u32) {
+        let mut flags = FLAG_ACCUMULATE;
+        if self.settings.adaptive {
+            flags |= FLAG_ADAPTIVE;
+        }
+        if first_pass {
+            flags |= FLAG_RESET;
+        }
+        let params = SamplingParams {
+            flags,
+            noise_threshold: self.settings.noise_threshold,
+            min_samples: MIN_SAMPLES.min(max_samples),
+            max_samples,
+            samples_per_pixel: samples,
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+        queue.write_buffer(&self.stats_buffer, 0, bytemuck::bytes_of(&FrameCounters::zeroed()));
+        //whatever was accumulated for the interactive view is gone
+        self.reset();
+    }
+
+    //called after the dispatch has been submitted, reads back how many pixels are still sampling
+    //this waits for the gpu, so it only happens while adaptive sampling or ray counting is on
+    pub fn end_frame(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let adaptive = self.settings.adaptive && !self.converged;
+        if !adaptive && !self.count_rays {
+            return;
+        }
+        self.counters = self.read_counters(device, queue);
+        if !adaptive {
+            return;
+        }
+        self.active_pixels = self.counters.active_pixels;
+
+        if self.active_pixels == 0 {
+            self.converged = true;
+            println!(
+                "Converged to {:.2}% noise after {} samples per pixel in {:.1}s",
+                self.settings.noise_threshold * 100.0,
+                self.samples,
+                self.started.elapsed().as_secs_f32()
+            );
+        }
+    }
+
+    //the counters of the last dispatch, blocks until the gpu is done
+    pub fn read_counters(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> FrameCounters {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Active Pixels Readback Encoder"),
+        });
+        encoder.copy_buffer_to_buffer(&self.stats_buffer, 0, &self.readback_buffer, 0, STATS_HEADER_SIZE);
+        queue.submit(Some(encoder.finish()));
+
+        let slice = self.readback_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        device.poll(wgpu::Maintain::Wait);
+        let counters = bytemuck::pod_read_unaligned(&slice.get_mapped_range()[..]);
+        self.readback_buffer.unmap();
+        counters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ray_count_carries_into_the_high_word() {
+        let counters = |rays_low, rays_high| FrameCounters { rays_low, rays_high, ..FrameCounters::default() };
+        assert_eq!(counters(1234, 0).rays(), 1234);
+        assert_eq!(counters(u32::MAX, 0).rays(), u32::MAX as u64);
+        assert_eq!(counters(5, 1).rays(), (1 << 32) + 5);
+        //2400x1600 at 160 samples of 8 bounces each
+        assert_eq!(counters(620_232_704, 1).rays(), 2400 * 1600 * 160 * 8);
+    }
+}
@@ -0,0 +1,318 @@
+//runs single functions of the compute shader and its modules on a headless device so tests can check their results numerically
+//a test supplies `fn bench(input: In) -> Out` in wgsl, which is appended to the preprocessed shader source together with an
+//entry point that calls it once per element of an input buffer, so the functions under test are exactly the ones the renderer runs
+
+use bytemuck::Pod;
+use glam::{vec3, Vec3};
+use wgpu::util::DeviceExt;
+
+use crate::gpu::raytracer::shaders;
+use crate::gpu::wgpu_init::headless_device;
+
+//clear of the shader's own bindings in group 0
+const INPUT_BINDING: u32 = 10;
+const OUTPUT_BINDING: u32 = 11;
+
+struct ShaderBench {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    //globals of the shader that the function under test reads, as (group, binding, buffer)
+    resources: Vec<(u32, u32, wgpu::Buffer)>,
+}
+
+impl ShaderBench {
+    fn new() -> Option<Self> {
+        let (device, queue) = headless_device()?;
+        Some(Self { device, queue, resources: Vec::new() })
+    }
+
+    fn with_uniform(mut self, group: u32, binding: u32, contents: &[u8]) -> Self {
+        let buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bench Uniform Buffer"),
+            contents,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        self.resources.push((group, binding, buffer));
+        self
+    }
+
+    fn with_storage(mut self, group: u32, binding: u32, contents: &[u8]) -> Self {
+        let buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bench Storage Buffer"),
+            contents,
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        self.resources.push((group, binding, buffer));
+        self
+    }
+
+    //calls `bench` once per input, `input_type` and `output_type` are the wgsl names of `I` and `O`
+    fn run<I: Pod, O: Pod>(&self, input_type: &str, output_type: &str, bench: &str, inputs: &[I]) -> Vec<O> {
+        let source = format!(
+            "{}\n\
+             @group(0) @binding({INPUT_BINDING}) var<storage, read> bench_input: array<{input_type}>;\n\
+             @group(0) @binding({OUTPUT_BINDING}) var<storage, read_write> bench_output: array<{output_type}>;\n\
+             {bench}\n\
+             @compute @workgroup_size(1)\n\
+             fn bench_main(@builtin(global_invocation_id) id: vec3<u32>) {{\n\
+                 bench_output[id.x] = bench(bench_input[id.x]);\n\
+             }}\n",
+            shaders::load("compute_shader.wgsl").source
+        );
+
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = self.device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Bench Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        //the layout only holds what bench_main ends up using
+        let pipeline = self.device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Bench Pipeline"),
+            layout: None,
+            module: &module,
+            entry_point: "bench_main",
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        if let Some(error) = pollster::block_on(self.device.pop_error_scope()) {
+            panic!("bench shader failed to compile: {error}");
+        }
+
+        let input_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bench Input Buffer"),
+            contents: bytemuck::cast_slice(inputs),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let output_size = (inputs.len() * std::mem::size_of::<O>()) as u64;
+        let output_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Bench Output Buffer"),
+            size: output_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Bench Readback Buffer"),
+            size: output_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut entries: Vec<(u32, u32, &wgpu::Buffer)> = vec![(0, INPUT_BINDING, &input_buffer), (0, OUTPUT_BINDING, &output_buffer)];
+        entries.extend(self.resources.iter().map(|(group, binding, buffer)| (*group, *binding, buffer)));
+        let groups = entries.iter().map(|(group, _, _)| group + 1).max().unwrap_or(0);
+        let bind_groups: Vec<wgpu::BindGroup> = (0..groups)
+            .map(|group| {
+                let group_entries: Vec<wgpu::BindGroupEntry> = entries
+                    .iter()
+                    .filter(|(g, _, _)| *g == group)
+                    .map(|(_, binding, buffer)| wgpu::BindGroupEntry { binding: *binding, resource: buffer.as_entire_binding() })
+                    .collect();
+                self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Bench Bind Group"),
+                    layout: &pipeline.get_bind_group_layout(group),
+                    entries: &group_entries,
+                })
+            })
+            .collect();
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Bench Encoder") });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("Bench Pass"), timestamp_writes: None });
+            pass.set_pipeline(&pipeline);
+            for (group, bind_group) in bind_groups.iter().enumerate() {
+                pass.set_bind_group(group as u32, bind_group, &[]);
+            }
+            pass.dispatch_workgroups(inputs.len() as u32, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&output_buffer, 0, &readback_buffer, 0, output_size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = readback_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        self.device.poll(wgpu::Maintain::Wait);
+        let outputs = bytemuck::pod_collect_to_vec(&slice.get_mapped_range()[..]);
+        readback_buffer.unmap();
+        outputs
+    }
+}
+
+//a bench on the headless device, or None with a note when there is no adapter
+fn bench() -> Option<ShaderBench> {
+    let bench = ShaderBench::new();
+    if bench.is_none() {
+        println!("No adapter available, skipping the shader bench");
+    }
+    bench
+}
+
+fn vec4(v: Vec3, w: f32) -> [f32; 4] {
+    v.extend(w).to_array()
+}
+
+fn assert_close(actual: f32, expected: f32, tolerance: f32, what: &str) {
+    assert!((actual - expected).abs() <= tolerance, "{what}: expected {expected}, got {actual}");
+}
+
+#[test]
+fn detect_hit_distances() {
+    let Some(bench) = bench() else { return };
+    //origin, direction, sphere centre and radius
+    let cases = [
+        [vec4(vec3(0.0, 0.0, -5.0), 0.0), vec4(Vec3::Z, 0.0), vec4(Vec3::ZERO, 1.0)],
+        //the direction doesn't have to be normalized, t is in units of its length
+        [vec4(vec3(0.0, 0.0, -5.0), 0.0), vec4(2.0 * Vec3::Z, 0.0), vec4(Vec3::ZERO, 1.0)],
+        [vec4(vec3(3.0, 4.0, -10.0), 0.0), vec4(Vec3::Z, 0.0), vec4(vec3(3.0, 4.0, 10.0), 5.0)],
+        [vec4(vec3(0.0, 0.0, -5.0), 0.0), vec4(Vec3::X, 0.0), vec4(Vec3::ZERO, 1.0)],
+        //grazing rays have no discriminant to speak of and count as misses
+        [vec4(vec3(1.0, 0.0, -5.0), 0.0), vec4(Vec3::Z, 0.0), vec4(Vec3::ZERO, 1.0)],
+        //from inside only the hit behind the origin is returned
+        [vec4(Vec3::ZERO, 0.0), vec4(Vec3::Z, 0.0), vec4(Vec3::ZERO, 2.0)],
+    ];
+    let outputs: Vec<f32> = bench.run(
+        "HitCase",
+        "f32",
+        "struct HitCase { origin: vec4<f32>, dir: vec4<f32>, sphere: vec4<f32> }\n\
+         fn bench(input: HitCase) -> f32 {\n\
+             return detect_hit(input.origin.xyz, input.dir.xyz, Sphere(input.sphere.xyz, input.sphere.w, Material()));\n\
+         }",
+        &cases,
+    );
+    let expected = [4.0, 2.0, 15.0, -1.0, -1.0, -2.0];
+    for (i, (actual, expected)) in outputs.into_iter().zip(expected).enumerate() {
+        assert_close(actual, expected, 1e-4, &format!("case {i}"));
+    }
+}
+
+#[test]
+fn refract_dir_follows_snells_law() {
+    let Some(bench) = bench() else { return };
+    let incidence = 45f32.to_radians();
+    let oblique = vec3(incidence.sin(), 0.0, -incidence.cos());
+    //direction with the refractive index, normal with 1 when leaving the material
+    let cases = [
+        [vec4(oblique, 1.5), vec4(Vec3::Z, 0.0)],
+        [vec4(-Vec3::Z, 1.5), vec4(Vec3::Z, 0.0)],
+        [vec4(oblique, 1.5), vec4(Vec3::Z, 1.0)],
+        [vec4(vec3(60f32.to_radians().sin(), 0.0, -60f32.to_radians().cos()), 1.5), vec4(Vec3::Z, 1.0)],
+    ];
+    let outputs: Vec<[f32; 4]> = bench.run(
+        "array<vec4<f32>, 2>",
+        "vec4<f32>",
+        "fn bench(input: array<vec4<f32>, 2>) -> vec4<f32> {\n\
+             return vec4<f32>(refract_dir(input[0].xyz, input[0].w, input[1].xyz, input[1].w > 0.0), 0.0);\n\
+         }",
+        &cases,
+    );
+    let angle = |v: [f32; 4]| v[0].atan2(-v[2]);
+
+    //entering glass bends towards the normal, sin 45 / 1.5
+    assert_close(angle(outputs[0]), (incidence.sin() / 1.5).asin(), 1e-4, "entering");
+    assert_close(Vec3::from_slice(&outputs[0]).length(), 1.0, 1e-4, "entering length");
+    assert_close(angle(outputs[1]), 0.0, 1e-6, "normal incidence");
+    //leaving bends away from it, sin 45 * 1.5 is past 1 so that's total internal reflection
+    assert_eq!(outputs[2], [0.0; 4], "45 degrees leaving glass should reflect");
+    assert_eq!(outputs[3], [0.0; 4], "60 degrees leaving glass should reflect");
+}
+
+#[test]
+fn refract_dir_leaving_below_the_critical_angle() {
+    let Some(bench) = bench() else { return };
+    let incidence = 30f32.to_radians();
+    let cases = [[vec4(vec3(incidence.sin(), 0.0, -incidence.cos()), 1.5), vec4(Vec3::Z, 1.0)]];
+    let outputs: Vec<[f32; 4]> = bench.run(
+        "array<vec4<f32>, 2>",
+        "vec4<f32>",
+        "fn bench(input: array<vec4<f32>, 2>) -> vec4<f32> {\n\
+             return vec4<f32>(refract_dir(input[0].xyz, input[0].w, input[1].xyz, input[1].w > 0.0), 0.0);\n\
+         }",
+        &cases,
+    );
+    assert_close(outputs[0][0].atan2(-outputs[0][2]), (incidence.sin() * 1.5).asin(), 1e-4, "leaving");
+}
+
+#[test]
+fn conic_distribution_stays_in_its_cone() {
+    let Some(bench) = bench() else { return };
+    let bench = bench.with_uniform(1, 2, bytemuck::bytes_of(&[1234u32, 0, 0, 0]));
+    let axes = [vec3(0.3, 0.5, 0.8).normalize(), Vec3::X, vec3(0.0, -1.0, 0.2).normalize()];
+    let spreads = [0.0, 0.001, 0.1, 0.5];
+    //axis with the cone's half angle as bits, the thread id to draw random numbers for
+    let mut cases = Vec::new();
+    for axis in axes {
+        for spread in spreads {
+            for id in 0..16u32 {
+                cases.push([vec4(axis, spread).map(f32::to_bits), [id * 7, id * 13, 0, 0]]);
+            }
+        }
+    }
+    let outputs: Vec<[f32; 4]> = bench.run(
+        "ConeCase",
+        "vec4<f32>",
+        "struct ConeCase { axis: vec4<f32>, id: vec4<u32> }\n\
+         fn bench(input: ConeCase) -> vec4<f32> {\n\
+             return vec4<f32>(conic_distribution(input.axis.xyz, input.axis.w, input.id.xyz), 0.0);\n\
+         }",
+        &cases,
+    );
+    for (case, output) in cases.iter().zip(outputs) {
+        let [x, y, z, spread] = case[0].map(f32::from_bits);
+        let axis = vec3(x, y, z);
+        let dir = Vec3::from_slice(&output);
+        assert_close(dir.length(), 1.0, 1e-4, "length");
+        let angle = dir.dot(axis).clamp(-1.0, 1.0).acos();
+        assert!(angle <= spread + 2e-3, "{dir} is {angle} from {axis}, outside a cone of {spread}");
+    }
+}
+
+#[test]
+fn sample_spherical_background_interpolates_the_map() {
+    let Some(bench) = bench() else { return };
+    //3x3 map, each texel's red and green say which column and row it is
+    let texels: Vec<u32> = (0..3u32)
+        .flat_map(|y| (0..3u32).map(move |x| (x * 100) | ((y * 100) << 8) | (255 << 24)))
+        .collect();
+    let bench = bench
+        .with_storage(3, 0, bytemuck::cast_slice(&texels))
+        .with_uniform(3, 1, bytemuck::cast_slice(&[3u32, 3]));
+    //atan2(0, 0) is undefined in wgsl, so the poles are approached from the +x side
+    let near_top = vec3(1e-3, 1.0, 0.0).normalize();
+    let near_bottom = vec3(1e-3, -1.0, 0.0).normalize();
+    let cases = [
+        near_top,
+        near_bottom,
+        Vec3::X,
+        //the sign of a zero z picks the side of the seam
+        vec3(-1.0, 0.0, 0.0),
+        vec3(-1.0, 0.0, -0.0),
+        Vec3::Z,
+        -Vec3::Z,
+        vec3(1.0, 1.0, 0.0).normalize(),
+        vec3(0.0, 1.0, 1.0).normalize(),
+    ]
+    .map(|dir| vec4(dir, 0.0));
+    let outputs: Vec<[f32; 4]> = bench.run(
+        "vec4<f32>",
+        "vec4<f32>",
+        "fn bench(dir: vec4<f32>) -> vec4<f32> {\n\
+             return vec4<f32>(sample_spherical_background(dir.xyz), 0.0);\n\
+         }",
+        &cases,
+    );
+    let texel = |x: f32, y: f32| [x * 100.0 / 255.0, y * 100.0 / 255.0];
+    //up is the top row, the seam at u = 0 and 1 faces -x
+    let expected = [
+        texel(1.0, 0.0),
+        texel(1.0, 2.0),
+        texel(1.0, 1.0),
+        texel(2.0, 1.0),
+        texel(0.0, 1.0),
+        texel(1.5, 1.0),
+        texel(0.5, 1.0),
+        texel(1.0, 0.5),
+        texel(1.5, 0.5),
+    ];
+    for (i, (output, expected)) in outputs.into_iter().zip(expected).enumerate() {
+        assert_close(output[0], expected[0], 1e-3, &format!("case {i} column"));
+        assert_close(output[1], expected[1], 1e-3, &format!("case {i} row"));
+    }
+}
@@ -0,0 +1,109 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::gpu::preprocessor::{self, Shader};
+use super::compute_pipeline::{MAX_BOUNCES, RESOLUTION_X, RESOLUTION_Y};
+
+//every wgsl file, by its path relative to this folder, baked into the binary so the app runs from anywhere
+const FILES: [(&str, &str); 10] = [
+    ("compute_shader.wgsl", include_str!("compute_shader.wgsl")),
+    ("denoise_shader.wgsl", include_str!("denoise_shader.wgsl")),
+    ("fragment_shader.wgsl", include_str!("fragment_shader.wgsl")),
+    ("shaders/camera.wgsl", include_str!("shaders/camera.wgsl")),
+    ("shaders/common.wgsl", include_str!("shaders/common.wgsl")),
+    ("shaders/environment.wgsl", include_str!("shaders/environment.wgsl")),
+    ("shaders/intersection.wgsl", include_str!("shaders/intersection.wgsl")),
+    ("shaders/materials.wgsl", include_str!("shaders/materials.wgsl")),
+    ("shaders/rng.wgsl", include_str!("shaders/rng.wgsl")),
+    ("shaders/sampling.wgsl", include_str!("shaders/sampling.wgsl")),
+];
+
+pub fn embedded(path: &str) -> Result<String, String> {
+    FILES
+        .iter()
+        .find(|(name, _)| *name == path)
+        .map(|(_, text)| text.to_string())
+        .ok_or_else(|| format!("no shader file named {path}"))
+}
+
+//values from rust that the shaders are built with
+pub fn defines() -> Vec<(&'static str, String)> {
+    vec![
+        ("RESOLUTION_X", format!("{RESOLUTION_X}u")),
+        ("RESOLUTION_Y", format!("{RESOLUTION_Y}u")),
+        ("MAX_BOUNCES", format!("{MAX_BOUNCES}u")),
+    ]
+}
+
+//expands and validates one of the built in shaders, they ship with the binary so an error here is a bug
+pub fn load(entry: &str) -> Shader {
+    let shader = preprocessor::preprocess(entry, &defines(), &embedded)
+        .and_then(|shader| shader.validate().map(|_| shader));
+    match shader {
+        Ok(shader) => shader,
+        Err(e) => panic!("{entry} failed to compile:\n{e}"),
+    }
+}
+
+//where the wgsl lives in the source tree, read directly while watching for changes
+pub fn source_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("src/gpu/raytracer")
+}
+
+//like `load`, but reads the files under `dir` and hands errors back, they are expected while a shader is being edited
+pub fn load_from(dir: &Path, entry: &str) -> Result<Shader, String> {
+    let read = |path: &str| std::fs::read_to_string(dir.join(path)).map_err(|e| format!("failed to read {path}: {e}"));
+    let shader = preprocessor::preprocess(entry, &defines(), &read)?;
+    shader.validate()?;
+    Ok(shader)
+}
+
+//runs `create` with wgpu's validation errors caught, instead of the default handler that panics on them
+pub fn capture_errors<T>(device: &wgpu::Device, create: impl FnOnce() -> T) -> Result<T, String> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let created = create();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(e) => Err(e.to_string()),
+        None => Ok(created),
+    }
+}
+
+//how often the files are checked, often enough to feel immediate without stat'ing them every frame
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+//notices when any of the wgsl files changes on disk by polling their modification times
+pub struct ShaderWatcher {
+    dir: PathBuf,
+    modified: Vec<Option<SystemTime>>,
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    pub fn new(dir: PathBuf) -> Self {
+        let modified = modified_times(&dir);
+        Self { dir, modified, last_poll: Instant::now() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    //true once after each change, editors that save in several steps may report the same edit twice
+    pub fn poll(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+        let modified = modified_times(&self.dir);
+        let changed = modified != self.modified;
+        self.modified = modified;
+        changed
+    }
+}
+
+fn modified_times(dir: &Path) -> Vec<Option<SystemTime>> {
+    FILES
+        .iter()
+        .map(|(path, _)| std::fs::metadata(dir.join(path)).and_then(|m| m.modified()).ok())
+        .collect()
+}
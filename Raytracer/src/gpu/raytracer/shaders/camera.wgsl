@@ -0,0 +1,27 @@
+// the pinhole camera of the raytracer, the denoiser runs it backwards to reproject its history
+
+struct Camera {
+    position: vec3<f32>,
+    aspect_ratio: f32,
+    up: vec3<f32>,
+    fov_y: f32,
+    forward: vec3<f32>,
+    _padding: f32,
+}
+
+// the direction through a point on screen, ndc runs from -1 to 1 with y up
+fn camera_ray(camera: Camera, ndc: vec2<f32>) -> vec3<f32> {
+    let scale = tan(camera.fov_y * 0.5);
+    let right = normalize(cross(camera.forward, camera.up));
+    let up = normalize(cross(right, camera.forward));
+    return normalize(ndc.x * camera.aspect_ratio * scale * right + ndc.y * scale * up + camera.forward);
+}
+
+// inverse of camera_ray: where `direction` from the camera lands on screen
+fn camera_ndc(camera: Camera, direction: vec3<f32>) -> vec2<f32> {
+    let scale = tan(camera.fov_y * 0.5);
+    let right = normalize(cross(camera.forward, camera.up));
+    let up = normalize(cross(right, camera.forward));
+    let z = dot(direction, camera.forward);
+    return vec2<f32>(dot(direction, right) / (z * camera.aspect_ratio * scale), dot(direction, up) / (z * scale));
+}
@@ -0,0 +1,16 @@
+// shared by every pipeline
+
+// size of the output buffer, RESOLUTION_X and RESOLUTION_Y are defined from compute_pipeline.rs
+const OUTPUT_WIDTH: u32 = RESOLUTION_X;
+const OUTPUT_HEIGHT: u32 = RESOLUTION_Y;
+
+// primary hit features that guide the denoiser
+struct GBufferTexel {
+    normal: vec3<f32>,
+    depth: f32, // distance to the primary hit, 0 where the ray escaped to the background
+    albedo: vec4<f32>,
+}
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
@@ -0,0 +1,50 @@
+// the equirectangular environment map that rays escaping the scene see, bound as group 3
+
+struct EnvDimensions{
+    width: u32,
+    height: u32,
+}
+
+@group(3) @binding(0) var<storage, read> env_buffer: array<u32>;
+@group(3) @binding(1) var<uniform> env_dimensions: EnvDimensions;
+
+fn sample_spherical_background(ray_dir: vec3<f32>) -> vec3<f32> {
+
+    let relative_dir = normalize(ray_dir);
+    let theta = atan2(relative_dir.z, relative_dir.x);  
+    let phi = acos(relative_dir.y);                    
+
+
+    let u = (theta / (2.0 * 3.141592653589793)) + 0.5;
+    let v = phi / 3.141592653589793;
+
+    let x = u * f32(env_dimensions.width - 1);
+    let y = v * f32(env_dimensions.height - 1);
+
+    let x0 = u32(floor(x));
+    let x1 = min(x0 + 1, env_dimensions.width - 1);
+    let y0 = u32(floor(y));
+    let y1 = min(y0 + 1, env_dimensions.height - 1);
+
+    let tx = x - f32(x0);
+    let ty = y - f32(y0);
+
+    let color00 = unpack_color(env_buffer[y0 * env_dimensions.width + x0]);
+    let color10 = unpack_color(env_buffer[y0 * env_dimensions.width + x1]);
+    let color01 = unpack_color(env_buffer[y1 * env_dimensions.width + x0]);
+    let color11 = unpack_color(env_buffer[y1 * env_dimensions.width + x1]);
+
+    let color0 = mix(color00, color10, tx);
+    let color1 = mix(color01, color11, tx);
+    let final_color = mix(color0, color1, ty);
+
+    return final_color.rgb;
+}
+
+fn unpack_color(packed_color: u32) -> vec4<f32> {
+    let r = f32((packed_color >> 0) & 0xFF) / 255.0;
+    let g = f32((packed_color >> 8) & 0xFF) / 255.0;
+    let b = f32((packed_color >> 16) & 0xFF) / 255.0;
+    let a = f32((packed_color >> 24) & 0xFF) / 255.0;
+    return vec4<f32>(r, g, b, a);
+}
@@ -0,0 +1,26 @@
+// spheres and where rays meet them
+
+#include "materials.wgsl"
+
+const MAX_RAY_DISTANCE: f32 = 1000.0; // Maximum ray travel distance
+
+struct Sphere {
+    center: vec3<f32>,
+    radius: f32,
+    material: Material,
+}
+
+fn detect_hit(origin: vec3<f32>, ray_dir: vec3<f32>, sphere: Sphere) -> f32 {
+    let oc = origin - sphere.center;
+    let a = dot(ray_dir, ray_dir);
+    let b = 2.0 * dot(oc, ray_dir);
+    let c = dot(oc, oc) - sphere.radius * sphere.radius;
+    let discriminant = b * b - 4.0 * a * c;
+
+    if discriminant > 0.0 {
+        let t = (-b - sqrt(discriminant)) / (2.0 * a);
+        return t;
+    }
+    //no valid hit - flag value of -1 (does not consider hits behind camera)
+    return -1.0;
+}
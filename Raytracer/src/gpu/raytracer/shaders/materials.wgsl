@@ -0,0 +1,48 @@
+// surface description of a sphere and what light does at it
+
+struct Material{
+    refractive_index: f32,
+    mirror_matte: f32,
+    absorption: f32,
+    specular: f32,
+    color: vec4<f32>
+}
+
+// spheres with identical materials share an id, kept below 2^24 so it survives being stored as a float
+fn material_hash(material: Material) -> u32 {
+    var words = array<u32, 8>(
+        bitcast<u32>(material.refractive_index),
+        bitcast<u32>(material.mirror_matte),
+        bitcast<u32>(material.absorption),
+        bitcast<u32>(material.specular),
+        bitcast<u32>(material.color.r),
+        bitcast<u32>(material.color.g),
+        bitcast<u32>(material.color.b),
+        bitcast<u32>(material.color.a),
+    );
+    var hash = 2166136261u;
+    for (var i = 0u; i < 8u; i++) {
+        hash = (hash ^ words[i]) * 16777619u;
+    }
+    return max(hash & 0xFFFFFFu, 1u);
+}
+
+fn refract_dir(ray_dir: vec3<f32>, refractive_index: f32, normal: vec3<f32>, exit_cond: bool) -> vec3<f32> {
+
+    var eta_ratio = 1.0 / refractive_index;
+    if exit_cond{
+        eta_ratio = refractive_index / 1.0;
+    }
+    let cos_theta = dot(-ray_dir, normal);
+    let sin_theta_sq = 1.0 - cos_theta * cos_theta;
+
+    if eta_ratio * eta_ratio * sin_theta_sq > 1.0 {
+        return vec3<f32>(0.0, 0.0, 0.0);
+    }
+
+    //snells law
+    let r_out_perpendicular = eta_ratio * (ray_dir + cos_theta * normal);
+    let r_out_parallel = -sqrt(abs(1.0 - dot(r_out_perpendicular, r_out_perpendicular))) * normal;
+
+    return r_out_perpendicular + r_out_parallel;
+}
@@ -0,0 +1,23 @@
+// hash based random numbers, every call with the same offset gives a thread the same number
+// expects the including shader to declare `rand_seed`, the value that changes from frame to frame
+
+fn rand(global_id: vec3<u32>, offset: u32) -> u32 {
+    var state = rand_seed ^ (global_id.x * 374761393u) ^ (global_id.y * 668265263u) ^ (global_id.z * 2246822519u) ^ offset;
+    state = state * 1664525u + 1013904223u;  // LCG parameters for 32-bit values
+    return state;
+}
+fn rand_float(global_id: vec3<u32>, offset: u32) -> f32 {
+    let random_int = rand(global_id, offset);
+    return f32(random_int) / f32(0xFFFFFFFFu);  // normalize to 0-1
+}
+fn rand_normal(global_id: vec3<u32>, offset: u32, stdev: f32, average: f32) -> f32 {
+    let u1 = f32(rand(global_id, offset)) / f32(0xFFFFFFFFu);
+    let u2 = f32(rand(global_id, offset + 1u)) / f32(0xFFFFFFFFu);
+
+    // Box-Muller transform
+    let z0 = sqrt(-2.0 * log(u1)) * cos(2.0 * 3.14159265359 * u2);
+
+    let random_value = z0 * stdev + average;
+
+    return random_value;
+}
@@ -0,0 +1,76 @@
+// random directions and colours for the raytracer
+
+#include "rng.wgsl"
+
+fn init_color(global_id: vec3<u32>) -> vec3<f32> {
+    let random_r = rand_float(global_id, u32(0));
+    let random_g = rand_float(global_id, u32(1)); 
+    let random_b = rand_float(global_id, u32(2));
+
+    let total_random = random_r + random_g + random_b;
+
+    let lightcolor = vec3<f32>(1.0,1.0,1.0);
+
+    let color = vec3<f32>(
+        (random_r / total_random) * lightcolor.x,
+        (random_g / total_random) * lightcolor.y,
+        (random_b / total_random) * lightcolor.z
+    );
+    
+    return color;
+}
+
+fn conic_distribution(a: vec3<f32>, stdev: f32, global_id: vec3<u32>) -> vec3<f32> {
+
+    //given a vector, a, returns a vector that is *close* to a but has a random angle difference according to a distribution
+    //random angles in spherical coordinates
+    let u = rand_float(global_id,u32(0)); 
+    let o = rand_float(global_id,u32(1));
+    let theta = acos(1.0 - u * (1.0 - cos(stdev)));
+    let phi = o * 2.0 * 3.141592653589793;
+
+    let sin_theta = sin(theta);
+    let x = sin_theta * cos(phi);
+    let y = sin_theta * sin(phi);
+    let z = cos(theta);
+
+    var dir = vec3<f32>(x, y, z);
+
+    let up = vec3<f32>(0.0, 0.0, 1.0); 
+    let axis = -normalize(cross(up, a));
+    let angle = acos(dot(up, normalize(a)));
+    let rotation_matrix = rotation_matrix_around_axis(axis, angle);
+
+    // apply rotation to the direction vector
+    dir = (rotation_matrix * vec4<f32>(dir, 0.0)).xyz;
+    return normalize(dir); 
+}
+
+fn rotation_matrix_around_axis(axis: vec3<f32>, angle: f32) -> mat4x4<f32> {
+    let cos_a = cos(angle);
+    let sin_a = sin(angle);
+    let one_minus_cos_a = 1.0 - cos_a;
+
+    let x = axis.x;
+    let y = axis.y;
+    let z = axis.z;
+
+    return mat4x4<f32>(
+        cos_a + x * x * one_minus_cos_a,
+        x * y * one_minus_cos_a - z * sin_a,
+        x * z * one_minus_cos_a + y * sin_a,
+        0.0,
+
+        y * x * one_minus_cos_a + z * sin_a,
+        cos_a + y * y * one_minus_cos_a,
+        y * z * one_minus_cos_a - x * sin_a,
+        0.0,
+
+        z * x * one_minus_cos_a - y * sin_a,
+        z * y * one_minus_cos_a + x * sin_a,
+        cos_a + z * z * one_minus_cos_a,
+        0.0,
+
+        0.0, 0.0, 0.0, 1.0
+    );
+}
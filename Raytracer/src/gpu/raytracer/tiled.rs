@@ -0,0 +1,106 @@
+use std::time::Instant;
+
+use crate::gpu::raytracer::compute_pipeline::{read_texels, ComputeState, TileInfo, RESOLUTION_Y};
+use crate::scene::scene::Scene;
+
+pub const DEFAULT_TILE_SIZE: u32 = 512;
+pub const DEFAULT_SAMPLES: u32 = 256;
+
+//an offline render larger than the interactive output buffer
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TiledRender {
+    pub width: u32,
+    pub height: u32,
+    //tiles are square, small ones keep each dispatch well under gpu watchdog timeouts
+    pub tile_size: u32,
+    //samples per pixel, taken a frame's worth at a time with whatever is left in the last pass, see SamplingSettings::samples_per_frame
+    //with adaptive sampling on this is the most a pixel takes
+    pub samples: u32,
+}
+
+impl TiledRender {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height, tile_size: DEFAULT_TILE_SIZE, samples: DEFAULT_SAMPLES }
+    }
+
+    //tiles reuse the interactive buffers, so a tile can't hold more pixels than those do
+    fn tile_size(&self) -> u32 {
+        self.tile_size.clamp(1, RESOLUTION_Y)
+    }
+
+    //origin and size of every tile, row by row from the bottom of the image like the shader's rows
+    fn tiles(&self) -> Vec<([u32; 2], [u32; 2])> {
+        let tile_size = self.tile_size();
+        let mut tiles = Vec::new();
+        for y in (0..self.height).step_by(tile_size as usize) {
+            for x in (0..self.width).step_by(tile_size as usize) {
+                tiles.push(([x, y], [tile_size.min(self.width - x), tile_size.min(self.height - y)]));
+            }
+        }
+        tiles
+    }
+}
+
+//renders `scene` from its camera one tile at a time, each tile read back and stitched into the image on the cpu
+//the interactive view's accumulation is thrown away and restarts once this returns
+pub fn render_tiled(device: &wgpu::Device, queue: &wgpu::Queue, compute_state: &mut ComputeState, scene: &Scene, render: &TiledRender) -> image::RgbaImage {
+    let mut image = image::RgbaImage::new(render.width, render.height);
+    let tiles = render.tiles();
+    let samples_per_pass = compute_state.sampler.samples_per_frame();
+    let passes = render.samples.div_ceil(samples_per_pass).max(1);
+    let started = Instant::now();
+
+    let mut camera = compute_state.camera_uniform;
+    scene.compile_camera(&mut camera);
+    camera.aspect_ratio = render.width as f32 / render.height as f32;
+    queue.write_buffer(&compute_state.camera_buffer, 0, bytemuck::cast_slice(&[camera]));
+
+    println!(
+        "Rendering {}x{} in {} tiles of up to {} pixels, {} samples per pixel",
+        render.width,
+        render.height,
+        tiles.len(),
+        render.tile_size(),
+        render.samples
+    );
+    for (tile_index, &(origin, size)) in tiles.iter().enumerate() {
+        compute_state.set_tile(queue, TileInfo::new(origin, size, [render.width, render.height]));
+
+        for pass in 0..passes {
+            let seed = compute_state.fixed_seed.unwrap_or(0).wrapping_add(pass.wrapping_mul(0x9E37_79B9));
+            queue.write_buffer(&compute_state.rand_buffer, 0, bytemuck::cast_slice(&[seed]));
+            let samples = samples_per_pass.min(render.samples - pass * samples_per_pass);
+            compute_state.sampler.begin_tile_pass(queue, pass == 0, samples, render.samples);
+            compute_state.dispatch_pixels(device, queue, None);
+            //waiting here also stops a slow tile from queueing up more work than the driver will tolerate
+            if compute_state.sampler.read_counters(device, queue).active_pixels == 0 {
+                break;
+            }
+        }
+
+        //the tile's rows start at the bottom like the rest of the gpu buffers, the image starts at the top
+        let texels = read_texels(device, queue, &compute_state.output_buffer, size[0] as u64 * size[1] as u64);
+        for y in 0..size[1] {
+            for x in 0..size[0] {
+                let pixel = texels[(y * size[0] + x) as usize];
+                let image_y = render.height - 1 - (origin[1] + y);
+                image.put_pixel(origin[0] + x, image_y, image::Rgba(pixel.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)));
+            }
+        }
+
+        let done = tile_index + 1;
+        println!(
+            "Tile {done}/{} at ({}, {}) done, {:.0}% after {:.1}s",
+            tiles.len(),
+            origin[0],
+            origin[1],
+            done as f32 / tiles.len() as f32 * 100.0,
+            started.elapsed().as_secs_f32()
+        );
+    }
+
+    compute_state.set_tile(queue, TileInfo::FULL);
+    queue.write_buffer(&compute_state.camera_buffer, 0, bytemuck::cast_slice(&[compute_state.camera_uniform]));
+    compute_state.sampler.reset();
+    image
+}
@@ -1,49 +1,116 @@
-use wgpu::{Features, Limits};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use wgpu::{Backends, Features, InstanceFlags, Limits, PowerPreference, PresentMode};
 use winit::window::Window;
 
+use crate::gpu::error::RendererError;
+
+//adapters to try in order, the fastest first and then ones that ask less of the system
+const ADAPTER_PREFERENCES: [(PowerPreference, bool); 3] = [
+    (PowerPreference::HighPerformance, false),
+    (PowerPreference::LowPower, false),
+    (PowerPreference::LowPower, true),
+];
+
+//which adapter to ask for, set from the command line
+#[derive(Copy, Clone, Debug)]
+pub struct AdapterOptions {
+    pub backends: Backends,
+    //tried before the rest of ADAPTER_PREFERENCES when set
+    pub power_preference: Option<PowerPreference>,
+    //only the software adapter, to tell driver bugs apart from bugs in the renderer
+    pub force_fallback_adapter: bool,
+    //wgpu's debug labels and the api's validation layers, slower but they catch misuse early
+    pub validation: bool,
+}
+
+impl Default for AdapterOptions {
+    fn default() -> Self {
+        Self {
+            backends: Backends::all(),
+            power_preference: None,
+            force_fallback_adapter: false,
+            validation: cfg!(debug_assertions),
+        }
+    }
+}
+
+impl AdapterOptions {
+    //the WGPU_VALIDATION and WGPU_DEBUG environment variables still override `validation`
+    pub fn create_instance(&self) -> wgpu::Instance {
+        //some drivers, e.g. MoltenVK, don't fully conform but run the renderer fine
+        let mut flags = InstanceFlags::ALLOW_UNDERLYING_NONCOMPLIANT_ADAPTER;
+        if self.validation {
+            flags |= InstanceFlags::debugging();
+        }
+        wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: self.backends,
+            flags: flags.with_env(),
+            dx12_shader_compiler: wgpu::Dx12Compiler::Fxc,
+            gles_minor_version: wgpu::Gles3MinorVersion::Automatic,
+        })
+    }
+
+    //the requests to make in order, the chosen preference first and the software adapter last
+    fn preferences(&self) -> Vec<(PowerPreference, bool)> {
+        if self.force_fallback_adapter {
+            return vec![(self.power_preference.unwrap_or_default(), true)];
+        }
+        let chosen = self.power_preference.map(|power_preference| (power_preference, false));
+        chosen.into_iter().chain(ADAPTER_PREFERENCES.into_iter().filter(|p| Some(*p) != chosen)).collect()
+    }
+}
+
+//how frames reach the screen, set from the command line
+#[derive(Copy, Clone, Debug)]
+pub struct PresentOptions {
+    //checked against what the surface supports, fifo is used when it can't do this one
+    pub present_mode: PresentMode,
+    //frames queued ahead of the display, more smooths out uneven frame times at the cost of input lag
+    pub frame_latency: u32,
+}
+
+impl Default for PresentOptions {
+    fn default() -> Self {
+        Self { present_mode: PresentMode::Fifo, frame_latency: 2 }
+    }
+}
+
+//what the device reported through its callbacks, which may run on any thread
+#[derive(Default)]
+struct DeviceEvents {
+    lost: Mutex<Option<String>>,
+    out_of_memory: AtomicBool,
+}
+
 pub struct Init<'a> {
+    instance: wgpu::Instance,
+    options: AdapterOptions,
+    present: PresentOptions,
     pub surface: wgpu::Surface<'a>,
+    pub adapter: wgpu::Adapter,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
     pub size: winit::dpi::PhysicalSize<u32>,
+    events: Arc<DeviceEvents>,
 }
 
 impl <'a>Init<'a> {
-    pub async fn new(window: &Window) -> Self {
+    //fails when there is no adapter that can draw to the window, the caller can fall back to the cpu renderer
+    pub async fn new(window: &Window, options: AdapterOptions, present: PresentOptions) -> Result<Self, RendererError> {
         let size = window.inner_size();
 
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor{
-            backends: wgpu::Backends::all(),
-            flags: wgpu::InstanceFlags::all(),
-            dx12_shader_compiler: wgpu::Dx12Compiler::Fxc,
-            gles_minor_version: wgpu::Gles3MinorVersion::Automatic,
-        });
+        let instance = options.create_instance();
 
         //the surface must not outlive the window
-        let surface = unsafe { instance.create_surface_unsafe(wgpu::SurfaceTargetUnsafe::from_window(window).unwrap()).unwrap() };
+        let target = unsafe { wgpu::SurfaceTargetUnsafe::from_window(window) }.map_err(RendererError::WindowHandle)?;
+        let surface = unsafe { instance.create_surface_unsafe(target) }.map_err(RendererError::CreateSurface)?;
 
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    required_features: Features::default(),
-                    required_limits: Limits::downlevel_defaults(),
-                    memory_hints: wgpu::MemoryHints::MemoryUsage,
-                },
-                None, 
-            )
-            .await
-            .unwrap();
+        let (adapter, device, queue) = open_device(&instance, Some(&surface), &options).await?;
+        println!("Rendering on {}", describe_adapter(&adapter.get_info()));
+        let events = watch_device(&device);
 
         let surface_format = wgpu::TextureFormat::Rgba8Unorm;
         let config = wgpu::SurfaceConfiguration {
@@ -51,26 +118,186 @@ impl <'a>Init<'a> {
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
+            present_mode: supported_present_mode(&surface, &adapter, present.present_mode),
             alpha_mode: wgpu::CompositeAlphaMode::Auto,
             view_formats: vec![],
-            desired_maximum_frame_latency: 60,
+            desired_maximum_frame_latency: present.frame_latency,
         };
         surface.configure(&device, &config);
 
-        Self {
+        Ok(Self {
+            instance,
+            options,
+            present,
             surface,
+            adapter,
             device,
             queue,
             config,
             size,
-        }
+            events,
+        })
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         self.size = new_size;
         self.config.width = new_size.width;
         self.config.height = new_size.height;
-        self.surface.configure(&self.device, &self.config);
+        self.configure_surface();
     }
+
+    //sets the surface up again after it was lost or went out of date, e.g. when the window moved to another monitor
+    pub fn configure_surface(&self) {
+        //a minimised window has no area to draw to, it is configured again once it is resized back
+        if self.config.width > 0 && self.config.height > 0 {
+            self.surface.configure(&self.device, &self.config);
+        }
+    }
+
+    //what went wrong with the device since it was opened, out of memory takes precedence as there is no coming back from it
+    pub fn device_error(&self) -> Option<RendererError> {
+        if self.events.out_of_memory.load(Ordering::Relaxed) {
+            return Some(RendererError::OutOfMemory);
+        }
+        self.events.lost.lock().unwrap().clone().map(RendererError::DeviceLost)
+    }
+
+    //opens a new device in place of a lost one, everything created on the old device has to be made again
+    pub async fn recover_device(&mut self) -> Result<(), RendererError> {
+        let (adapter, device, queue) = open_device(&self.instance, Some(&self.surface), &self.options).await?;
+        self.events = watch_device(&device);
+        self.adapter = adapter;
+        self.device = device;
+        self.queue = queue;
+        //the new adapter may not be the old one, so the present mode is checked again
+        self.config.present_mode = supported_present_mode(&self.surface, &self.adapter, self.present.present_mode);
+        self.configure_surface();
+        Ok(())
+    }
+}
+
+//a device for work that never reaches the screen, picked the same way as the window's
+pub async fn open_headless(options: &AdapterOptions) -> Result<(wgpu::Adapter, wgpu::Device, wgpu::Queue), RendererError> {
+    open_device(&options.create_instance(), None, options).await
+}
+
+//the first adapter from the preferences that opens a device, so a failing discrete gpu falls back to the integrated or software one
+//without a surface any adapter will do
+async fn open_device(instance: &wgpu::Instance, surface: Option<&wgpu::Surface<'_>>, options: &AdapterOptions) -> Result<(wgpu::Adapter, wgpu::Device, wgpu::Queue), RendererError> {
+    let mut error = RendererError::NoAdapter;
+    let mut tried = Vec::new();
+    for (power_preference, force_fallback_adapter) in options.preferences() {
+        let Some(adapter) = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference,
+                compatible_surface: surface,
+                force_fallback_adapter,
+            })
+            .await
+        else {
+            continue;
+        };
+        //different preferences often pick the same adapter, which would only fail again
+        let info = adapter.get_info();
+        if tried.contains(&info) {
+            continue;
+        }
+        match adapter.request_device(&device_descriptor(&adapter), None).await {
+            Ok((device, queue)) => return Ok((adapter, device, queue)),
+            Err(e) => {
+                eprintln!("Failed to open {} ({:?}), trying another adapter: {e}", info.name, info.backend);
+                error = RendererError::RequestDevice(e);
+            }
+        }
+        tried.push(info);
+    }
+    Err(error)
+}
+
+//`requested` if the surface can present with it, otherwise fifo which every surface supports
+//the automatic modes pick a supported mode by themselves
+fn supported_present_mode(surface: &wgpu::Surface<'_>, adapter: &wgpu::Adapter, requested: PresentMode) -> PresentMode {
+    let supported = surface.get_capabilities(adapter).present_modes;
+    match requested {
+        PresentMode::AutoVsync | PresentMode::AutoNoVsync => requested,
+        _ if supported.contains(&requested) => requested,
+        _ => {
+            eprintln!("Present mode {requested:?} isn't supported here, using Fifo instead, supported modes are {supported:?}");
+            PresentMode::Fifo
+        }
+    }
+}
+
+//records device loss and running out of memory instead of panicking, the app checks for them once a frame
+fn watch_device(device: &wgpu::Device) -> Arc<DeviceEvents> {
+    let events = Arc::new(DeviceEvents::default());
+    let lost = events.clone();
+    device.set_device_lost_callback(move |reason, message| {
+        //dropping the device, as a recovery does with the old one, is not a loss
+        if !matches!(reason, wgpu::DeviceLostReason::Dropped | wgpu::DeviceLostReason::ReplacedCallback) {
+            let reason = if message.is_empty() { format!("{reason:?}") } else { format!("{message}, {reason:?}") };
+            *lost.lost.lock().unwrap() = Some(reason);
+        }
+    });
+    let errors = events.clone();
+    device.on_uncaptured_error(Box::new(move |error| match error {
+        wgpu::Error::OutOfMemory { .. } => errors.out_of_memory.store(true, Ordering::Relaxed),
+        //every call on a lost device fails, the loss itself is what gets handled
+        _ if errors.lost.lock().unwrap().is_some() => {}
+        //anything else is a bug, treated as fatal like wgpu's default handler does
+        error => panic!("wgpu error: {error}"),
+    }));
+    events
+}
+
+//one line with everything a bug report needs to know about an adapter
+pub fn describe_adapter(info: &wgpu::AdapterInfo) -> String {
+    let driver = match (info.driver.is_empty(), info.driver_info.is_empty()) {
+        (true, true) => "unknown driver".to_string(),
+        (false, true) => info.driver.clone(),
+        (true, false) => info.driver_info.clone(),
+        (false, false) => format!("{} {}", info.driver, info.driver_info),
+    };
+    format!("{} ({:?}, {:?}, {driver}, vendor {:#06x}, device {:#06x})", info.name, info.backend, info.device_type, info.vendor, info.device)
+}
+
+//prints every adapter the chosen backends offer, for --list-adapters
+pub fn list_adapters(options: &AdapterOptions) {
+    let adapters = options.create_instance().enumerate_adapters(options.backends);
+    if adapters.is_empty() {
+        println!("No adapters found");
+    }
+    for (index, adapter) in adapters.iter().enumerate() {
+        println!("{index}: {}", describe_adapter(&adapter.get_info()));
+    }
+}
+
+//what the renderer needs from a device, shared with the headless setup of the golden image tests
+pub fn device_descriptor(adapter: &wgpu::Adapter) -> wgpu::DeviceDescriptor<'static> {
+    wgpu::DeviceDescriptor {
+        label: None,
+        //timestamps let the profiler time passes on the gpu, without them it falls back to cpu timers
+        required_features: adapter.features() & Features::TIMESTAMP_QUERY,
+        //the raytracer and denoiser bind more storage buffers than the downlevel minimum of 4
+        required_limits: Limits {
+            max_storage_buffers_per_shader_stage: adapter.limits().max_storage_buffers_per_shader_stage,
+            ..Limits::downlevel_defaults()
+        },
+        memory_hints: wgpu::MemoryHints::MemoryUsage,
+    }
+}
+
+//the software fallback adapter if the platform has one, otherwise any adapter that will run without a window
+#[cfg(test)]
+pub fn headless_device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::default();
+    let adapter = [true, false].into_iter().find_map(|force_fallback_adapter| {
+        pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::LowPower,
+            compatible_surface: None,
+            force_fallback_adapter,
+        }))
+    })?;
+    println!("Testing on {}", adapter.get_info().name);
+    pollster::block_on(adapter.request_device(&device_descriptor(&adapter), None)).ok()
 }
\ No newline at end of file
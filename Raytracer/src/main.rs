@@ -1,15 +1,44 @@
 pub mod controls;
+pub mod cpu;
 pub mod gpu;
+pub mod options;
 pub mod scene;
+pub mod ui;
+#[cfg(test)]
+mod golden;
 
+use std::path::PathBuf;
+
+use controls::bookmarks::{Bookmarks, CameraTransition};
 use controls::cameracontroller::{ CameraController};
+use controls::camerapath::{CameraPlayback, CameraRecorder, PlaybackRate};
+use controls::inputmap::{Action, InputMap, Trigger};
+use cpu::raytracer::{self as cpu_raytracer, CpuRaytracer};
+use gpu::raytracer::aov::{self, AovView};
+use gpu::raytracer::benchmark;
 use gpu::raytracer::compute_pipeline::{self, ComputeState};
+use gpu::raytracer::denoiser::Denoiser;
+use gpu::raytracer::shaders::{self, ShaderWatcher};
 use gpu::raytracer::fragment_pipeline::{self, RenderState};
-use gpu::wgpu_init::Init;
-use winit::event::{DeviceEvent, MouseButton, RawKeyEvent, WindowEvent};
+use gpu::raytracer::tiled::{self, TiledRender};
+use gpu::pacing::FramePacer;
+use gpu::profiler::{Profiler, Scope};
+use gpu::error::RendererError;
+use gpu::wgpu_init::{self, Init};
+use options::Options;
+use winit::event::{DeviceEvent, ElementState, KeyEvent, WindowEvent};
 use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
+use winit::keyboard::{ModifiersState, PhysicalKey};
+use winit::window::CursorGrabMode;
+use scene::history::History;
+use scene::picking;
+use scene::scenefile;
+use ui::overlay::Overlay;
 use scene::scene::Scene;
 
+const DEFAULT_CAMERA_PATH: &str = "camera_path.txt";
+const WINDOW_TITLE: &str = "Raytracer";
+
 struct Timer{
     last_render_time: std::time::Instant,
 }impl Timer{
@@ -26,28 +55,609 @@ struct App<'a> {
     scene: Option<Scene>,
     controller: Option<CameraController>,
     timer: Option<Timer>,
+    pacer: Option<FramePacer>,
+    options: Options,
+    recorder: Option<CameraRecorder>,
+    playback: Option<CameraPlayback>,
+    //where fixed rate playback writes its numbered frames
+    playback_frames: Option<PathBuf>,
+    bookmarks: Option<Bookmarks>,
+    transition: Option<CameraTransition>,
+    modifiers: ModifiersState,
+    input_map: InputMap,
+    overlay: Option<Overlay>,
+    denoiser: Option<Denoiser>,
+    profiler: Option<Profiler>,
+    //set while the wgsl files are watched for changes
+    shader_watcher: Option<ShaderWatcher>,
+    aov_view: AovView,
+    cursor_position: winit::dpi::PhysicalPosition<f64>,
+    //index into `Scene::spheres` of the last picked object
+    selected: Option<usize>,
+    history: History,
+    //why the app stopped, reported by main once the event loop has exited
+    error: Option<RendererError>,
+}
+
+impl <'a>App<'a> {
+    fn new(options: Options, input_map: InputMap) -> Self {
+        Self { options, input_map, ..Default::default() }
+    }
+
+    fn process_trigger(&mut self, trigger: Trigger, state: ElementState) {
+        let pressed = state == ElementState::Pressed;
+        let actions: Vec<Action> = if pressed {
+            self.input_map.pressed(trigger, self.modifiers).collect()
+        } else {
+            self.input_map.released(trigger).collect()
+        };
+        for action in actions {
+            self.handle_action(action, pressed);
+        }
+    }
+
+    fn handle_action(&mut self, action: Action, pressed: bool) {
+        if let Some(cameracontroller) = self.controller.as_mut() {
+            if cameracontroller.process_action(action, pressed) {
+                return;
+            }
+        }
+        if !pressed {
+            return;
+        }
+        match action {
+            Action::Screenshot => self.save_screenshot(),
+            Action::SaveExr => self.save_exr(),
+            Action::CycleAovView => self.cycle_aov_view(),
+            Action::ToggleCameraMode => self.toggle_camera_mode(),
+            Action::ToggleMouseCapture => {
+                let captured = self.controller.as_ref().is_some_and(|c| !c.is_captured());
+                self.set_mouse_capture(captured);
+            }
+            Action::ToggleOverlay => {
+                if let Some(overlay) = self.overlay.as_mut() {
+                    overlay.visible = !overlay.visible;
+                }
+            }
+            Action::ToggleDenoiser => {
+                if let (Some(denoiser), Some(compute_state)) = (self.denoiser.as_mut(), self.compute_state.as_mut()) {
+                    denoiser.settings.enabled = !denoiser.settings.enabled;
+                    //otherwise a converged image keeps showing the old filter
+                    compute_state.sampler.refresh();
+                    println!("Denoiser {}", if denoiser.settings.enabled { "on" } else { "off" });
+                }
+            }
+            Action::ToggleProfiler => self.toggle_profiler(),
+            Action::Pick => self.pick_object(),
+            Action::Focus => self.focus_on_object(),
+            Action::ToggleRecording => self.toggle_recording(),
+            Action::TogglePlayback => self.toggle_playback(),
+            Action::Undo => {
+                if let Some(scene) = self.scene.as_mut() {
+                    match self.history.undo(scene) {
+                        Ok(true) => {}
+                        Ok(false) => println!("Nothing to undo"),
+                        Err(e) => eprintln!("Failed to undo: {e}"),
+                    }
+                }
+            }
+            Action::Redo => {
+                if let Some(scene) = self.scene.as_mut() {
+                    match self.history.redo(scene) {
+                        Ok(true) => {}
+                        Ok(false) => println!("Nothing to redo"),
+                        Err(e) => eprintln!("Failed to redo: {e}"),
+                    }
+                }
+            }
+            Action::SaveScene => self.save_scene(),
+            Action::ReloadScene => self.reload_scene(),
+            Action::StoreBookmark(slot) => self.store_bookmark(slot),
+            Action::RecallBookmark(slot) => self.recall_bookmark(slot),
+            _ => {}
+        }
+    }
+
+    //grabs and hides the cursor so the mouse can turn the camera freely
+    fn set_mouse_capture(&mut self, captured: bool) {
+        let (Some(window), Some(cameracontroller)) = (self.window.as_ref(), self.controller.as_mut()) else { return };
+        if captured {
+            //not every platform can lock the cursor in place, confining it to the window is the next best thing
+            let grab = window
+                .set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined));
+            if let Err(e) = grab {
+                eprintln!("Failed to capture the mouse: {e}");
+                return;
+            }
+        } else {
+            let _ = window.set_cursor_grab(CursorGrabMode::None);
+        }
+        window.set_cursor_visible(!captured);
+        cameracontroller.set_captured(captured);
+    }
+
+    fn save_screenshot(&mut self) {
+        let (Some(init), Some(compute_state)) = (self.init.as_ref(), self.compute_state.as_ref()) else { return };
+        let seconds = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let path = PathBuf::from(format!("screenshot_{seconds}.png"));
+        match compute_state.save_screenshot(&init.device, &init.queue, &path) {
+            Ok(()) => println!("Saved screenshot to {}", path.display()),
+            Err(e) => eprintln!("Failed to save screenshot to {}: {e}", path.display()),
+        }
+    }
+
+    //offline render at a size of its own, saved next to the screenshots
+    fn render_tiled(&mut self, scene: &Scene, render: &TiledRender) {
+        let (Some(init), Some(compute_state)) = (self.init.as_ref(), self.compute_state.as_mut()) else { return };
+        let image = tiled::render_tiled(&init.device, &init.queue, compute_state, scene, render);
+        save_render(&image, render);
+    }
+
+    //beauty plus every aov in one multi-layer exr
+    fn save_exr(&mut self) {
+        let (Some(init), Some(compute_state), Some(scene)) = (self.init.as_ref(), self.compute_state.as_mut(), self.scene.as_ref()) else { return };
+        let was_enabled = compute_state.aovs_enabled();
+        if !was_enabled {
+            //the aovs are only written while enabled, so render a frame into them first
+            compute_state.set_aovs_enabled(&init.device, &init.queue, scene, true);
+            compute_state.dispatch(init, None);
+            if let Some(denoiser) = self.denoiser.as_mut() {
+                denoiser.dispatch(init, &compute_state.camera_uniform, None);
+            }
+        }
+
+        let seconds = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let path = PathBuf::from(format!("render_{seconds}.exr"));
+        match aov::save_exr(&init.device, &init.queue, compute_state, &path) {
+            Ok(()) => println!("Saved render passes to {}", path.display()),
+            Err(e) => eprintln!("Failed to save render passes to {}: {e}", path.display()),
+        }
+
+        if !was_enabled {
+            compute_state.set_aovs_enabled(&init.device, &init.queue, scene, false);
+        }
+    }
+
+    //timings go to the window title while the profiler runs
+    fn toggle_profiler(&mut self) {
+        let (Some(profiler), Some(compute_state)) = (self.profiler.as_mut(), self.compute_state.as_mut()) else { return };
+        profiler.toggle();
+        compute_state.sampler.count_rays = profiler.enabled;
+        if profiler.enabled {
+            println!("Profiling with {} timers", if profiler.uses_timestamps() { "gpu timestamp" } else { "cpu" });
+        } else if let Some(window) = self.window.as_ref() {
+            window.set_title(WINDOW_TITLE);
+        }
+    }
+
+    //steps the display through the beauty pass and each aov
+    fn cycle_aov_view(&mut self) {
+        let (Some(init), Some(compute_state), Some(fragment_state), Some(scene)) = (self.init.as_ref(), self.compute_state.as_mut(), self.fragment_state.as_mut(), self.scene.as_ref()) else { return };
+        self.aov_view = self.aov_view.next();
+        Self::apply_aov_view(self.aov_view, init, compute_state, fragment_state, scene);
+        println!("Showing {}", self.aov_view.name());
+    }
+
+    fn apply_aov_view(aov_view: AovView, init: &Init, compute_state: &mut ComputeState, fragment_state: &mut RenderState, scene: &Scene) {
+        compute_state.set_aovs_enabled(&init.device, &init.queue, scene, aov_view.needs_aovs());
+        let (buffer, stride, offset) = aov_view.source(compute_state);
+        fragment_state.set_view(&init.device, &init.queue, aov_view, buffer, stride, offset);
+    }
+
+    fn camera_path(&self) -> PathBuf {
+        self.options.record_path.clone().unwrap_or_else(|| DEFAULT_CAMERA_PATH.into())
+    }
+
+    fn toggle_recording(&mut self) {
+        match self.recorder.take() {
+            Some(recorder) => match recorder.save() {
+                Ok(()) => println!("Saved {} camera keyframes to {}", recorder.keyframe_count(), recorder.path().display()),
+                Err(e) => eprintln!("Failed to save camera path to {}: {e}", recorder.path().display()),
+            },
+            None => {
+                let recorder = CameraRecorder::new(self.camera_path());
+                println!("Recording camera path to {}", recorder.path().display());
+                self.recorder = Some(recorder);
+            }
+        }
+    }
+
+    fn toggle_playback(&mut self) {
+        if self.playback.take().is_some() {
+            self.stop_playback();
+            return;
+        }
+        let path = self.options.play_path.clone().unwrap_or_else(|| self.camera_path());
+        let rate = match self.options.play_fps {
+            Some(fps) => PlaybackRate::FixedFrameRate(fps),
+            None => PlaybackRate::RealTime,
+        };
+        match CameraPlayback::load(&path, rate) {
+            Ok(playback) => {
+                println!("Playing camera path {} ({:.1}s)", path.display(), playback.duration());
+                if let (PlaybackRate::FixedFrameRate(_), Some(compute_state)) = (rate, self.compute_state.as_mut()) {
+                    compute_state.fixed_seed = Some(0);
+                    let folder = playback_frame_folder(&path);
+                    match std::fs::create_dir_all(&folder) {
+                        Ok(()) => {
+                            println!("Saving frames to {}", folder.display());
+                            self.playback_frames = Some(folder);
+                        }
+                        Err(e) => eprintln!("Failed to create {}, playing without saving frames: {e}", folder.display()),
+                    }
+                }
+                self.playback = Some(playback);
+            }
+            Err(e) => eprintln!("Failed to load camera path {}: {e}", path.display()),
+        }
+    }
+
+    fn stop_playback(&mut self) {
+        println!("Camera playback stopped");
+        self.playback_frames = None;
+        //carry on from wherever the path left the camera
+        if let (Some(cameracontroller), Some(scene)) = (self.controller.as_mut(), self.scene.as_ref()) {
+            cameracontroller.sync(&scene.camera);
+        }
+        if let Some(compute_state) = self.compute_state.as_mut() {
+            compute_state.fixed_seed = None;
+        }
+    }
+
+    fn save_scene(&mut self) {
+        let Some(scene) = self.scene.as_ref() else { return };
+        match scenefile::save_scene(scene, &scene.path) {
+            Ok(()) => println!("Saved scene to {}", scene.path.display()),
+            Err(e) => eprintln!("Failed to save scene: {e}"),
+        }
+    }
+
+    //reads the scene file back in, the edit history is kept so changes made before the reload can still be undone
+    fn reload_scene(&mut self) {
+        let (Some(scene), Some(init)) = (self.scene.as_mut(), self.init.as_ref()) else { return };
+        let reloaded = match scenefile::load_scene(&scene.path) {
+            Ok(reloaded) => reloaded,
+            Err(e) => {
+                eprintln!("Failed to reload scene: {e}");
+                return;
+            }
+        };
+        if let Some(compute_state) = self.compute_state.as_mut() {
+            if reloaded.environment_path() != scene.environment_path() {
+                if let Err(e) = compute_state.set_environment(&init.device, reloaded.environment_path()) {
+                    eprintln!("Failed to load environment map {}: {e}", reloaded.environment_path().display());
+                }
+            }
+        }
+        if let Some(fragment_state) = self.fragment_state.as_mut() {
+            fragment_state.set_exposure(&init.queue, reloaded.exposure);
+        }
+        *scene = reloaded;
+        self.transition = None;
+        if let Some(cameracontroller) = self.controller.as_mut() {
+            cameracontroller.sync(&scene.camera);
+        }
+        println!("Reloaded scene from {}", scene.path.display());
+    }
+
+    fn store_bookmark(&mut self, slot: usize) {
+        let (Some(bookmarks), Some(scene)) = (self.bookmarks.as_mut(), self.scene.as_ref()) else { return };
+        match bookmarks.store(slot, &scene.camera) {
+            Ok(()) => println!("Stored bookmark {} in {}", slot + 1, bookmarks.path().display()),
+            Err(e) => eprintln!("Failed to save bookmarks to {}: {e}", bookmarks.path().display()),
+        }
+    }
+
+    //flies back to a stored view
+    fn recall_bookmark(&mut self, slot: usize) {
+        let (Some(bookmarks), Some(scene)) = (self.bookmarks.as_ref(), self.scene.as_ref()) else { return };
+        if let Some(target) = bookmarks.get(slot) {
+            self.transition = Some(CameraTransition::new(scene.camera, *target));
+        }
+    }
+
+    fn toggle_camera_mode(&mut self) {
+        if let (Some(cameracontroller), Some(scene)) = (self.controller.as_mut(), self.scene.as_ref()) {
+            cameracontroller.toggle_mode(&scene.camera);
+            println!("Camera mode: {:?}", cameracontroller.mode());
+        }
+    }
+
+    //screen position to pick from, the centre of the view while the mouse is captured
+    fn pick_point(&self) -> glam::Vec2 {
+        match (self.window.as_ref(), self.controller.as_ref()) {
+            (Some(window), Some(cameracontroller)) if !cameracontroller.is_captured() => {
+                picking::cursor_to_ndc(self.cursor_position, window.inner_size())
+            }
+            _ => glam::Vec2::ZERO,
+        }
+    }
+
+    fn pick_object(&mut self) {
+        let (Some(scene), Some(compute_state)) = (self.scene.as_ref(), self.compute_state.as_ref()) else { return };
+        let hit = picking::pick(scene, &compute_state.camera_uniform, self.pick_point());
+        match hit {
+            Some(hit) => println!(
+                "Picked object {} at distance {:.2}, position ({:.2}, {:.2}, {:.2}), material {:?}",
+                hit.index, hit.distance, hit.position.x, hit.position.y, hit.position.z, hit.material
+            ),
+            None => println!("Nothing under the cursor"),
+        }
+        self.selected = hit.map(|hit| hit.index);
+    }
+
+    //orbits the selected object, or whatever is in the centre of the view if nothing is selected
+    fn focus_on_object(&mut self) {
+        let (Some(cameracontroller), Some(scene), Some(compute_state)) = (self.controller.as_mut(), self.scene.as_ref(), self.compute_state.as_ref()) else { return };
+        let target = self
+            .selected
+            .or_else(|| picking::pick(scene, &compute_state.camera_uniform, glam::Vec2::ZERO).map(|hit| hit.index));
+        match target.and_then(|index| scene.spheres().get(index)) {
+            Some(sphere) => cameracontroller.focus(sphere.center.into(), sphere.radius),
+            None => println!("Nothing to focus on"),
+        }
+    }
+
+    //rebuilds the pipelines from the wgsl on disk, a pipeline whose shader fails to build keeps running the last good one
+    fn reload_shaders(&mut self) {
+        let (Some(watcher), Some(init), Some(compute_state), Some(fragment_state)) = (self.shader_watcher.as_ref(), self.init.as_ref(), self.compute_state.as_mut(), self.fragment_state.as_mut()) else { return };
+        let compute = shaders::load_from(watcher.dir(), "compute_shader.wgsl")
+            .and_then(|shader| compute_state.set_shader(&init.device, &shader));
+        let fragment = shaders::load_from(watcher.dir(), "fragment_shader.wgsl")
+            .and_then(|shader| fragment_state.set_shader(&init.device, &shader));
+        let denoise = match self.denoiser.as_mut() {
+            Some(denoiser) => shaders::load_from(watcher.dir(), "denoise_shader.wgsl").and_then(|shader| denoiser.set_shader(&init.device, &shader)),
+            None => Ok(()),
+        };
+        if compute.is_ok() {
+            if let Some(denoiser) = self.denoiser.as_mut() {
+                denoiser.reset_history();
+            }
+        }
+        //a converged image isn't denoised again on its own, so it is traced once more to show the new filter
+        if denoise.is_ok() {
+            compute_state.sampler.refresh();
+        }
+
+        let errors: Vec<String> = [compute, fragment, denoise].into_iter().filter_map(Result::err).collect();
+        let error = (!errors.is_empty()).then(|| errors.join("\n"));
+        match error.as_ref() {
+            Some(error) => eprintln!("Failed to reload shaders:\n{error}"),
+            None => println!("Reloaded shaders from {}", watcher.dir().display()),
+        }
+        if let Some(overlay) = self.overlay.as_mut() {
+            overlay.shader_error = error;
+        }
+    }
+
+    //a lost device takes every buffer and pipeline with it, so everything on the gpu is made again on a new one
+    //the scene, camera and settings live on the cpu and carry over
+    fn recover_device(&mut self) -> Result<(), RendererError> {
+        let (Some(init), Some(window), Some(scene)) = (self.init.as_mut(), self.window.as_ref(), self.scene.as_ref()) else { return Ok(()) };
+        pollster::block_on(init.recover_device())?;
+        println!("Continuing on {}", init.adapter.get_info().name);
+
+        let mut compute_state = pollster::block_on(ComputeState::new(&init.device, &init.queue, &window.inner_size(), scene));
+        if let Some(old) = self.compute_state.take() {
+            compute_state.sampler.settings = old.sampler.settings;
+            compute_state.sampler.count_rays = old.sampler.count_rays;
+            compute_state.fixed_seed = old.fixed_seed;
+        }
+        let mut fragment_state = pollster::block_on(RenderState::new(&init.device, &compute_state.output_buffer));
+        fragment_state.set_exposure(&init.queue, scene.exposure);
+        Self::apply_aov_view(self.aov_view, init, &mut compute_state, &mut fragment_state, scene);
+
+        let mut denoiser = Denoiser::new(&init.device, &compute_state);
+        if let Some(old) = self.denoiser.take() {
+            denoiser.settings = old.settings;
+        }
+        let mut overlay = Overlay::new(window, &init.device, init.config.format);
+        if let Some(old) = self.overlay.take() {
+            overlay.visible = old.visible;
+            overlay.shader_error = old.shader_error;
+        }
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.set_device(&init.device, &init.queue);
+        }
+
+        self.compute_state = Some(compute_state);
+        self.fragment_state = Some(fragment_state);
+        self.denoiser = Some(denoiser);
+        self.overlay = Some(overlay);
+        //the new pipelines were built from the shaders in the binary
+        self.reload_shaders();
+        Ok(())
+    }
+
+    //idles once adaptive sampling has converged, unless the camera is still on its way somewhere
+    fn schedule_next_frame(&mut self, event_loop: &ActiveEventLoop, camera_moved: bool) {
+        let (Some(window), Some(pacer)) = (self.window.as_ref(), self.pacer.as_mut()) else { return };
+        let converged = self.compute_state.as_ref().is_some_and(|c| c.sampler.is_converged());
+        let idle = self.options.idle_when_converged && converged && !camera_moved;
+        pacer.schedule(event_loop, window, idle, self.shader_watcher.is_some());
+    }
+
+    //stops the app, main reports `error` once the event loop has exited
+    fn fail(&mut self, event_loop: &ActiveEventLoop, error: RendererError) {
+        if self.recorder.is_some() {
+            self.toggle_recording();
+        }
+        self.error = Some(error);
+        event_loop.exit();
+    }
+
+    //lays out the editing ui, object edits reach the gpu through `sync_scene`
+    fn run_overlay(&mut self) {
+        let (Some(overlay), Some(window), Some(scene), Some(init), Some(denoiser), Some(compute_state)) = (self.overlay.as_mut(), self.window.as_ref(), self.scene.as_mut(), self.init.as_ref(), self.denoiser.as_mut(), self.compute_state.as_mut()) else { return };
+        let changes = overlay.run(window, scene, &mut self.history, &mut self.selected, &mut denoiser.settings, &mut compute_state.sampler);
+
+        if let (true, Some(fragment_state)) = (changes.display, self.fragment_state.as_mut()) {
+            fragment_state.set_exposure(&init.queue, scene.exposure);
+        }
+    }
+
+    //saves what the last dispatch rendered as the next numbered frame of a fixed rate playback
+    fn save_playback_frame(&mut self) {
+        let (Some(folder), Some(playback), Some(init), Some(compute_state)) = (self.playback_frames.as_ref(), self.playback.as_ref(), self.init.as_ref(), self.compute_state.as_ref()) else { return };
+        //the camera only takes its first pose from the path after the first frame
+        let Some(frame) = playback.frame().checked_sub(1) else { return };
+        let path = folder.join(format!("frame_{frame:05}.png"));
+        if let Err(e) = compute_state.save_screenshot(&init.device, &init.queue, &path) {
+            eprintln!("Failed to save playback frame to {}: {e}", path.display());
+        }
+    }
+
+    //moves the scene camera from a bookmark transition or playback path if one is running, otherwise from the controller
+    fn update_camera(&mut self, delta_time: f32) {
+        let Some(scene) = self.scene.as_mut() else { return };
+
+        let mut playback_finished = false;
+        if let Some(transition) = self.transition.as_mut() {
+            if !transition.update(&mut scene.camera, delta_time) {
+                self.transition = None;
+                if let Some(cameracontroller) = self.controller.as_mut() {
+                    cameracontroller.sync(&scene.camera);
+                }
+            }
+        } else if let Some(playback) = self.playback.as_mut() {
+            playback_finished = !playback.update(&mut scene.camera, delta_time);
+            if let Some(compute_state) = self.compute_state.as_mut() {
+                if compute_state.fixed_seed.is_some() {
+                    compute_state.fixed_seed = Some(playback.frame());
+                }
+            }
+        } else if let Some(cameracontroller) = self.controller.as_mut() {
+            cameracontroller.update_camera(&mut scene.camera, delta_time);
+        }
+
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record(&scene.camera, delta_time);
+        }
+
+        if playback_finished {
+            self.playback = None;
+            self.stop_playback();
+        }
+    }
 }
 
 impl <'a>winit::application::ApplicationHandler for App<'a> {
     fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
-        let window_attributes = winit::window::Window::default_attributes().with_title("Raytracer").with_inner_size(winit::dpi::PhysicalSize::new(1200, 800));
+        let window_attributes = winit::window::Window::default_attributes().with_title(WINDOW_TITLE).with_inner_size(winit::dpi::PhysicalSize::new(1200, 800));
         let window = event_loop.create_window(window_attributes).unwrap();
-        self.init = Some(pollster::block_on(Init::new(&window))); 
-        self.compute_state = Some(pollster::block_on(compute_pipeline::ComputeState::new(&self.init.as_ref().unwrap().device, &self.init.as_ref().unwrap().queue, &window.inner_size())));
+        let scene = load_scene(&self.options);
+        match pollster::block_on(Init::new(&window, self.options.adapter, self.options.present)) {
+            Ok(init) => self.init = Some(init),
+            Err(e) => {
+                //still produce an image, there's just nothing to show it in
+                eprintln!("Failed to start the gpu renderer, rendering a still on the cpu instead: {e}");
+                let size = window.inner_size();
+                let render = self.options.render.unwrap_or_else(|| TiledRender::new(size.width, size.height));
+                render_on_cpu(&scene, &render);
+                event_loop.exit();
+                return;
+            }
+        }
+        self.compute_state = Some(pollster::block_on(compute_pipeline::ComputeState::new(&self.init.as_ref().unwrap().device, &self.init.as_ref().unwrap().queue, &window.inner_size(), &scene)));
         self.fragment_state = Some(pollster::block_on(fragment_pipeline::RenderState::new(&self.init.as_ref().unwrap().device, &self.compute_state.as_ref().unwrap().output_buffer)));
-        self.controller = Some(CameraController::new(100.0, 0.6));
-        self.scene = Some(Scene::new());
+        let init = self.init.as_ref().unwrap();
+        self.fragment_state.as_mut().unwrap().set_exposure(&init.queue, scene.exposure);
+        self.overlay = Some(Overlay::new(&window, &init.device, init.config.format));
+        self.denoiser = Some(Denoiser::new(&init.device, self.compute_state.as_ref().unwrap()));
+        let mut profiler = Profiler::new(&init.device, &init.queue);
+        if let Some(path) = self.options.profile_csv.as_ref() {
+            match profiler.log_to(path) {
+                Ok(()) => self.compute_state.as_mut().unwrap().sampler.count_rays = true,
+                Err(e) => eprintln!("Failed to open profiler log {}: {e}", path.display()),
+            }
+        }
+        self.profiler = Some(profiler);
+        self.compute_state.as_mut().unwrap().sampler.settings.samples_per_frame = self.options.samples_per_frame;
+        //the adaptive sampler's running mean is what accumulates until the app can idle
+        if self.options.idle_when_converged {
+            self.compute_state.as_mut().unwrap().sampler.settings.adaptive = true;
+        }
+        if let Some(noise) = self.options.converge {
+            let sampler = &mut self.compute_state.as_mut().unwrap().sampler;
+            sampler.settings.adaptive = true;
+            sampler.settings.noise_threshold = noise;
+        }
+        //with --converge set, tiles stop early once their pixels are below its threshold
+        if let Some(render) = self.options.render {
+            self.render_tiled(&scene, &render);
+            event_loop.exit();
+            return;
+        }
+        //the first load picks up edits made since the binary was built
+        if self.options.watch_shaders {
+            self.shader_watcher = Some(ShaderWatcher::new(shaders::source_dir()));
+            self.reload_shaders();
+        }
+        self.controller = Some(CameraController::new(self.input_map.controller_settings));
+        match Bookmarks::load_or_default(scene.sidecar_path("bookmarks")) {
+            Ok(bookmarks) => self.bookmarks = Some(bookmarks),
+            Err(e) => eprintln!("Failed to load bookmarks: {e}"),
+        }
+        self.scene = Some(scene);
         self.timer = Some(Timer::new());
+        self.pacer = Some(FramePacer::new(self.options.max_fps));
         self.window = Some(window);
-        
+
+        if self.options.record_path.is_some() {
+            self.toggle_recording();
+        }
+        if self.options.play_path.is_some() {
+            self.toggle_playback();
+        }
     }
 
-    fn window_event(&mut self, event_loop: &winit::event_loop::ActiveEventLoop, id: winit::window::WindowId, event: WindowEvent) {
+    fn window_event(&mut self, event_loop: &winit::event_loop::ActiveEventLoop, _id: winit::window::WindowId, event: WindowEvent) {
+        if let (false, Some(pacer), Some(window)) = (event == WindowEvent::RedrawRequested, self.pacer.as_mut(), self.window.as_ref()) {
+            pacer.wake(window);
+        }
+        if let (Some(overlay), Some(window)) = (self.overlay.as_mut(), self.window.as_ref()) {
+            if overlay.on_window_event(window, &event) {
+                return;
+            }
+        }
         match event {
             WindowEvent::CloseRequested => {
                 println!("The close button was pressed; stopping");
+                if self.recorder.is_some() {
+                    self.toggle_recording();
+                }
                 event_loop.exit();
             },
+            WindowEvent::KeyboardInput { event: KeyEvent { physical_key: PhysicalKey::Code(code), state, repeat: false, .. }, .. } => {
+                self.process_trigger(Trigger::Key(code), state);
+            },
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = position;
+            },
+            //taken from the window rather than the raw device, so scrolling a panel or another window leaves the camera alone
+            WindowEvent::MouseWheel { delta, .. } => {
+                if let Some(cameracontroller) = self.controller.as_mut() {
+                    cameracontroller.process_scroll(&delta);
+                }
+            },
+            WindowEvent::MouseInput { button, state, .. } => {
+                self.process_trigger(Trigger::Mouse(button), state);
+            },
+            WindowEvent::Focused(false) => {
+                //keys released while another window has focus never reach us
+                self.set_mouse_capture(false);
+                if let Some(cameracontroller) = self.controller.as_mut() {
+                    cameracontroller.release_all();
+                }
+            },
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+            },
             WindowEvent::Resized(physical_size) => {
                 if let Some(init) = self.init.as_mut() {
                     init.resize(physical_size);
@@ -58,36 +668,112 @@ impl <'a>winit::application::ApplicationHandler for App<'a> {
 
             },
             WindowEvent::RedrawRequested => {
+                if let Some(pacer) = self.pacer.as_mut() {
+                    pacer.begin_frame();
+                }
 
-                if let Some(compute_pipeline) = self.compute_state.as_mut() {
-                    compute_pipeline.dispatch(self.init.as_ref().unwrap()); 
+                if let Some(error) = self.init.as_ref().and_then(Init::device_error) {
+                    let recovered = match error {
+                        RendererError::DeviceLost(reason) => {
+                            eprintln!("The graphics device was lost ({reason}), rebuilding the renderer");
+                            self.recover_device()
+                        }
+                        error => Err(error),
+                    };
+                    if let Err(e) = recovered {
+                        self.fail(event_loop, e);
+                        return;
+                    }
+                }
+
+                if self.shader_watcher.as_mut().is_some_and(|watcher| watcher.poll()) {
+                    self.reload_shaders();
+                }
+                self.run_overlay();
+
+                if let (Some(compute_pipeline), Some(scene), Some(init)) = (self.compute_state.as_mut(), self.scene.as_mut(), self.init.as_ref()) {
+                    if compute_pipeline.sync_scene(&init.device, &init.queue, scene) {
+                        if let Some(denoiser) = self.denoiser.as_mut() {
+                            denoiser.reset_history();
+                        }
+                    }
+                }
+
+                let mut out_of_memory = false;
+                if let (Some(compute_pipeline), Some(profiler), Some(init)) = (self.compute_state.as_mut(), self.profiler.as_mut(), self.init.as_ref()) {
+                    //once adaptive sampling has converged the last denoised image is simply shown again
+                    profiler.begin(Scope::Compute);
+                    if compute_pipeline.dispatch(init, profiler.compute_pass_writes(Scope::Compute)) {
+                        profiler.end(Scope::Compute, &init.device);
+                        if let Some(denoiser) = self.denoiser.as_mut() {
+                            //a disabled denoiser still runs to drop its history, but has no pass to time
+                            let enabled = denoiser.settings.enabled;
+                            profiler.begin(Scope::Denoise);
+                            denoiser.dispatch(init, &compute_pipeline.camera_uniform, profiler.compute_pass_writes(Scope::Denoise));
+                            if enabled {
+                                profiler.end(Scope::Denoise, &init.device);
+                            }
+                        }
+                    }
 
                     if let Some(fragment_pipeline) = self.fragment_state.as_mut() {
-                        let _ = fragment_pipeline.render(self.init.as_ref().unwrap()); 
+                        profiler.begin(Scope::Display);
+                        let presented = fragment_pipeline.render(init, self.overlay.as_mut(), profiler.render_pass_writes(Scope::Display));
+                        profiler.end(Scope::Display, &init.device);
+                        match presented {
+                            Ok(()) => {}
+                            //the surface no longer matches the window, this frame is dropped and the next one goes to the new configuration
+                            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => init.configure_surface(),
+                            //the compositor was busy, the next frame simply tries again
+                            Err(wgpu::SurfaceError::Timeout) => {}
+                            Err(wgpu::SurfaceError::OutOfMemory) => out_of_memory = true,
+                        }
                     }
 
-                    if let Some(timer) = self.timer.as_mut() {
-                        let delta_time = (std::time::Instant::now() - timer.last_render_time).as_secs_f32();
-                        timer.last_render_time = std::time::Instant::now();
+                    let counters = compute_pipeline.sampler.counters();
+                    let samples = counters.traced_pixels as u64 * compute_pipeline.sampler.samples_per_frame() as u64;
+                    if let Some(readout) = profiler.end_frame(&init.device, &init.queue, samples, counters.rays()) {
+                        if let Some(window) = self.window.as_ref() {
+                            window.set_title(&format!("{WINDOW_TITLE} | {readout}"));
+                        }
+                    }
+                }
 
-                            if let Some(cameracontroller) = self.controller.as_mut() {
+                if out_of_memory {
+                    self.fail(event_loop, RendererError::OutOfMemory);
+                    return;
+                }
+                self.save_playback_frame();
 
-                                if let Some(scene) = self.scene.as_mut(){
+                if self.options.converge.is_some() && self.compute_state.as_ref().is_some_and(|c| c.sampler.is_converged()) {
+                    self.save_screenshot();
+                    event_loop.exit();
+                    return;
+                }
 
-                                    compute_pipeline.update(&self.init.as_ref().unwrap().queue, cameracontroller, scene, delta_time);
+                let camera_before = self.scene.as_ref().map(|scene| scene.camera);
+                if let Some(timer) = self.timer.as_mut() {
+                    let delta_time = (std::time::Instant::now() - timer.last_render_time).as_secs_f32();
+                    timer.last_render_time = std::time::Instant::now();
+                    self.update_camera(delta_time);
+                }
+                let camera_moved = self.scene.as_ref().map(|scene| scene.camera) != camera_before;
 
-                                }
-                            }
-                    }
+                if let (Some(compute_pipeline), Some(scene)) = (self.compute_state.as_mut(), self.scene.as_ref()) {
+                    compute_pipeline.update(&self.init.as_ref().unwrap().queue, scene);
                 }
-                self.window.as_ref().unwrap().request_redraw();
+                self.schedule_next_frame(event_loop, camera_moved);
             }
             _ => (),
         }
     }
     
     fn new_events(&mut self, event_loop: &ActiveEventLoop, cause: winit::event::StartCause) {
-        let _ = (event_loop, cause);
+        let _ = event_loop;
+        //the wait scheduled by the frame pacer is over
+        if let (winit::event::StartCause::ResumeTimeReached { .. }, Some(window)) = (cause, self.window.as_ref()) {
+            window.request_redraw();
+        }
     }
     
     fn user_event(&mut self, event_loop: &ActiveEventLoop, event: ()) {
@@ -96,27 +782,18 @@ impl <'a>winit::application::ApplicationHandler for App<'a> {
     
     fn device_event(
         &mut self,
-        event_loop: &ActiveEventLoop,
-        device_id: winit::event::DeviceId,
+        _event_loop: &ActiveEventLoop,
+        _device_id: winit::event::DeviceId,
         event: winit::event::DeviceEvent,
     ) {
-        if let Some(cameracontroller) = self.controller.as_mut() {
-        
-        match event {
-            DeviceEvent::Key(RawKeyEvent { physical_key, state }) => {
-                cameracontroller.process_keyboard(physical_key, state);
-            }
-            DeviceEvent::MouseMotion { delta: (dx, dy) } => {
-                cameracontroller.process_mouse(dx, dy);
-            }
-            DeviceEvent::Button { button, state } => {
-                cameracontroller.process_mouse_button(MouseButton::Left, state);
-            }
-            DeviceEvent::MouseWheel { delta } => {
-                cameracontroller.process_scroll(&delta);
+        if let (Some(pacer), Some(window), Some(cameracontroller)) = (self.pacer.as_mut(), self.window.as_ref(), self.controller.as_ref()) {
+            //raw mouse motion only turns the camera while the mouse is captured
+            if cameracontroller.is_captured() {
+                pacer.wake(window);
             }
-            _ => {}
         }
+        if let (Some(cameracontroller), DeviceEvent::MouseMotion { delta: (dx, dy) }) = (self.controller.as_mut(), event) {
+            cameracontroller.process_mouse(dx, dy);
         }
     }
     
@@ -136,10 +813,98 @@ impl <'a>winit::application::ApplicationHandler for App<'a> {
         let _ = event_loop;
     }
 }
+//camera_path.txt plays back into camera_path_frames/
+fn playback_frame_folder(path: &std::path::Path) -> PathBuf {
+    let stem = path.file_stem().map_or_else(|| "camera_path".into(), |s| s.to_string_lossy());
+    path.with_file_name(format!("{stem}_frames"))
+}
+
+//the scene file if there is one, otherwise the showcase
+fn load_scene(options: &Options) -> Scene {
+    if options.scene_path.exists() {
+        scenefile::load_scene(&options.scene_path).unwrap_or_else(|e| {
+            eprintln!("Failed to load scene, using the showcase instead: {e}");
+            Scene::showcase(options.scene_path.clone())
+        })
+    } else {
+        Scene::showcase(options.scene_path.clone())
+    }
+}
+
+//offline renders are saved next to the screenshots
+fn save_render(image: &image::RgbaImage, render: &TiledRender) {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let path = PathBuf::from(format!("render_{}x{}_{seconds}.png", render.width, render.height));
+    match image.save(&path) {
+        Ok(()) => println!("Saved render to {}", path.display()),
+        Err(e) => eprintln!("Failed to save render to {}: {e}", path.display()),
+    }
+}
+
+//traces the same rays as the gpu's offline render, without needing a gpu
+fn render_on_cpu(scene: &Scene, render: &TiledRender) {
+    println!("Rendering {}x{} on the cpu, {} samples per pixel", render.width, render.height, render.samples);
+    let raytracer = CpuRaytracer::new(scene, render.width, render.height);
+    let pixels = raytracer.render(render.samples, 0);
+    save_render(&cpu_raytracer::to_image(&pixels, render.width, render.height), render);
+}
+
+//times the compute pass on a headless device, see gpu::raytracer::benchmark
+fn run_benchmark(options: &Options, frames: u32) {
+    let (adapter, device, queue) = match pollster::block_on(wgpu_init::open_headless(&options.adapter)) {
+        Ok(opened) => opened,
+        Err(e) => {
+            eprintln!("Failed to open a device to benchmark on: {e}");
+            std::process::exit(1);
+        }
+    };
+    println!("Benchmarking on {}", wgpu_init::describe_adapter(&adapter.get_info()));
+    if cfg!(debug_assertions) {
+        println!("This is a debug build, use --release for representative numbers");
+    }
+    benchmark::run(&device, &queue, &load_scene(options), frames, options.samples_per_frame);
+}
+
 fn main(){
+let options = match Options::from_env() {
+    Ok(options) => options,
+    Err(e) => {
+        eprintln!("{e}");
+        std::process::exit(2);
+    }
+};
+if options.list_adapters {
+    wgpu_init::list_adapters(&options.adapter);
+    return;
+}
+//no window, so this also works on machines without a display
+if let (true, Some(render)) = (options.cpu, options.render) {
+    render_on_cpu(&load_scene(&options), &render);
+    return;
+}
+if let Some(frames) = options.benchmark {
+    run_benchmark(&options, frames);
+    return;
+}
 let event_loop = EventLoop::new().unwrap();
-event_loop.set_control_flow(ControlFlow::Poll);
+//frames are requested one at a time by the FramePacer
 event_loop.set_control_flow(ControlFlow::Wait);
-let mut app = App::default();
-let _ = event_loop.run_app(&mut app);
+let input_map = match InputMap::load_or_default(&options.input_path) {
+    Ok(input_map) => input_map,
+    Err(e) => {
+        eprintln!("Failed to load input bindings: {e}");
+        std::process::exit(2);
+    }
+};
+let mut app = App::new(options, input_map);
+if let Err(e) = event_loop.run_app(&mut app) {
+    eprintln!("The event loop stopped unexpectedly: {e}");
+    std::process::exit(1);
+}
+if let Some(e) = app.error {
+    eprintln!("Stopped because {e}");
+    std::process::exit(1);
+}
 }
\ No newline at end of file
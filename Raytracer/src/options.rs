@@ -0,0 +1,304 @@
+use std::path::PathBuf;
+
+use wgpu::{Backends, PowerPreference, PresentMode};
+
+use crate::gpu::raytracer::sampling::SAMPLES_PER_FRAME;
+use crate::gpu::raytracer::tiled::TiledRender;
+use crate::gpu::wgpu_init::{AdapterOptions, PresentOptions};
+
+//size of the still --cpu renders when no --render size is given, the same as the window
+const CPU_RENDER_SIZE: (u32, u32) = (1200, 800);
+
+//command line options, parsed by hand to keep the dependency list short
+//  --record <file>     start recording the camera path to <file> immediately
+//  --play <file>       play back a recorded camera path instead of using the controller
+//  --play-fps <fps>    advance playback at a fixed frame rate rather than in real time, saving every frame as a numbered png
+//                      in a folder named after the path, camera_path.txt plays into camera_path_frames/
+//  --input <file>      key and mouse bindings, defaults to input.toml
+//  --scene <file>      scene to load and save, the built in showcase is used until the file exists
+//  --converge <noise>  render with adaptive sampling until every pixel's noise is below <noise>, then save and exit
+//  --render <w>x<h>    render the scene offline at any size in tiles, save it and exit, --converge sets the noise it stops at
+//  --tile-size <px>    edge length of the tiles used by --render, defaults to 512
+//  --samples <n>       samples per pixel for --render, defaults to 256
+//  --profile-csv <file> turn on the profiler and log every frame's timings to <file>
+//  --cpu               render a still on the cpu without opening a window, at the --render size or 1200x800, save it and exit
+//  --watch-shaders, --no-watch-shaders  rebuild the pipelines whenever the wgsl under src/ changes, on by default in debug builds
+//  --list-adapters     print the adapters the chosen backends offer and exit
+//  --backend <names>   comma separated graphics apis to pick an adapter from: vulkan, metal, dx12, gl, or all
+//  --power <pref>      prefer the high or low power adapter, the others are still tried if it fails
+//  --fallback-adapter  only use the software adapter
+//  --validation, --no-validation  api validation, on by default in debug builds
+//  --present-mode <mode>  fifo (vsync, the default), fifo-relaxed, mailbox, immediate, auto-vsync or auto-no-vsync
+//  --no-vsync          the same as --present-mode auto-no-vsync
+//  --frame-latency <n> frames queued ahead of the display, defaults to 2
+//  --max-fps <fps>     cap the interactive frame rate
+//  --idle-when-converged  accumulate with adaptive sampling and stop drawing once every pixel is done, until something changes
+//  --samples-per-frame <n>  samples each pixel takes per dispatch, defaults to 16, --render takes its samples in steps of this
+//  --benchmark <frames>  time the path tracing pass over <frames> frames without opening a window, then exit
+#[derive(Debug)]
+pub struct Options {
+    pub record_path: Option<PathBuf>,
+    pub play_path: Option<PathBuf>,
+    pub play_fps: Option<f32>,
+    pub input_path: PathBuf,
+    pub scene_path: PathBuf,
+    pub converge: Option<f32>,
+    pub render: Option<TiledRender>,
+    pub profile_csv: Option<PathBuf>,
+    pub cpu: bool,
+    pub watch_shaders: bool,
+    pub list_adapters: bool,
+    pub adapter: AdapterOptions,
+    pub present: PresentOptions,
+    pub max_fps: Option<f32>,
+    pub idle_when_converged: bool,
+    pub samples_per_frame: u32,
+    pub benchmark: Option<u32>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            record_path: None,
+            play_path: None,
+            play_fps: None,
+            input_path: PathBuf::from("input.toml"),
+            scene_path: PathBuf::from("showcase.scene"),
+            converge: None,
+            render: None,
+            profile_csv: None,
+            cpu: false,
+            watch_shaders: cfg!(debug_assertions),
+            list_adapters: false,
+            adapter: AdapterOptions::default(),
+            present: PresentOptions::default(),
+            max_fps: None,
+            idle_when_converged: false,
+            samples_per_frame: SAMPLES_PER_FRAME,
+            benchmark: None,
+        }
+    }
+}
+
+impl Options {
+    pub fn from_env() -> Result<Self, String> {
+        Self::parse(std::env::args().skip(1))
+    }
+
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+        let mut tile_size = None;
+        let mut samples = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--record" => options.record_path = Some(next_value(&mut args, &arg)?.into()),
+                "--input" => options.input_path = next_value(&mut args, &arg)?.into(),
+                "--scene" => options.scene_path = next_value(&mut args, &arg)?.into(),
+                "--profile-csv" => options.profile_csv = Some(next_value(&mut args, &arg)?.into()),
+                "--play" => options.play_path = Some(next_value(&mut args, &arg)?.into()),
+                "--play-fps" => {
+                    let fps = next_value(&mut args, &arg)?
+                        .parse::<f32>()
+                        .map_err(|e| format!("invalid value for --play-fps: {e}"))?;
+                    if fps <= 0.0 {
+                        return Err("--play-fps must be greater than zero".to_string());
+                    }
+                    options.play_fps = Some(fps);
+                }
+                "--converge" => {
+                    let noise = next_value(&mut args, &arg)?
+                        .parse::<f32>()
+                        .map_err(|e| format!("invalid value for --converge: {e}"))?;
+                    if noise <= 0.0 {
+                        return Err("--converge must be greater than zero".to_string());
+                    }
+                    options.converge = Some(noise);
+                }
+                "--render" => {
+                    let value = next_value(&mut args, &arg)?;
+                    let (width, height) = value
+                        .split_once('x')
+                        .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)))
+                        .filter(|&(w, h)| w > 0 && h > 0)
+                        .ok_or_else(|| format!("invalid value for --render: `{value}`, expected <width>x<height>"))?;
+                    options.render = Some(TiledRender::new(width, height));
+                }
+                "--cpu" => options.cpu = true,
+                "--watch-shaders" => options.watch_shaders = true,
+                "--no-watch-shaders" => options.watch_shaders = false,
+                "--list-adapters" => options.list_adapters = true,
+                "--backend" => options.adapter.backends = parse_backends(&next_value(&mut args, &arg)?)?,
+                "--power" => {
+                    let value = next_value(&mut args, &arg)?;
+                    options.adapter.power_preference = Some(match value.as_str() {
+                        "high" => PowerPreference::HighPerformance,
+                        "low" => PowerPreference::LowPower,
+                        _ => return Err(format!("invalid value for --power: `{value}`, expected high or low")),
+                    });
+                }
+                "--fallback-adapter" => options.adapter.force_fallback_adapter = true,
+                "--present-mode" => {
+                    let value = next_value(&mut args, &arg)?;
+                    options.present.present_mode = match value.as_str() {
+                        "fifo" => PresentMode::Fifo,
+                        "fifo-relaxed" => PresentMode::FifoRelaxed,
+                        "mailbox" => PresentMode::Mailbox,
+                        "immediate" => PresentMode::Immediate,
+                        "auto-vsync" => PresentMode::AutoVsync,
+                        "auto-no-vsync" => PresentMode::AutoNoVsync,
+                        _ => return Err(format!(
+                            "invalid value for --present-mode: `{value}`, expected fifo, fifo-relaxed, mailbox, immediate, auto-vsync or auto-no-vsync"
+                        )),
+                    };
+                }
+                "--no-vsync" => options.present.present_mode = PresentMode::AutoNoVsync,
+                "--frame-latency" => options.present.frame_latency = positive_integer(&mut args, &arg)?,
+                "--max-fps" => {
+                    let fps = next_value(&mut args, &arg)?
+                        .parse::<f32>()
+                        .map_err(|e| format!("invalid value for --max-fps: {e}"))?;
+                    if fps <= 0.0 {
+                        return Err("--max-fps must be greater than zero".to_string());
+                    }
+                    options.max_fps = Some(fps);
+                }
+                "--idle-when-converged" => options.idle_when_converged = true,
+                "--samples-per-frame" => options.samples_per_frame = positive_integer(&mut args, &arg)?,
+                "--benchmark" => options.benchmark = Some(positive_integer(&mut args, &arg)?),
+                "--validation" => options.adapter.validation = true,
+                "--no-validation" => options.adapter.validation = false,
+                "--tile-size" => tile_size = Some(positive_integer(&mut args, &arg)?),
+                "--samples" => samples = Some(positive_integer(&mut args, &arg)?),
+                _ => return Err(format!("unknown argument `{arg}`")),
+            }
+        }
+
+        //--samples applies to the cpu still as well
+        if options.cpu && options.render.is_none() {
+            options.render = Some(TiledRender::new(CPU_RENDER_SIZE.0, CPU_RENDER_SIZE.1));
+        }
+        if let Some(render) = options.render.as_mut() {
+            render.tile_size = tile_size.unwrap_or(render.tile_size);
+            render.samples = samples.unwrap_or(render.samples);
+        } else if tile_size.is_some() || samples.is_some() {
+            return Err("--tile-size and --samples only apply to --render and --cpu".to_string());
+        }
+        Ok(options)
+    }
+}
+
+fn next_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("{flag} expects a value"))
+}
+
+fn parse_backends(value: &str) -> Result<Backends, String> {
+    value.split(',').try_fold(Backends::empty(), |backends, name| {
+        let backend = match name.trim() {
+            "vulkan" => Backends::VULKAN,
+            "metal" => Backends::METAL,
+            "dx12" => Backends::DX12,
+            "gl" => Backends::GL,
+            "all" => Backends::all(),
+            _ => return Err(format!("unknown backend `{name}` for --backend, expected vulkan, metal, dx12, gl or all")),
+        };
+        Ok(backends | backend)
+    })
+}
+
+fn positive_integer(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<u32, String> {
+    match next_value(args, flag)?.parse::<u32>() {
+        Ok(0) => Err(format!("{flag} must be greater than zero")),
+        Ok(value) => Ok(value),
+        Err(e) => Err(format!("invalid value for {flag}: {e}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn error(args: &[&str]) -> String {
+        parse(args).expect_err("parsed")
+    }
+
+    #[test]
+    fn render_size() {
+        let render = parse(&["--render", "16384x8192"]).unwrap().render.unwrap();
+        assert_eq!((render.width, render.height), (16384, 8192));
+        assert_eq!(render, TiledRender::new(16384, 8192));
+        for value in ["", "1920", "1920x", "x1080", "0x1080", "1920x0", "1920*1080", "-1x5", "1920x1080x2", "wide x tall"] {
+            assert!(error(&["--render", value]).contains("expected <width>x<height>"), "`{value}` parsed");
+        }
+        assert_eq!(error(&["--render"]), "--render expects a value");
+    }
+
+    #[test]
+    fn tile_size_and_samples_need_an_offline_render() {
+        let render = parse(&["--tile-size", "256", "--render", "800x600", "--samples", "64"]).unwrap().render.unwrap();
+        assert_eq!((render.tile_size, render.samples), (256, 64));
+        let cpu = parse(&["--cpu", "--samples", "32"]).unwrap().render.unwrap();
+        assert_eq!((cpu.width, cpu.height, cpu.samples), (CPU_RENDER_SIZE.0, CPU_RENDER_SIZE.1, 32));
+
+        let message = "--tile-size and --samples only apply to --render and --cpu";
+        assert_eq!(error(&["--tile-size", "256"]), message);
+        assert_eq!(error(&["--samples", "64"]), message);
+        assert!(parse(&[]).unwrap().render.is_none());
+    }
+
+    #[test]
+    fn backend_lists() {
+        let backends = |value: &str| parse(&["--backend", value]).map(|o| o.adapter.backends);
+        assert_eq!(backends("vulkan"), Ok(Backends::VULKAN));
+        assert_eq!(backends("vulkan,gl"), Ok(Backends::VULKAN | Backends::GL));
+        assert_eq!(backends("metal, dx12"), Ok(Backends::METAL | Backends::DX12));
+        assert_eq!(backends("all"), Ok(Backends::all()));
+        for value in ["", "opengl", "vulkan,", "vulkan;gl"] {
+            assert!(backends(value).is_err(), "`{value}` parsed");
+        }
+    }
+
+    #[test]
+    fn present_modes() {
+        let mode = |args: &[&str]| parse(args).map(|o| o.present.present_mode);
+        assert_eq!(mode(&[]), Ok(PresentMode::Fifo));
+        assert_eq!(mode(&["--present-mode", "fifo"]), Ok(PresentMode::Fifo));
+        assert_eq!(mode(&["--present-mode", "fifo-relaxed"]), Ok(PresentMode::FifoRelaxed));
+        assert_eq!(mode(&["--present-mode", "mailbox"]), Ok(PresentMode::Mailbox));
+        assert_eq!(mode(&["--present-mode", "immediate"]), Ok(PresentMode::Immediate));
+        assert_eq!(mode(&["--present-mode", "auto-vsync"]), Ok(PresentMode::AutoVsync));
+        assert_eq!(mode(&["--present-mode", "auto-no-vsync"]), Ok(PresentMode::AutoNoVsync));
+        assert_eq!(mode(&["--no-vsync"]), Ok(PresentMode::AutoNoVsync));
+        assert!(mode(&["--present-mode", "vsync"]).is_err());
+        assert!(mode(&["--present-mode", "Mailbox"]).is_err());
+    }
+
+    #[test]
+    fn positive_integers_reject_zero() {
+        for flag in ["--frame-latency", "--samples-per-frame", "--benchmark", "--tile-size", "--samples"] {
+            assert_eq!(error(&["--render", "64x64", flag, "0"]), format!("{flag} must be greater than zero"));
+            assert!(error(&["--render", "64x64", flag, "-3"]).starts_with(&format!("invalid value for {flag}")));
+            assert!(error(&["--render", "64x64", flag, "many"]).starts_with(&format!("invalid value for {flag}")));
+        }
+        assert_eq!(parse(&["--frame-latency", "1"]).unwrap().present.frame_latency, 1);
+    }
+
+    #[test]
+    fn switches_can_be_turned_off_again() {
+        assert!(!parse(&["--watch-shaders", "--no-watch-shaders"]).unwrap().watch_shaders);
+        assert!(parse(&["--no-watch-shaders", "--watch-shaders"]).unwrap().watch_shaders);
+        assert!(!parse(&["--validation", "--no-validation"]).unwrap().adapter.validation);
+        assert!(parse(&["--no-validation", "--validation"]).unwrap().adapter.validation);
+    }
+
+    #[test]
+    fn unknown_arguments() {
+        assert_eq!(error(&["--fast"]), "unknown argument `--fast`");
+        assert_eq!(error(&["scene.toml"]), "unknown argument `scene.toml`");
+    }
+}
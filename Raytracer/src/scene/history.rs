@@ -0,0 +1,260 @@
+use glam::Vec3;
+
+use crate::gpu::raytracer::compute_pipeline::{Sphere, Star};
+use crate::gpu::raytracer::materials::Material;
+use crate::scene::scene::Scene;
+
+const MAX_STEPS: usize = 200;
+
+//a single reversible change to the scene, holding both sides so it can be applied either way
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SceneEdit {
+    Transform { index: usize, from: (Vec3, f32), to: (Vec3, f32) },
+    Material { index: usize, from: Material, to: Material },
+    AddSphere { index: usize, sphere: Sphere },
+    RemoveSphere { index: usize, sphere: Sphere },
+    Light { index: usize, from: Star, to: Star },
+    AddLight { index: usize, light: Star },
+    RemoveLight { index: usize, light: Star },
+}
+
+impl SceneEdit {
+    fn inverse(&self) -> SceneEdit {
+        match *self {
+            SceneEdit::Transform { index, from, to } => SceneEdit::Transform { index, from: to, to: from },
+            SceneEdit::Material { index, from, to } => SceneEdit::Material { index, from: to, to: from },
+            SceneEdit::AddSphere { index, sphere } => SceneEdit::RemoveSphere { index, sphere },
+            SceneEdit::RemoveSphere { index, sphere } => SceneEdit::AddSphere { index, sphere },
+            SceneEdit::Light { index, from, to } => SceneEdit::Light { index, from: to, to: from },
+            SceneEdit::AddLight { index, light } => SceneEdit::RemoveLight { index, light },
+            SceneEdit::RemoveLight { index, light } => SceneEdit::AddLight { index, light },
+        }
+    }
+
+    //the scene may have been reloaded since the edit was recorded, so check the index still points somewhere
+    fn applies_to(&self, scene: &Scene) -> bool {
+        match *self {
+            SceneEdit::Transform { index, .. } | SceneEdit::Material { index, .. } | SceneEdit::RemoveSphere { index, .. } => index < scene.spheres().len(),
+            SceneEdit::AddSphere { index, .. } => index <= scene.spheres().len(),
+            SceneEdit::Light { index, .. } | SceneEdit::RemoveLight { index, .. } => index < scene.lights().len(),
+            SceneEdit::AddLight { index, .. } => index <= scene.lights().len(),
+        }
+    }
+
+    fn apply(&self, scene: &mut Scene) {
+        match *self {
+            SceneEdit::Transform { index, to, .. } => scene.set_transform(index, to.0, to.1),
+            SceneEdit::Material { index, to, .. } => scene.set_material(index, to),
+            SceneEdit::AddSphere { index, sphere } => scene.insert_sphere(index, sphere),
+            SceneEdit::RemoveSphere { index, .. } => { scene.remove_sphere(index); }
+            SceneEdit::Light { index, to, .. } => scene.set_light(index, to),
+            SceneEdit::AddLight { index, light } => scene.insert_light(index, light),
+            SceneEdit::RemoveLight { index, .. } => { scene.remove_light(index); }
+        }
+    }
+
+    //folds `next` into this edit when both change the same property of the same object
+    fn merge(&mut self, next: &SceneEdit) -> bool {
+        match (self, next) {
+            (SceneEdit::Transform { index, to, .. }, SceneEdit::Transform { index: i, to: t, .. }) if index == i => *to = *t,
+            (SceneEdit::Material { index, to, .. }, SceneEdit::Material { index: i, to: t, .. }) if index == i => *to = *t,
+            (SceneEdit::Light { index, to, .. }, SceneEdit::Light { index: i, to: t, .. }) if index == i => *to = *t,
+            _ => return false,
+        }
+        true
+    }
+}
+
+//undo and redo stacks for interactive edits
+//kept by the app rather than the scene so it carries over when the scene file is saved or reloaded
+#[derive(Default)]
+pub struct History {
+    undo: Vec<SceneEdit>,
+    redo: Vec<SceneEdit>,
+    //while a drag is in progress consecutive edits of the same property become one step
+    gesture_open: bool,
+}
+
+impl History {
+    //applies `edit` to the scene and records it
+    pub fn apply(&mut self, scene: &mut Scene, edit: SceneEdit) {
+        edit.apply(scene);
+        self.redo.clear();
+
+        if self.gesture_open {
+            if let Some(last) = self.undo.last_mut() {
+                if last.merge(&edit) {
+                    return;
+                }
+            }
+        }
+        self.undo.push(edit);
+        if self.undo.len() > MAX_STEPS {
+            self.undo.remove(0);
+        }
+        self.gesture_open = true;
+    }
+
+    //called once the mouse button is released, the next edit starts a new step
+    pub fn end_gesture(&mut self) {
+        self.gesture_open = false;
+    }
+
+    //Ok(false) when there is nothing to undo, an error when the edit no longer fits the scene and was dropped
+    pub fn undo(&mut self, scene: &mut Scene) -> Result<bool, String> {
+        self.gesture_open = false;
+        Self::step(&mut self.undo, &mut self.redo, scene)
+    }
+
+    pub fn redo(&mut self, scene: &mut Scene) -> Result<bool, String> {
+        self.gesture_open = false;
+        Self::step(&mut self.redo, &mut self.undo, scene)
+    }
+
+    //pops from one stack, applies the reverse and pushes it on the other
+    fn step(from: &mut Vec<SceneEdit>, to: &mut Vec<SceneEdit>, scene: &mut Scene) -> Result<bool, String> {
+        let Some(edit) = from.pop() else { return Ok(false) };
+        let inverse = edit.inverse();
+        if !inverse.applies_to(scene) {
+            return Err(format!("dropped an edit that no longer matches the scene: {edit:?}"));
+        }
+        inverse.apply(scene);
+        to.push(inverse);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::gpu::raytracer::materials;
+
+    fn sphere(x: f32) -> Sphere {
+        Sphere { center: [x, 0.0, 0.0], radius: 10.0, material: materials::glass_material() }
+    }
+
+    fn light(intensity: f32) -> Star {
+        Star { color: [1.0, 1.0, 1.0], intensity, position: [0.0, 100.0, 0.0], radius: 0.0 }
+    }
+
+    fn scene() -> Scene {
+        let mut scene = Scene::empty(PathBuf::from("history.scene"));
+        scene.add_sphere(sphere(0.0));
+        scene.add_sphere(sphere(50.0));
+        scene.add_light(light(1.0));
+        scene
+    }
+
+    //applies `edit`, then checks undo gets back to where it started and redo back to the edit
+    fn check_round_trip(edit: SceneEdit) {
+        let mut scene = scene();
+        let mut history = History::default();
+        let (spheres, lights) = (scene.spheres().to_vec(), scene.lights().to_vec());
+        history.apply(&mut scene, edit);
+        let (edited_spheres, edited_lights) = (scene.spheres().to_vec(), scene.lights().to_vec());
+        assert!(edited_spheres != spheres || edited_lights != lights, "{edit:?} changed nothing");
+
+        assert_eq!(history.undo(&mut scene), Ok(true));
+        assert_eq!((scene.spheres(), scene.lights()), (spheres.as_slice(), lights.as_slice()), "undoing {edit:?}");
+        assert_eq!(history.redo(&mut scene), Ok(true));
+        assert_eq!((scene.spheres(), scene.lights()), (edited_spheres.as_slice(), edited_lights.as_slice()), "redoing {edit:?}");
+        assert_eq!(history.redo(&mut scene), Ok(false));
+    }
+
+    #[test]
+    fn every_edit_undoes_and_redoes() {
+        let glass = materials::glass_material();
+        let gold = materials::polished_gold();
+        check_round_trip(SceneEdit::Transform { index: 1, from: (Vec3::new(50.0, 0.0, 0.0), 10.0), to: (Vec3::new(5.0, 6.0, 7.0), 3.0) });
+        check_round_trip(SceneEdit::Material { index: 0, from: glass, to: gold });
+        check_round_trip(SceneEdit::AddSphere { index: 1, sphere: sphere(25.0) });
+        check_round_trip(SceneEdit::AddSphere { index: 2, sphere: sphere(75.0) });
+        check_round_trip(SceneEdit::RemoveSphere { index: 0, sphere: sphere(0.0) });
+        check_round_trip(SceneEdit::Light { index: 0, from: light(1.0), to: light(0.25) });
+        check_round_trip(SceneEdit::AddLight { index: 0, light: light(0.5) });
+        check_round_trip(SceneEdit::RemoveLight { index: 0, light: light(1.0) });
+    }
+
+    #[test]
+    fn a_new_edit_clears_redo() {
+        let mut scene = scene();
+        let mut history = History::default();
+        history.apply(&mut scene, SceneEdit::AddLight { index: 1, light: light(0.5) });
+        assert_eq!(history.undo(&mut scene), Ok(true));
+        history.apply(&mut scene, SceneEdit::RemoveSphere { index: 0, sphere: sphere(0.0) });
+        assert_eq!(history.redo(&mut scene), Ok(false));
+        assert_eq!(scene.lights().len(), 1);
+    }
+
+    #[test]
+    fn edits_during_a_gesture_become_one_step() {
+        let mut scene = scene();
+        let mut history = History::default();
+        let mut set_intensity = |history: &mut History, intensity| {
+            let from = scene.lights()[0];
+            history.apply(&mut scene, SceneEdit::Light { index: 0, from, to: light(intensity) });
+        };
+        //a slider drag, then a second drag after the button came up
+        for intensity in [0.9, 0.8, 0.7] {
+            set_intensity(&mut history, intensity);
+        }
+        history.end_gesture();
+        set_intensity(&mut history, 0.5);
+        assert_eq!(history.undo.len(), 2);
+
+        assert_eq!(history.undo(&mut scene), Ok(true));
+        assert_eq!(scene.lights()[0], light(0.7));
+        assert_eq!(history.undo(&mut scene), Ok(true));
+        assert_eq!(scene.lights()[0], light(1.0));
+        assert_eq!(history.undo(&mut scene), Ok(false));
+    }
+
+    #[test]
+    fn a_gesture_only_merges_edits_of_the_same_property() {
+        let mut scene = scene();
+        let mut history = History::default();
+        history.apply(&mut scene, SceneEdit::Material { index: 0, from: materials::glass_material(), to: materials::polished_gold() });
+        history.apply(&mut scene, SceneEdit::Material { index: 1, from: materials::glass_material(), to: materials::pearlescent() });
+        history.apply(&mut scene, SceneEdit::Material { index: 1, from: materials::pearlescent(), to: materials::dark_mirror() });
+        assert_eq!(history.undo.len(), 2);
+
+        //undoing closes the gesture, so the next edit of the same property is a step of its own
+        assert_eq!(history.undo(&mut scene), Ok(true));
+        assert_eq!(scene.spheres()[1].material, materials::glass_material());
+        history.apply(&mut scene, SceneEdit::Material { index: 0, from: materials::polished_gold(), to: materials::emerald_crystal() });
+        assert_eq!(history.undo.len(), 2);
+    }
+
+    #[test]
+    fn only_the_latest_steps_are_kept() {
+        let mut scene = scene();
+        let mut history = History::default();
+        for step in 0..MAX_STEPS + 10 {
+            let from = light(step as f32);
+            history.apply(&mut scene, SceneEdit::Light { index: 0, from, to: light(step as f32 + 1.0) });
+            history.end_gesture();
+        }
+        assert_eq!(history.undo.len(), MAX_STEPS);
+        while history.undo(&mut scene) == Ok(true) {}
+        //the first ten steps fell off the bottom
+        assert_eq!(scene.lights()[0], light(10.0));
+    }
+
+    #[test]
+    fn edits_that_no_longer_fit_the_scene_are_reported() {
+        let mut scene = scene();
+        let mut history = History::default();
+        history.apply(&mut scene, SceneEdit::AddSphere { index: 2, sphere: sphere(75.0) });
+        history.end_gesture();
+        history.apply(&mut scene, SceneEdit::Material { index: 2, from: materials::glass_material(), to: materials::polished_gold() });
+        //as if the scene had been reloaded from a file without the new sphere
+        scene.remove_sphere(2);
+
+        assert!(history.undo(&mut scene).is_err());
+        assert_eq!(scene.spheres().len(), 2);
+        assert!(history.undo(&mut scene).is_err());
+        assert_eq!(history.undo(&mut scene), Ok(false));
+    }
+}
@@ -1,2 +1,6 @@
 
-pub(crate) mod scene;
\ No newline at end of file
+pub mod history;
+pub mod picking;
+pub mod scenefile;
+#[allow(clippy::module_inception)]
+pub(crate) mod scene;
@@ -0,0 +1,140 @@
+use glam::{Vec2, Vec3};
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+
+use crate::gpu::raytracer::compute_pipeline::CameraUniform;
+use crate::gpu::raytracer::materials::Material;
+use crate::scene::scene::Scene;
+
+//what lies under a point on the screen
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PickHit {
+    pub index: usize,
+    pub distance: f32,
+    pub position: Vec3,
+    pub material: Material,
+}
+
+//window pixel coordinates to the [-1, 1] range the shader uses, with +y at the top of the screen
+pub fn cursor_to_ndc(cursor: PhysicalPosition<f64>, size: PhysicalSize<u32>) -> Vec2 {
+    let width = size.width.max(1) as f32;
+    let height = size.height.max(1) as f32;
+    Vec2::new(
+        cursor.x as f32 / width * 2.0 - 1.0,
+        1.0 - cursor.y as f32 / height * 2.0,
+    )
+}
+
+//the same primary ray the compute shader builds for a pixel, without the anti-aliasing jitter
+pub fn primary_ray(camera: &CameraUniform, ndc: Vec2) -> (Vec3, Vec3) {
+    let forward = Vec3::from(camera.forward);
+    let scale = (camera.fov_y * 0.5).tan();
+    let aspect_corrected_x = ndc.x * camera.aspect_ratio * scale;
+    let aspect_corrected_y = ndc.y * scale;
+
+    let right = forward.cross(Vec3::from(camera.up)).normalize();
+    let up = right.cross(forward).normalize();
+
+    let direction = (aspect_corrected_x * right + aspect_corrected_y * up + forward).normalize();
+    (Vec3::from(camera.position), direction)
+}
+
+//matches `detect_hit` in the compute shader: the near root of the quadratic, negative for a miss
+pub fn detect_hit(origin: Vec3, direction: Vec3, center: Vec3, radius: f32) -> f32 {
+    let oc = origin - center;
+    let a = direction.dot(direction);
+    let b = 2.0 * oc.dot(direction);
+    let c = oc.dot(oc) - radius * radius;
+    let discriminant = b * b - 4.0 * a * c;
+
+    if discriminant > 0.0 {
+        return (-b - discriminant.sqrt()) / (2.0 * a);
+    }
+    -1.0
+}
+
+//casts a ray through `ndc` and returns the closest object it hits
+pub fn pick(scene: &Scene, camera: &CameraUniform, ndc: Vec2) -> Option<PickHit> {
+    let (origin, direction) = primary_ray(camera, ndc);
+
+    scene
+        .spheres()
+        .iter()
+        .enumerate()
+        .map(|(index, sphere)| (index, detect_hit(origin, direction, sphere.center.into(), sphere.radius)))
+        .filter(|(_, t)| *t > 0.0)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(index, distance)| PickHit {
+            index,
+            distance,
+            position: origin + direction * distance,
+            material: scene.spheres()[index].material,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::gpu::raytracer::compute_pipeline::Sphere;
+    use crate::gpu::raytracer::materials;
+
+    const SIZE: PhysicalSize<u32> = PhysicalSize::new(1200, 800);
+
+    //looking down +z from the origin
+    fn camera() -> CameraUniform {
+        CameraUniform::new(Vec3::ZERO, Vec3::Z, Vec3::Y, 1.05, SIZE.width as f32 / SIZE.height as f32)
+    }
+
+    fn scene(spheres: &[([f32; 3], f32)]) -> Scene {
+        let mut scene = Scene::empty(PathBuf::from("picking.scene"));
+        for &(center, radius) in spheres {
+            scene.add_sphere(Sphere { center, radius, material: materials::glass_material() });
+        }
+        scene
+    }
+
+    fn centre() -> Vec2 {
+        cursor_to_ndc(PhysicalPosition::new(SIZE.width as f64 / 2.0, SIZE.height as f64 / 2.0), SIZE)
+    }
+
+    #[test]
+    fn centre_pixel_hits_the_sphere_ahead() {
+        let hit = pick(&scene(&[([0.0, 0.0, 50.0], 10.0)]), &camera(), centre()).expect("nothing picked");
+        assert_eq!(hit.index, 0);
+        assert!((hit.distance - 40.0).abs() < 1e-3, "{}", hit.distance);
+        assert!(hit.position.distance(Vec3::new(0.0, 0.0, 40.0)) < 1e-3, "{}", hit.position);
+        assert_eq!(hit.material, materials::glass_material());
+    }
+
+    #[test]
+    fn misses_return_none() {
+        assert_eq!(pick(&scene(&[]), &camera(), centre()), None);
+        //off to the side and behind the camera
+        assert_eq!(pick(&scene(&[([40.0, 0.0, 50.0], 10.0), ([0.0, 0.0, -50.0], 10.0)]), &camera(), centre()), None);
+    }
+
+    #[test]
+    fn nearest_of_overlapping_spheres_wins() {
+        let scene = scene(&[([0.0, 0.0, 60.0], 15.0), ([0.0, 0.0, 50.0], 15.0)]);
+        let hit = pick(&scene, &camera(), centre()).expect("nothing picked");
+        assert_eq!(hit.index, 1);
+        assert!((hit.distance - 35.0).abs() < 1e-3, "{}", hit.distance);
+    }
+
+    #[test]
+    fn cursor_at_the_edge_of_the_viewport() {
+        assert_eq!(cursor_to_ndc(PhysicalPosition::new(0.0, 0.0), SIZE), Vec2::new(-1.0, 1.0));
+        let right_edge = cursor_to_ndc(PhysicalPosition::new(SIZE.width as f64, SIZE.height as f64 / 2.0), SIZE);
+        assert_eq!(right_edge, Vec2::new(1.0, 0.0));
+
+        //a sphere on the ray through the right edge is picked there, but not from the centre
+        let (origin, direction) = primary_ray(&camera(), right_edge);
+        assert!(direction.x.abs() > 0.5 && direction.z > 0.0, "{direction}");
+        let scene = scene(&[((origin + direction * 100.0).into(), 5.0)]);
+        let hit = pick(&scene, &camera(), right_edge).expect("nothing picked at the edge");
+        assert_eq!(hit.index, 0);
+        assert!((hit.distance - 95.0).abs() < 1e-2, "{}", hit.distance);
+        assert_eq!(pick(&scene, &camera(), centre()), None);
+    }
+}
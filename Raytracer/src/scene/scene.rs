@@ -1,37 +1,382 @@
-use glam::{vec3, Quat, Vec3};
+use std::f32::consts::{PI, TAU};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
-use crate::{controls::cameracontroller::CameraController, gpu::raytracer::compute_pipeline::CameraUniform};
+use glam::{vec3, Vec3};
 
+use crate::gpu::raytracer::compute_pipeline::{CameraUniform, Sphere, Star};
+use crate::gpu::raytracer::materials::{self, Material};
 
+const DEFAULT_FOV_Y: f32 = 1.05;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Camera{
     pub position: Vec3,
     pub pitch: f32,
     pub yaw: f32,
+    pub fov_y: f32,
 
 }impl Camera{
     pub fn new(position: Vec3, forward: Vec3) -> Self {
-        let yaw = forward.z.atan2(forward.x);    
-        let pitch = forward.y.clamp(-1.0, 1.0).asin();  
-    
-        (yaw, pitch);
+        let yaw = forward.z.atan2(forward.x);
+        let pitch = forward.y.clamp(-1.0, 1.0).asin();
+
         Self {
             position,
             yaw,
             pitch,
+            fov_y: DEFAULT_FOV_Y,
+
+        }
+    }
 
+    //interpolates towards `other`, turning the short way round when yaw wraps
+    pub fn lerp(&self, other: &Camera, t: f32) -> Self {
+        let yaw_delta = (other.yaw - self.yaw + PI).rem_euclid(TAU) - PI;
+        Self {
+            position: self.position.lerp(other.position, t),
+            yaw: self.yaw + yaw_delta * t,
+            pitch: self.pitch + (other.pitch - self.pitch) * t,
+            fov_y: self.fov_y + (other.fov_y - self.fov_y) * t,
         }
     }
+
+    pub fn forward(&self) -> Vec3 {
+        Vec3::new(
+            self.pitch.cos() * self.yaw.cos(),
+            self.pitch.sin(),
+            self.pitch.cos() * self.yaw.sin()
+        ).normalize()
+    }
+
+    pub fn right(&self) -> Vec3 {
+        Vec3::new(
+            -self.yaw.sin(),
+            0.0,
+            self.yaw.cos()
+        ).normalize()
+    }
+}
+
+//span of elements edited since the gpu copy was last updated
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DirtyRange {
+    range: Option<Range<usize>>,
+}
+
+impl DirtyRange {
+    fn mark(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        self.range = Some(match self.range.take() {
+            Some(dirty) => dirty.start.min(range.start)..dirty.end.max(range.end),
+            None => range,
+        });
+    }
+
+    //clamped to `len` so elements that were removed since being marked are not uploaded
+    fn take(&mut self, len: usize) -> Option<Range<usize>> {
+        self.range.take().map(|r| r.start.min(len)..r.end.min(len)).filter(|r| !r.is_empty())
+    }
+}
+
+//edits collected by `Scene::compile_objects` for the gpu to apply
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ObjectChanges {
+    pub spheres: Option<Range<usize>>,
+    pub lights: Option<Range<usize>>,
+    //set when objects were added or removed, so the shader needs the new counts
+    pub counts_changed: bool,
+}
+
+impl ObjectChanges {
+    pub fn is_empty(&self) -> bool {
+        self.spheres.is_none() && self.lights.is_none() && !self.counts_changed
+    }
 }
 
 pub struct Scene{
     pub camera: Camera,
+    spheres: Vec<Sphere>,
+    lights: Vec<Star>,
+    dirty_spheres: DirtyRange,
+    dirty_lights: DirtyRange,
+    counts_changed: bool,
+    //brightness multiplier applied when the image is displayed
+    pub exposure: f32,
+    //equirectangular image used for rays that escape the scene, relative paths are taken from the working directory
+    //scene files store it relative to their own folder, `scenefile` converts between the two
+    pub environment: PathBuf,
+    //where the scene lives on disk, sidecar files such as bookmarks are stored next to it
+    pub path: PathBuf,
 }impl Scene{
     pub fn new()->Self{
-        Self { camera: Camera::new(vec3(0.0,0.0,-80.0), vec3(0.0,3.0,9.5)) }
+        let spheres = vec![
+            Sphere {
+                center: [0.0,0.0,0.0],  
+                radius: 30.0,              
+                material: materials::glass_material(),
+            },
+            Sphere {
+                center: [100.0,-0.0,0.0],  
+                radius: 30.0,            
+                material: materials::metal_material(),
+            },
+            Sphere {
+                center: [50.0,-0.0,-60.0],  
+                radius: 10.0,            
+                material: materials::colored_glass(),
+            },
+            Sphere {
+                center: [50.0,-0.0,-30.0],  
+                radius: 10.0,            
+                material: materials::dark_mirror(),
+            },
+            Sphere {
+                center: [50.0,-0.0,0.0],  
+                radius: 10.0,           
+                material: materials::emerald_crystal(),
+            },
+            Sphere {
+                center: [50.0, -0.0, 30.0],
+                radius: 10.0,
+                material: materials::polished_gold(),
+            },
+            Sphere {
+                center: [50.0, -0.0, 60.0],
+                radius: 10.0,
+                material: materials::pearlescent(),
+            },
+        ];
+
+        let light_position = vec3(0.676,0.041,-0.735);
+        let lights = vec![
+            Star {
+                color: [1.0,0.55,0.0],
+                intensity: 1.0,
+                position: (light_position * 2000.0).into(),
+                radius: 0.0,
+            },
+        ];
+
+        Self {
+            camera: Camera::new(vec3(0.0,0.0,-80.0), vec3(0.0,3.0,9.5)),
+            dirty_spheres: DirtyRange::default(),
+            dirty_lights: DirtyRange::default(),
+            counts_changed: false,
+            spheres,
+            lights,
+            exposure: 1.0,
+            environment: PathBuf::from("src/assets/dock_texture.jpg"),
+            path: PathBuf::from("showcase.scene"),
+        }
+    }
+
+    //the built in scene, saved to `path` once the user asks for it
+    pub fn showcase(path: PathBuf) -> Self {
+        Self { path, ..Self::new() }
+    }
+
+    //an empty scene with the default camera, used as the starting point when loading from disk
+    pub fn empty(path: PathBuf) -> Self {
+        Self {
+            spheres: Vec::new(),
+            lights: Vec::new(),
+            path,
+            ..Self::new()
+        }
+    }
+
+    pub fn environment_path(&self) -> &Path {
+        &self.environment
     }
-    pub fn compile_camera(&mut self, controller: &mut CameraController, delta_time:f32, uniform: &mut CameraUniform){
-        controller.update_camera(&mut self.camera, delta_time, uniform)
+    pub fn sidecar_path(&self, extension: &str) -> PathBuf {
+        self.path.with_extension(extension)
+    }
+    //copies the camera pose into the uniform that is uploaded to the compute shader
+    pub fn compile_camera(&self, uniform: &mut CameraUniform){
+        uniform.position = self.camera.position.into();
+        uniform.forward = self.camera.forward().into();
+        uniform.fov_y = self.camera.fov_y;
+    }
+
+    pub fn spheres(&self) -> &[Sphere] {
+        &self.spheres
+    }
+
+    pub fn lights(&self) -> &[Star] {
+        &self.lights
+    }
+
+    pub fn add_sphere(&mut self, sphere: Sphere) -> usize {
+        self.insert_sphere(self.spheres.len(), sphere);
+        self.spheres.len() - 1
+    }
+
+    //`index` may be equal to the number of spheres to append
+    pub fn insert_sphere(&mut self, index: usize, sphere: Sphere) {
+        self.spheres.insert(index, sphere);
+        self.dirty_spheres.mark(index..self.spheres.len());
+        self.counts_changed = true;
+    }
+
+    //later spheres shift down by one, so everything from `index` on is reuploaded
+    pub fn remove_sphere(&mut self, index: usize) -> Sphere {
+        let sphere = self.spheres.remove(index);
+        self.dirty_spheres.mark(index..self.spheres.len());
+        self.counts_changed = true;
+        sphere
+    }
+
+    pub fn set_sphere(&mut self, index: usize, sphere: Sphere) {
+        if self.spheres[index] != sphere {
+            self.spheres[index] = sphere;
+            self.dirty_spheres.mark(index..index + 1);
+        }
+    }
+
+    pub fn set_transform(&mut self, index: usize, center: Vec3, radius: f32) {
+        let sphere = Sphere { center: center.into(), radius, ..self.spheres[index] };
+        self.set_sphere(index, sphere);
+    }
+
+    pub fn set_material(&mut self, index: usize, material: Material) {
+        let sphere = Sphere { material, ..self.spheres[index] };
+        self.set_sphere(index, sphere);
+    }
+
+    pub fn add_light(&mut self, light: Star) -> usize {
+        self.insert_light(self.lights.len(), light);
+        self.lights.len() - 1
+    }
+
+    pub fn insert_light(&mut self, index: usize, light: Star) {
+        self.lights.insert(index, light);
+        self.dirty_lights.mark(index..self.lights.len());
+        self.counts_changed = true;
+    }
+
+    pub fn remove_light(&mut self, index: usize) -> Star {
+        let light = self.lights.remove(index);
+        self.dirty_lights.mark(index..self.lights.len());
+        self.counts_changed = true;
+        light
+    }
+
+    pub fn set_light(&mut self, index: usize, light: Star) {
+        if self.lights[index] != light {
+            self.lights[index] = light;
+            self.dirty_lights.mark(index..index + 1);
+        }
+    }
+
+    //hands over everything edited since the last call and resets the tracking
+    pub fn compile_objects(&mut self) -> ObjectChanges {
+        let changes = ObjectChanges {
+            spheres: self.dirty_spheres.take(self.spheres.len()),
+            lights: self.dirty_lights.take(self.lights.len()),
+            counts_changed: self.counts_changed,
+        };
+        self.counts_changed = false;
+        changes
     }
-    pub fn compile_objects(){}
     pub fn update(&mut self){}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sphere(x: f32) -> Sphere {
+        Sphere { center: [x, 0.0, 0.0], radius: 10.0, material: materials::glass_material() }
+    }
+
+    fn light(intensity: f32) -> Star {
+        Star { color: [1.0, 1.0, 1.0], intensity, position: [0.0, 100.0, 0.0], radius: 0.0 }
+    }
+
+    //four spheres and two lights, with the initial upload already taken
+    fn scene() -> Scene {
+        let mut scene = Scene::empty(PathBuf::from("dirty.scene"));
+        for x in 0..4 {
+            scene.add_sphere(sphere(x as f32));
+        }
+        scene.add_light(light(1.0));
+        scene.add_light(light(0.5));
+        scene.compile_objects();
+        scene
+    }
+
+    #[test]
+    fn dirty_ranges_merge_into_one_span() {
+        let mut dirty = DirtyRange::default();
+        assert_eq!(dirty.take(10), None);
+
+        dirty.mark(4..5);
+        dirty.mark(1..2);
+        dirty.mark(3..3);
+        assert_eq!(dirty.take(10), Some(1..5));
+        assert_eq!(dirty.take(10), None);
+
+        dirty.mark(2..6);
+        dirty.mark(3..4);
+        assert_eq!(dirty.take(10), Some(2..6));
+    }
+
+    #[test]
+    fn dirty_ranges_are_clamped_to_what_is_left() {
+        let mut dirty = DirtyRange::default();
+        dirty.mark(2..8);
+        assert_eq!(dirty.take(5), Some(2..5));
+        dirty.mark(6..8);
+        assert_eq!(dirty.take(5), None);
+    }
+
+    #[test]
+    fn setting_objects_marks_only_what_changed() {
+        let mut scene = scene();
+        assert!(scene.compile_objects().is_empty());
+
+        scene.set_sphere(2, sphere(2.0));
+        assert!(scene.compile_objects().is_empty());
+
+        scene.set_transform(1, Vec3::new(1.0, 5.0, 0.0), 10.0);
+        scene.set_material(3, materials::polished_gold());
+        scene.set_light(1, light(0.25));
+        assert_eq!(
+            scene.compile_objects(),
+            ObjectChanges { spheres: Some(1..4), lights: Some(1..2), counts_changed: false }
+        );
+        assert!(scene.compile_objects().is_empty());
+    }
+
+    #[test]
+    fn inserting_and_removing_reuploads_everything_after() {
+        let mut scene = scene();
+        scene.insert_sphere(1, sphere(0.5));
+        assert_eq!(scene.compile_objects(), ObjectChanges { spheres: Some(1..5), lights: None, counts_changed: true });
+
+        scene.remove_sphere(2);
+        assert_eq!(scene.compile_objects(), ObjectChanges { spheres: Some(2..4), lights: None, counts_changed: true });
+
+        //removing the last object leaves nothing to upload, only the count changes
+        scene.remove_light(1);
+        assert_eq!(scene.compile_objects(), ObjectChanges { spheres: None, lights: None, counts_changed: true });
+
+        assert_eq!(scene.add_light(light(0.75)), 1);
+        assert_eq!(scene.compile_objects(), ObjectChanges { spheres: None, lights: Some(1..2), counts_changed: true });
+    }
+
+    #[test]
+    fn edits_between_uploads_are_combined() {
+        let mut scene = scene();
+        scene.set_sphere(0, sphere(-1.0));
+        scene.add_sphere(sphere(4.0));
+        scene.remove_sphere(4);
+        scene.remove_sphere(3);
+        let changes = scene.compile_objects();
+        assert_eq!(changes, ObjectChanges { spheres: Some(0..3), lights: None, counts_changed: true });
+        assert_eq!(scene.spheres(), [sphere(-1.0), sphere(1.0), sphere(2.0)]);
+    }
+}
@@ -0,0 +1,201 @@
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::gpu::raytracer::compute_pipeline::{Sphere, Star};
+use crate::scene::scene::{Camera, Scene};
+
+//on disk layout of a scene, written as toml
+//floats are stored with enough digits to read back bit for bit, so a saved scene renders identically after loading
+#[derive(Serialize, Deserialize)]
+struct SceneFile {
+    camera: CameraSection,
+    environment: EnvironmentSection,
+    #[serde(default, rename = "sphere")]
+    spheres: Vec<Sphere>,
+    #[serde(default, rename = "light")]
+    lights: Vec<Star>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CameraSection {
+    position: [f32; 3],
+    yaw: f32,
+    pitch: f32,
+    fov_y: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EnvironmentSection {
+    map: PathBuf,
+    exposure: f32,
+}
+
+//the folder a scene file's relative paths are taken from, empty for a file in the working directory
+fn folder(path: &Path) -> &Path {
+    path.parent().unwrap_or(Path::new(""))
+}
+
+//drops `.` and folds `..` into the component before it, without touching the file system
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(normalized.components().next_back(), Some(Component::Normal(_))) => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+//turns a path in the scene file, relative to the file's folder, into one relative to the working directory
+fn resolve(folder: &Path, path: &Path) -> PathBuf {
+    normalize(&folder.join(path))
+}
+
+//the inverse of `resolve`, falls back to an absolute path when no relative one leads from `folder` to `path`
+fn relative_to(path: &Path, folder: &Path) -> io::Result<PathBuf> {
+    let (path, folder) = if path.is_absolute() == folder.is_absolute() {
+        (normalize(path), normalize(folder))
+    } else {
+        (normalize(&std::path::absolute(path)?), normalize(&std::path::absolute(folder)?))
+    };
+    let shared = path.components().zip(folder.components()).take_while(|(a, b)| a == b).count();
+    let mut relative = PathBuf::new();
+    for component in folder.components().skip(shared) {
+        match component {
+            Component::Normal(_) => relative.push(".."),
+            _ => return std::path::absolute(&path),
+        }
+    }
+    relative.extend(path.components().skip(shared));
+    Ok(relative)
+}
+
+pub fn save_scene(scene: &Scene, path: &Path) -> io::Result<()> {
+    let file = SceneFile {
+        camera: CameraSection {
+            position: scene.camera.position.into(),
+            yaw: scene.camera.yaw,
+            pitch: scene.camera.pitch,
+            fov_y: scene.camera.fov_y,
+        },
+        environment: EnvironmentSection {
+            map: relative_to(&scene.environment, folder(path))?,
+            exposure: scene.exposure,
+        },
+        spheres: scene.spheres().to_vec(),
+        lights: scene.lights().to_vec(),
+    };
+    let text = toml::to_string(&file).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    std::fs::write(path, text)
+}
+
+//every object is added through the scene api, so the whole scene is marked for upload
+pub fn load_scene(path: &Path) -> io::Result<Scene> {
+    let text = std::fs::read_to_string(path)?;
+    let file: SceneFile = toml::from_str(&text)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {e}", path.display())))?;
+
+    let mut scene = Scene::empty(path.to_path_buf());
+    scene.camera = Camera {
+        position: file.camera.position.into(),
+        yaw: file.camera.yaw,
+        pitch: file.camera.pitch,
+        fov_y: file.camera.fov_y,
+    };
+    scene.environment = resolve(folder(path), &file.environment.map);
+    scene.exposure = file.environment.exposure;
+    for sphere in file.spheres {
+        scene.add_sphere(sphere);
+    }
+    for light in file.lights {
+        scene.add_light(light);
+    }
+    Ok(scene)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::raytracer::materials::{self, Material};
+
+    //each test writes into its own folder under target so they can run in parallel
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/scenefile").join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn relative_paths_follow_the_scene_file() {
+        assert_eq!(resolve(Path::new("renders"), Path::new("../src/assets/sky.jpg")), Path::new("src/assets/sky.jpg"));
+        assert_eq!(resolve(Path::new(""), Path::new("./sky.jpg")), Path::new("sky.jpg"));
+        assert_eq!(relative_to(Path::new("src/assets/sky.jpg"), Path::new("renders")).unwrap(), Path::new("../src/assets/sky.jpg"));
+        assert_eq!(relative_to(Path::new("renders/maps/sky.jpg"), Path::new("renders")).unwrap(), Path::new("maps/sky.jpg"));
+        assert_eq!(relative_to(Path::new("sky.jpg"), Path::new("")).unwrap(), Path::new("sky.jpg"));
+        for (path, folder) in [("src/assets/sky.jpg", "renders/night"), ("/maps/sky.jpg", "/scenes"), ("sky.jpg", "../scenes")] {
+            let relative = relative_to(Path::new(path), Path::new(folder)).unwrap();
+            let resolved = resolve(Path::new(folder), &relative);
+            assert_eq!(normalize(&std::path::absolute(&resolved).unwrap()), normalize(&std::path::absolute(path).unwrap()), "{path} from {folder}");
+        }
+    }
+
+    #[test]
+    fn scene_in_a_subfolder_finds_the_default_environment() {
+        let path = test_dir("subfolder").join("renders/foo.scene");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let scene = Scene::showcase(path.clone());
+        assert!(scene.environment_path().exists(), "{}", scene.environment_path().display());
+        save_scene(&scene, &path).unwrap();
+
+        let loaded = load_scene(&path).unwrap();
+        assert!(loaded.environment_path().exists(), "{}", loaded.environment_path().display());
+        assert_eq!(
+            loaded.environment_path().canonicalize().unwrap(),
+            scene.environment_path().canonicalize().unwrap()
+        );
+    }
+
+    #[test]
+    fn environment_in_the_file_is_relative_to_the_file() {
+        let path = test_dir("hand_written").join("renders/foo.scene");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "[camera]\nposition = [0.0, 0.0, 0.0]\nyaw = 0.0\npitch = 0.0\nfov_y = 1.0\n\n[environment]\nmap = \"maps/sky.jpg\"\nexposure = 1.0\n").unwrap();
+        let loaded = load_scene(&path).unwrap();
+        assert_eq!(loaded.environment_path(), path.parent().unwrap().join("maps/sky.jpg"));
+    }
+
+    #[test]
+    fn save_and_load_round_trips_every_field() {
+        let dir = test_dir("round_trip");
+        let path = dir.join("round_trip.scene");
+        let mut scene = Scene::empty(path.clone());
+        //values without a short decimal form, so any rounding on the way through toml shows up
+        scene.camera = Camera { position: glam::vec3(0.1 + 0.2, -1.0 / 3.0, 1e-7), yaw: 2.0f32.sqrt(), pitch: -0.123_456_79, fov_y: 1.05 };
+        scene.exposure = 0.7;
+        scene.environment = dir.join("maps/night.hdr");
+        scene.add_sphere(Sphere { center: [1.0 / 7.0, -30.0, 1e10], radius: 12.345_679, material: materials::glass_material() });
+        scene.add_sphere(Sphere {
+            center: [0.0, -0.0, 5.5],
+            radius: 0.001,
+            material: Material { refractive_index: 1.33, mirror_matte: 0.3, absorption: 0.05, specular: 0.25, color: [0.1, 0.2, 0.3, 0.4] },
+        });
+        scene.add_light(Star { color: [1.0, 0.55, 0.0], intensity: 0.9, position: [1352.0, 82.0, -1470.0], radius: 4.5 });
+        scene.add_light(Star { color: [0.2, 0.3, 1.0], intensity: 1.0 / 3.0, position: [-10.0, 200.5, 0.25], radius: 0.0 });
+        save_scene(&scene, &path).unwrap();
+
+        let loaded = load_scene(&path).unwrap();
+        assert_eq!(loaded.path, path);
+        assert_eq!(loaded.camera, scene.camera);
+        assert_eq!(loaded.exposure, scene.exposure);
+        assert_eq!(loaded.environment_path(), scene.environment_path());
+        assert_eq!(loaded.spheres(), scene.spheres());
+        assert_eq!(loaded.lights(), scene.lights());
+        assert!(std::fs::read_to_string(&path).unwrap().contains("map = \"maps/night.hdr\""));
+    }
+}
@@ -0,0 +1,280 @@
+use winit::dpi::PhysicalSize;
+use winit::event::{ElementState, KeyEvent, WindowEvent};
+use winit::window::Window;
+
+use glam::Vec3;
+
+use crate::gpu::raytracer::compute_pipeline::{Sphere, Star};
+use crate::gpu::raytracer::denoiser::{DenoiserSettings, MAX_ITERATIONS};
+use crate::gpu::raytracer::materials::{self, Material};
+use crate::gpu::raytracer::sampling::AdaptiveSampler;
+use crate::scene::history::{History, SceneEdit};
+use crate::scene::scene::Scene;
+
+//display settings the user touched this frame, object edits are tracked by the scene itself
+#[derive(Copy, Clone, Debug, Default)]
+pub struct OverlayChanges {
+    pub display: bool,
+}
+
+//tessellated ui waiting to be drawn over the next frame
+struct OverlayFrame {
+    primitives: Vec<egui::ClippedPrimitive>,
+    textures_delta: egui::TexturesDelta,
+    pixels_per_point: f32,
+}
+
+//immediate mode editing panels drawn on top of the raytraced image
+pub struct Overlay {
+    context: egui::Context,
+    state: egui_winit::State,
+    renderer: egui_wgpu::Renderer,
+    frame: Option<OverlayFrame>,
+    pub visible: bool,
+    //why the last shader reload failed, shown until a reload succeeds
+    pub shader_error: Option<String>,
+}
+
+impl Overlay {
+    pub fn new(window: &Window, device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let context = egui::Context::default();
+        let state = egui_winit::State::new(
+            context.clone(),
+            egui::ViewportId::ROOT,
+            window,
+            Some(window.scale_factor() as f32),
+            None,
+            Some(device.limits().max_texture_dimension_2d as usize),
+        );
+        let renderer = egui_wgpu::Renderer::new(device, format, None, 1, false);
+
+        Self {
+            context,
+            state,
+            renderer,
+            frame: None,
+            visible: true,
+            shader_error: None,
+        }
+    }
+
+    //returns true when egui wants the event for itself, e.g. a click on a panel
+    //key and button releases are never kept, a movement key or look button let go over a panel must still end its action
+    pub fn on_window_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        if !self.visible {
+            return false;
+        }
+        let consumed = self.state.on_window_event(window, event).consumed;
+        let released = matches!(
+            event,
+            WindowEvent::KeyboardInput { event: KeyEvent { state: ElementState::Released, .. }, .. }
+                | WindowEvent::MouseInput { state: ElementState::Released, .. }
+        );
+        consumed && !released
+    }
+
+    //builds this frame's ui and applies any edits to the scene through `history`
+    pub fn run(&mut self, window: &Window, scene: &mut Scene, history: &mut History, selected: &mut Option<usize>, denoiser: &mut DenoiserSettings, sampler: &mut AdaptiveSampler) -> OverlayChanges {
+        let mut changes = OverlayChanges::default();
+        if !self.visible {
+            self.frame = None;
+            return changes;
+        }
+
+        let raw_input = self.state.take_egui_input(window);
+        let output = self.context.run(raw_input, |ctx| {
+            objects_panel(ctx, scene, history, selected);
+            lights_panel(ctx, scene, history);
+            render_panel(ctx, scene, denoiser, sampler, &mut changes);
+            if let Some(error) = self.shader_error.as_deref() {
+                shader_error_panel(ctx, error);
+            }
+        });
+        self.state.handle_platform_output(window, output.platform_output);
+        //a drag on a slider ends when the button comes up, until then it is a single undo step
+        if !self.context.input(|i| i.pointer.any_down()) {
+            history.end_gesture();
+        }
+
+        self.frame = Some(OverlayFrame {
+            primitives: self.context.tessellate(output.shapes, output.pixels_per_point),
+            textures_delta: output.textures_delta,
+            pixels_per_point: output.pixels_per_point,
+        });
+        changes
+    }
+
+    //draws the ui built by the last `run` on top of whatever is already in `view`
+    pub fn paint(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, size: PhysicalSize<u32>) {
+        let Some(frame) = self.frame.take() else { return };
+
+        for (id, delta) in &frame.textures_delta.set {
+            self.renderer.update_texture(device, queue, *id, delta);
+        }
+        let screen = egui_wgpu::ScreenDescriptor {
+            size_in_pixels: [size.width, size.height],
+            pixels_per_point: frame.pixels_per_point,
+        };
+        let callback_buffers = self.renderer.update_buffers(device, queue, encoder, &frame.primitives, &screen);
+        queue.submit(callback_buffers);
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Overlay Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            }).forget_lifetime();
+            self.renderer.render(&mut render_pass, &frame.primitives, &screen);
+        }
+
+        for id in &frame.textures_delta.free {
+            self.renderer.free_texture(id);
+        }
+    }
+}
+
+fn shader_error_panel(ctx: &egui::Context, error: &str) {
+    egui::Window::new("Shader error").anchor(egui::Align2::CENTER_BOTTOM, [0.0, -10.0]).show(ctx, |ui| {
+        ui.label("Still running the last shader that built:");
+        ui.label(egui::RichText::new(error).monospace().color(egui::Color32::LIGHT_RED));
+    });
+}
+
+fn objects_panel(ctx: &egui::Context, scene: &mut Scene, history: &mut History, selected: &mut Option<usize>) {
+    egui::Window::new("Objects").default_pos([10.0, 10.0]).show(ctx, |ui| {
+        for index in 0..scene.spheres().len() {
+            if ui.selectable_label(*selected == Some(index), format!("Sphere {index}")).clicked() {
+                *selected = Some(index);
+            }
+        }
+        ui.horizontal(|ui| {
+            if ui.button("Add sphere").clicked() {
+                //drop the new sphere a little in front of the camera so it is visible straight away
+                let center = scene.camera.position + scene.camera.forward().normalize() * 50.0;
+                let sphere = Sphere { center: center.into(), radius: 10.0, material: materials::metal_material() };
+                let index = scene.spheres().len();
+                history.apply(scene, SceneEdit::AddSphere { index, sphere });
+                *selected = Some(index);
+            }
+            if let Some(index) = selected.filter(|index| *index < scene.spheres().len()) {
+                if ui.button("Remove").clicked() {
+                    history.apply(scene, SceneEdit::RemoveSphere { index, sphere: scene.spheres()[index] });
+                    *selected = None;
+                }
+            }
+        });
+
+        let Some(index) = selected.filter(|index| *index < scene.spheres().len()) else { return };
+        //edit a copy and record the difference
+        let before = scene.spheres()[index];
+        let mut sphere = before;
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("Center");
+            for axis in &mut sphere.center {
+                ui.add(egui::DragValue::new(axis).speed(0.5));
+            }
+        });
+        ui.add(egui::Slider::new(&mut sphere.radius, 0.1..=100.0).text("Radius"));
+        ui.separator();
+        material_editor(ui, &mut sphere.material);
+
+        if (sphere.center, sphere.radius) != (before.center, before.radius) {
+            let from = (Vec3::from(before.center), before.radius);
+            let to = (Vec3::from(sphere.center), sphere.radius);
+            history.apply(scene, SceneEdit::Transform { index, from, to });
+        }
+        if sphere.material != before.material {
+            history.apply(scene, SceneEdit::Material { index, from: before.material, to: sphere.material });
+        }
+    });
+}
+
+fn material_editor(ui: &mut egui::Ui, material: &mut Material) {
+    ui.add(egui::Slider::new(&mut material.refractive_index, 0.0..=3.0).text("Refractive index"));
+    ui.add(egui::Slider::new(&mut material.mirror_matte, 0.0..=1.0).text("Roughness"));
+    ui.add(egui::Slider::new(&mut material.absorption, 0.0..=1.0).text("Absorption"));
+    ui.add(egui::Slider::new(&mut material.specular, 0.0..=1.0).text("Specular"));
+    ui.horizontal(|ui| {
+        ui.label("Color");
+        ui.color_edit_button_rgba_unmultiplied(&mut material.color);
+    });
+}
+
+fn lights_panel(ctx: &egui::Context, scene: &mut Scene, history: &mut History) {
+    egui::Window::new("Lights").default_pos([10.0, 420.0]).show(ctx, |ui| {
+        let mut removed = None;
+        for index in 0..scene.lights().len() {
+            let before = scene.lights()[index];
+            let mut light = before;
+            ui.push_id(index, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Light {index}"));
+                    if ui.small_button("Remove").clicked() {
+                        removed = Some(index);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Color");
+                    ui.color_edit_button_rgb(&mut light.color);
+                });
+                ui.add(egui::Slider::new(&mut light.intensity, 0.0..=10.0).text("Intensity"));
+                ui.horizontal(|ui| {
+                    ui.label("Position");
+                    for axis in &mut light.position {
+                        ui.add(egui::DragValue::new(axis).speed(10.0));
+                    }
+                });
+            });
+            if light != before {
+                history.apply(scene, SceneEdit::Light { index, from: before, to: light });
+            }
+        }
+        if let Some(index) = removed {
+            history.apply(scene, SceneEdit::RemoveLight { index, light: scene.lights()[index] });
+        }
+        if ui.button("Add light").clicked() {
+            let position = scene.camera.position + Vec3::Y * 100.0;
+            let light = Star { color: [1.0, 1.0, 1.0], intensity: 1.0, position: position.into(), radius: 0.0 };
+            history.apply(scene, SceneEdit::AddLight { index: scene.lights().len(), light });
+        }
+    });
+}
+
+fn render_panel(ctx: &egui::Context, scene: &mut Scene, denoiser: &mut DenoiserSettings, sampler: &mut AdaptiveSampler, changes: &mut OverlayChanges) {
+    egui::Window::new("Render").default_pos([10.0, 600.0]).show(ctx, |ui| {
+        //the camera uniform is rebuilt from the scene every frame so the fov needs no flag
+        ui.add(egui::Slider::new(&mut scene.camera.fov_y, 0.1..=2.5).text("Field of view (rad)"));
+        changes.display |= ui.add(egui::Slider::new(&mut scene.exposure, 0.0..=4.0).text("Exposure")).changed();
+        ui.separator();
+        let denoising = *denoiser;
+        ui.checkbox(&mut denoiser.enabled, "Denoiser");
+        ui.add_enabled_ui(denoiser.enabled, |ui| {
+            ui.checkbox(&mut denoiser.temporal, "Temporal accumulation");
+            ui.add(egui::Slider::new(&mut denoiser.iterations, 1..=MAX_ITERATIONS).text("Filter iterations"));
+        });
+        //a converged image is neither traced nor denoised again on its own, one more frame shows the new filter
+        if *denoiser != denoising {
+            sampler.refresh();
+        }
+        ui.separator();
+        //changing any of these restarts the accumulation
+        ui.add(egui::Slider::new(&mut sampler.settings.samples_per_frame, 1..=64).text("Samples per frame"));
+        ui.checkbox(&mut sampler.settings.adaptive, "Adaptive sampling");
+        ui.add_enabled_ui(sampler.settings.adaptive, |ui| {
+            ui.add(egui::Slider::new(&mut sampler.settings.noise_threshold, 0.001..=0.1).logarithmic(true).text("Noise threshold"));
+            ui.add(egui::Slider::new(&mut sampler.settings.max_samples, 64..=65536).logarithmic(true).text("Max samples"));
+            let status = if sampler.is_converged() { "converged".to_string() } else { format!("{} pixels sampling", sampler.active_pixels()) };
+            ui.label(format!("{} samples per pixel, {status}", sampler.samples_per_pixel()));
+        });
+    });
+}